@@ -0,0 +1,261 @@
+//! dig-like client, to debug the proxy (or any other server) without external tools.
+//!
+//! Usage: `dnsdig [@server] [-p port] [-t type] [-c class] [-f batch_file] [name] [type] [class]
+//! [+tcp] [+short] [+norecurse] [+noedns]`
+//!
+//! The server defaults to the proxy at `127.0.0.1#2053`. In batch mode every non-empty line of the
+//! file holds the arguments of one lookup, on top of the ones given on the command line.
+
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use dns_starter_rust::{
+    message::{header::Truncation, rdata::RData},
+    rr, tcp, Answer, DnsError, Edns, Header, Message, Question,
+};
+
+const DEFAULT_SERVER: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 2053;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+struct Lookup {
+    server: String,
+    port: u16,
+    name: String,
+    qtype: rr::Type,
+    qclass: rr::Class,
+    tcp: bool,
+    short: bool,
+    recurse: bool,
+    edns: bool,
+    batch: Option<String>,
+}
+
+impl Default for Lookup {
+    fn default() -> Self {
+        Lookup {
+            server: DEFAULT_SERVER.into(),
+            port: DEFAULT_PORT,
+            name: ".".into(),
+            qtype: rr::Type::A,
+            qclass: rr::Class::In,
+            tcp: false,
+            short: false,
+            recurse: true,
+            edns: true,
+            batch: None,
+        }
+    }
+}
+
+impl Lookup {
+    /// Applies `args` on top of `self`, the way dig reads its command line.
+    fn parse(mut self, args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(DnsError::ArgNoValue(arg.clone()));
+            match arg.as_str() {
+                "-p" => self.port = value()?.parse()?,
+                "-t" => self.qtype = value()?.parse()?,
+                "-c" => self.qclass = value()?.parse()?,
+                "-f" => self.batch = Some(value()?),
+                "+tcp" | "+vc" => self.tcp = true,
+                "+notcp" | "+novc" => self.tcp = false,
+                "+short" => self.short = true,
+                "+noshort" => self.short = false,
+                "+recurse" => self.recurse = true,
+                "+norecurse" => self.recurse = false,
+                "+edns" => self.edns = true,
+                "+noedns" => self.edns = false,
+                _ if arg.starts_with('@') => self.server = arg[1..].into(),
+                _ if arg.starts_with(['-', '+']) => bail!(DnsError::ArgUnknown(arg)),
+                _ => {
+                    if let Ok(qtype) = arg.parse() {
+                        self.qtype = qtype;
+                    } else if let Ok(qclass) = arg.parse() {
+                        self.qclass = qclass;
+                    } else {
+                        self.name = arg;
+                    }
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    fn address(&self) -> Result<SocketAddr> {
+        if let Ok(ip) = self.server.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, self.port));
+        }
+        (self.server.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(anyhow!("Cannot resolve server `{}`", self.server))
+    }
+
+    fn query(&self) -> Result<Message> {
+        let mut query = Message::new_query().question(Question {
+            domain: self.name.parse()?,
+            qtype: self.qtype,
+            qclass: self.qclass,
+        });
+        if self.recurse {
+            query = query.recursion_desired();
+        }
+        if self.edns {
+            query = query.edns(Edns::default());
+        }
+        Ok(query.build())
+    }
+}
+
+fn main() -> Result<()> {
+    let lookup = Lookup::default().parse(std::env::args().skip(1))?;
+
+    let Some(batch) = &lookup.batch else {
+        return run(&lookup);
+    };
+    for line in fs::read_to_string(batch)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        let args = line.split_whitespace().map(String::from);
+        if let Err(e) = lookup.clone().parse(args).and_then(|lookup| run(&lookup)) {
+            eprintln!(";; {}: {}", line, e);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn run(lookup: &Lookup) -> Result<()> {
+    let address = lookup.address()?;
+    let query = lookup.query()?;
+    let packed = query.pack()?;
+
+    let started = Instant::now();
+    let (mut raw, mut tcp) = if lookup.tcp {
+        (exchange_tcp(address, &packed)?, true)
+    } else {
+        (exchange_udp(address, &packed, query.get_id())?, false)
+    };
+    let mut response = Message::unpack(&raw)?;
+    if response.header().tc == Truncation::Yes && !tcp {
+        println!(";; Truncated, retrying in TCP mode.");
+        raw = exchange_tcp(address, &packed)?;
+        response = Message::unpack(&raw)?;
+        tcp = true;
+    }
+    let elapsed = started.elapsed();
+
+    if lookup.short {
+        for answer in response.answers() {
+            println!("{}", rdata(answer));
+        }
+        return Ok(());
+    }
+
+    println!(
+        "; <<>> dnsdig {} <<>> {}",
+        env!("CARGO_PKG_VERSION"),
+        query.questions()[0].domain.to_fqdn()
+    );
+    println!(";; Got answer:");
+    println!("{}", response);
+    println!();
+    println!(";; Query time: {} msec", elapsed.as_millis());
+    println!(
+        ";; SERVER: {}#{}({}) ({})",
+        address.ip(),
+        address.port(),
+        lookup.server,
+        if tcp { "TCP" } else { "UDP" }
+    );
+    println!(";; MSG SIZE  rcvd: {}", raw.len());
+    Ok(())
+}
+
+fn exchange_udp(address: SocketAddr, query: &[u8], id: u16) -> Result<Vec<u8>> {
+    let local: SocketAddr = match address {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+        SocketAddr::V6(_) => "[::]:0".parse()?,
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(address)?;
+    socket.send(query)?;
+
+    let mut buf = vec![0u8; tcp::MAX_MESSAGE_SIZE];
+    loop {
+        let size = socket.recv(&mut buf)?;
+        // Late replies to earlier queries may still arrive, skip anything not answering ours.
+        if Header::unpack_id(&buf[..size]).ok() == Some(id) {
+            return Ok(buf[..size].to_vec());
+        }
+    }
+}
+
+fn exchange_tcp(address: SocketAddr, query: &[u8]) -> Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    tcp::send(&mut stream, query)?;
+    tcp::recv(&mut stream)
+}
+
+fn rdata(answer: &Answer) -> String {
+    match answer.rdata() {
+        Ok(rdata) => rdata.to_string(),
+        Err(_) => RData::Unknown(answer.data().to_vec()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_starter_rust::message::header::RecursionDesired;
+
+    use super::*;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn parse() {
+        let lookup = Lookup::default()
+            .parse(args(
+                "@8.8.8.8 -p 53 example.com AAAA CH +tcp +short +norecurse",
+            ))
+            .unwrap();
+        assert_eq!("8.8.8.8:53", lookup.address().unwrap().to_string());
+        assert_eq!("example.com", lookup.name);
+        assert_eq!(rr::Type::Aaaa, lookup.qtype);
+        assert_eq!(rr::Class::Ch, lookup.qclass);
+        assert!(lookup.tcp && lookup.short && !lookup.recurse);
+
+        let query = lookup.query().unwrap();
+        assert_eq!(RecursionDesired::No, query.header().rd);
+        assert_eq!(rr::Class::Ch, query.questions()[0].qclass);
+    }
+
+    #[test]
+    fn parse_batch_line() {
+        let global = Lookup::default()
+            .parse(args("+short -f queries.txt"))
+            .unwrap();
+        let line = global.clone().parse(args("google.com -t MX")).unwrap();
+        assert!(line.short);
+        assert_eq!(rr::Type::Mx, line.qtype);
+        assert_eq!(Some("queries.txt".into()), line.batch);
+    }
+
+    #[test]
+    fn parse_unknown_option() {
+        assert!(Lookup::default().parse(args("+frobnicate")).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Certificates of the encrypted listeners, reloaded from their PEM files whenever these change so
+//! that renewals need no restart.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use rustls::{
+    crypto::ring::{default_provider, sign::any_supported_type},
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+
+/// Certificate chain and private key read from PEM files.
+///
+/// The files are checked for changes on every TLS handshake. A change that does not load, e.g.
+/// a certificate written before its key, keeps the previous certificate in use and is only
+/// retried once the files change again.
+#[derive(Debug)]
+pub struct Certificate {
+    cert: PathBuf,
+    key: PathBuf,
+    loaded: RwLock<Loaded>,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    /// Modification times of the files when last read, successfully or not; `None` if they could
+    /// not be had.
+    attempted: Option<(SystemTime, SystemTime)>,
+    key: Arc<CertifiedKey>,
+}
+
+impl Certificate {
+    /// Loads the chain in `cert`, leaf first, and the key in `key`.
+    pub fn load(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Result<Self> {
+        let (cert, key) = (cert.into(), key.into());
+        let loaded = Loaded::read(&cert, &key)?;
+        Ok(Certificate {
+            cert,
+            key,
+            loaded: RwLock::new(loaded),
+        })
+    }
+
+    /// Server configuration presenting this certificate, negotiating one of `alpn`.
+    pub fn server_config(self: Arc<Self>, alpn: &[&[u8]]) -> Result<ServerConfig> {
+        let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(self);
+        config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+        Ok(config)
+    }
+
+    /// The certificate to present, reloaded first if its files changed since they were last read.
+    fn current(&self) -> Arc<CertifiedKey> {
+        let modified = modified(&self.cert, &self.key).ok();
+        let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+        if modified == loaded.attempted {
+            return loaded.key.clone();
+        }
+        drop(loaded);
+
+        let mut loaded = self.loaded.write().unwrap_or_else(|e| e.into_inner());
+        // Another handshake may have read them in the meantime.
+        if modified != loaded.attempted {
+            match Loaded::read(&self.cert, &self.key) {
+                Ok(reloaded) => *loaded = reloaded,
+                Err(e) => {
+                    eprintln!(
+                        "Cannot reload certificate, keeping the previous one: {:#}",
+                        e
+                    );
+                    loaded.attempted = modified;
+                }
+            }
+        }
+        loaded.key.clone()
+    }
+}
+
+impl ResolvesServerCert for Certificate {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current())
+    }
+}
+
+impl Loaded {
+    fn read(cert: &Path, key: &Path) -> Result<Self> {
+        // Taken first, so that files changing while being read are read again next time.
+        let modified = modified(cert, key)?;
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("Cannot read certificate {}", cert.display()))?;
+        let signing_key = PrivateKeyDer::from_pem_file(key)
+            .map_err(anyhow::Error::from)
+            .and_then(|key| Ok(any_supported_type(&key)?))
+            .with_context(|| format!("Cannot read private key {}", key.display()))?;
+        let certified = CertifiedKey::new(chain, signing_key);
+        certified
+            .keys_match()
+            .with_context(|| format!("{} is not the key of {}", key.display(), cert.display()))?;
+        Ok(Loaded {
+            attempted: Some(modified),
+            key: Arc::new(certified),
+        })
+    }
+}
+
+fn modified(cert: &Path, key: &Path) -> std::io::Result<(SystemTime, SystemTime)> {
+    Ok((
+        fs::metadata(cert)?.modified()?,
+        fs::metadata(key)?.modified()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::resolver::tls::testing::pki;
+
+    use super::*;
+
+    #[test]
+    fn retries_failed_reloads_once_files_change() {
+        let (old, new) = (pki("certs_old"), pki("certs_new"));
+        let path =
+            |kind| std::env::temp_dir().join(format!("certs-{}.{}", std::process::id(), kind));
+        let (cert, key) = (path("crt"), path("key"));
+        old.write(&cert, &key);
+        let certificate = Certificate::load(&cert, &key).unwrap();
+        let initial = certificate.current();
+
+        fs::write(&cert, "not a certificate").unwrap();
+        assert!(Arc::ptr_eq(&initial, &certificate.current()));
+        // The broken files are remembered, later handshakes do not read them again.
+        let attempted = certificate.loaded.read().unwrap().attempted;
+        assert_eq!(modified(&cert, &key).ok(), attempted);
+        assert!(Arc::ptr_eq(&initial, &certificate.current()));
+
+        new.write(&cert, &key);
+        assert!(!Arc::ptr_eq(&initial, &certificate.current()));
+    }
+}
@@ -0,0 +1,385 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    dot,
+    errors::DnsError,
+    handler::OpcodePolicy,
+    message::resolver::{https::DohMethod, tls::TlsOptions, MultiQuestion},
+};
+
+/// Command line configuration of the proxy.
+///
+/// Usage: `run_server -r|--resolver <address> [--dnstap-file <path>] [--dnstap-socket <path>]
+/// [--metrics <address>] [--notify notimp|handle|proxy] [--update notimp|handle|proxy]
+/// [--passthrough] [--multi-question split|forward|reject] [--tls-server-name <name>]
+/// [--tls-pin spki-sha256:<base64>]... [--tls-ca <path>] [--doh-method get|post]
+/// [--dot-listen <address> --cert <path> --key <path> [--idle-timeout <seconds>]]
+/// [--doh-listen <address> [--doh-profile <path>=<resolver>]...]
+/// [--doq-listen <address> --cert <path> --key <path>] [--zero-rtt] [--zone <origin>=<path>]...`
+///
+/// The DNS over HTTPS listener terminates TLS when given `--cert` and `--key`, and serves plain
+/// HTTP otherwise. It also serves the JSON API at `/resolve?name=<name>&type=<type>`.
+///
+/// A resolver address of the form `tls://host[:port]` forwards over DNS over TLS, one of the form
+/// `https://host[:port]/path` over DNS over HTTPS and one of the form `quic://host[:port]` over
+/// DNS over QUIC.
+///
+/// Queries about names of a `--zone` are answered authoritatively from its master file, rather
+/// than forwarded.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub resolver: String,
+    pub dnstap: Option<DnstapTarget>,
+    /// Address of the HTTP listener serving Prometheus metrics at `/metrics`.
+    pub metrics: Option<String>,
+    /// What to do with NOTIFY messages, `NOTIMP` by default.
+    pub notify: OpcodePolicy,
+    /// What to do with UPDATE messages, `NOTIMP` by default.
+    pub update: OpcodePolicy,
+    /// Relay standard queries as raw bytes rather than resolving them.
+    pub passthrough: bool,
+    /// What to do with messages holding several questions, split by default.
+    pub multi_question: MultiQuestion,
+    /// How a `tls://`, `https://` or `quic://` resolver is authenticated.
+    pub tls: TlsOptions,
+    /// How queries are sent to an `https://` resolver, `POST` by default.
+    pub doh_method: DohMethod,
+    /// Address of the DNS over TLS listener.
+    pub dot: Option<String>,
+    /// Address of the DNS over HTTPS listener.
+    pub doh: Option<String>,
+    /// Paths of the DNS over HTTPS listener forwarding to resolvers of their own, e.g.
+    /// `/family=https://family.cloudflare-dns.com/dns-query`.
+    pub doh_profiles: Vec<(String, String)>,
+    /// Address of the DNS over QUIC listener.
+    pub doq: Option<String>,
+    /// Send 0-RTT data to `quic://` resolvers and accept it on the DNS over QUIC listener, saving
+    /// a round trip on reconnection at the cost of replayable queries.
+    pub zero_rtt: bool,
+    /// PEM files of the certificate chain and private key of the encrypted listeners.
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    /// How long encrypted connections may stay without queries.
+    pub idle_timeout: Duration,
+    /// Zones answered authoritatively, by origin and master file, e.g.
+    /// `example.com=/etc/zones/db.example.com`.
+    pub zones: Vec<(String, PathBuf)>,
+}
+
+/// Where dnstap frames are written to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DnstapTarget {
+    /// Plain file, unidirectional Frame Streams.
+    File(PathBuf),
+    /// Unix domain socket, bidirectional Frame Streams.
+    Socket(PathBuf),
+}
+
+impl Config {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut resolver = None;
+        let mut dnstap = None;
+        let mut metrics = None;
+        let mut notify = OpcodePolicy::default();
+        let mut update = OpcodePolicy::default();
+        let mut passthrough = false;
+        let mut multi_question = MultiQuestion::default();
+        let mut tls = TlsOptions::default();
+        let mut doh_method = DohMethod::default();
+        let mut dot = None;
+        let mut doh = None;
+        let mut doh_profiles = Vec::new();
+        let mut doq = None;
+        let mut zero_rtt = false;
+        let mut cert = None;
+        let mut key = None;
+        let mut idle_timeout = dot::DEFAULT_IDLE_TIMEOUT;
+        let mut zones = Vec::new();
+
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or(DnsError::ArgNoValue(flag.clone()));
+            match flag.as_str() {
+                "-r" | "--resolver" => resolver = Some(value()?),
+                "--dnstap-file" => dnstap = Some(DnstapTarget::File(value()?.into())),
+                "--dnstap-socket" => dnstap = Some(DnstapTarget::Socket(value()?.into())),
+                "--metrics" => metrics = Some(value()?),
+                "--notify" => notify = policy(&flag, value()?)?,
+                "--update" => update = policy(&flag, value()?)?,
+                "--passthrough" => passthrough = true,
+                "--multi-question" => multi_question = questions(&flag, value()?)?,
+                "--tls-server-name" => tls.server_name = Some(value()?),
+                "--tls-pin" => {
+                    let value = value()?;
+                    match value.parse() {
+                        Ok(pin) => tls.pins.push(pin),
+                        Err(_) => bail!(DnsError::ArgInvalid { flag, value }),
+                    }
+                }
+                "--tls-ca" => tls.ca = Some(value()?.into()),
+                "--doh-method" => doh_method = method(&flag, value()?)?,
+                "--dot-listen" => dot = Some(value()?),
+                "--doh-listen" => doh = Some(value()?),
+                "--doh-profile" => {
+                    let value = value()?;
+                    match value.split_once('=') {
+                        Some((path, resolver)) if path.starts_with('/') => {
+                            doh_profiles.push((path.into(), resolver.into()))
+                        }
+                        _ => bail!(DnsError::ArgInvalid { flag, value }),
+                    }
+                }
+                "--doq-listen" => doq = Some(value()?),
+                "--zero-rtt" => zero_rtt = true,
+                "--cert" => cert = Some(value()?.into()),
+                "--key" => key = Some(value()?.into()),
+                "--idle-timeout" => {
+                    let value = value()?;
+                    match value.parse() {
+                        Ok(seconds) => idle_timeout = Duration::from_secs(seconds),
+                        Err(_) => bail!(DnsError::ArgInvalid { flag, value }),
+                    }
+                }
+                "--zone" => {
+                    let value = value()?;
+                    match value.split_once('=') {
+                        Some((origin, path)) if !origin.is_empty() && !path.is_empty() => {
+                            zones.push((origin.into(), path.into()))
+                        }
+                        _ => bail!(DnsError::ArgInvalid { flag, value }),
+                    }
+                }
+                _ => bail!(DnsError::ArgUnknown(flag)),
+            }
+        }
+
+        for (flag, listener) in [("--dot-listen", &dot), ("--doq-listen", &doq)] {
+            if listener.is_none() {
+                continue;
+            }
+            for (requires, value) in [("--cert", &cert), ("--key", &key)] {
+                if value.is_none() {
+                    bail!(DnsError::ArgRequires {
+                        flag: flag.into(),
+                        requires: requires.into()
+                    });
+                }
+            }
+        }
+
+        match resolver {
+            Some(resolver) => Ok(Config {
+                resolver,
+                dnstap,
+                metrics,
+                notify,
+                update,
+                passthrough,
+                multi_question,
+                tls,
+                doh_method,
+                dot,
+                doh,
+                doh_profiles,
+                doq,
+                zero_rtt,
+                cert,
+                key,
+                idle_timeout,
+                zones,
+            }),
+            None => bail!(DnsError::ResolverNotSpecified),
+        }
+    }
+}
+
+fn policy(flag: &str, value: String) -> Result<OpcodePolicy> {
+    match value.as_str() {
+        "notimp" => Ok(OpcodePolicy::Reject),
+        "handle" => Ok(OpcodePolicy::Handle),
+        "proxy" => Ok(OpcodePolicy::Proxy),
+        _ => bail!(DnsError::ArgInvalid {
+            flag: flag.into(),
+            value
+        }),
+    }
+}
+
+fn questions(flag: &str, value: String) -> Result<MultiQuestion> {
+    match value.as_str() {
+        "split" => Ok(MultiQuestion::Split),
+        "forward" => Ok(MultiQuestion::Forward),
+        "reject" => Ok(MultiQuestion::Reject),
+        _ => bail!(DnsError::ArgInvalid {
+            flag: flag.into(),
+            value
+        }),
+    }
+}
+
+fn method(flag: &str, value: String) -> Result<DohMethod> {
+    match value.as_str() {
+        "get" => Ok(DohMethod::Get),
+        "post" => Ok(DohMethod::Post),
+        _ => bail!(DnsError::ArgInvalid {
+            flag: flag.into(),
+            value
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::resolver::tls::Pin;
+
+    use super::*;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn from_args() {
+        let config =
+            Config::from_args(args("--resolver 8.8.8.8:53 --dnstap-file /tmp/tap")).unwrap();
+        assert_eq!("8.8.8.8:53", config.resolver);
+        assert!(!config.passthrough);
+        assert_eq!(Some(DnstapTarget::File("/tmp/tap".into())), config.dnstap);
+    }
+
+    #[test]
+    fn from_args_opcode_policies() {
+        let config = Config::from_args(args(
+            "-r 8.8.8.8:53 --notify handle --update proxy --passthrough --multi-question reject",
+        ))
+        .unwrap();
+        assert!(config.passthrough);
+        assert_eq!(MultiQuestion::Reject, config.multi_question);
+        assert_eq!(OpcodePolicy::Handle, config.notify);
+        assert_eq!(OpcodePolicy::Proxy, config.update);
+
+        let err = Config::from_args(args("-r 8.8.8.8:53 --notify forward")).unwrap_err();
+        assert_eq!(
+            "Invalid value `forward` for command line flag `--notify`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn from_args_tls() {
+        let pin = "spki-sha256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        let config = Config::from_args(args(&format!(
+            "-r tls://dns.quad9.net --tls-server-name dns.quad9.net --tls-pin {} --tls-ca /tmp/ca.pem",
+            pin
+        )))
+        .unwrap();
+        assert_eq!(Some("dns.quad9.net".into()), config.tls.server_name);
+        assert_eq!(vec![pin.parse::<Pin>().unwrap()], config.tls.pins);
+        assert_eq!(Some("/tmp/ca.pem".into()), config.tls.ca);
+        assert_eq!(DohMethod::Post, config.doh_method);
+
+        let config = Config::from_args(args("-r https://dns.google/dns-query --doh-method get"));
+        assert_eq!(DohMethod::Get, config.unwrap().doh_method);
+
+        let err =
+            Config::from_args(args("-r tls://dns.quad9.net --tls-pin sha256:AA==")).unwrap_err();
+        assert_eq!(
+            "Invalid value `sha256:AA==` for command line flag `--tls-pin`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn from_args_dot_listener() {
+        let config = Config::from_args(args(
+            "-r 8.8.8.8:53 --dot-listen 0.0.0.0:853 --cert /tmp/dns.crt --key /tmp/dns.key --idle-timeout 10",
+        ))
+        .unwrap();
+        assert_eq!(Some("0.0.0.0:853".into()), config.dot);
+        assert_eq!(Some("/tmp/dns.crt".into()), config.cert);
+        assert_eq!(Some("/tmp/dns.key".into()), config.key);
+        assert_eq!(Duration::from_secs(10), config.idle_timeout);
+
+        let err = Config::from_args(args(
+            "-r 8.8.8.8:53 --dot-listen 0.0.0.0:853 --cert /tmp/dns.crt",
+        ))
+        .unwrap_err();
+        assert_eq!(
+            "Command line flag `--dot-listen` requires `--key`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn from_args_doh_listener() {
+        let config = Config::from_args(args(
+            "-r 8.8.8.8:53 --doh-listen 0.0.0.0:443 --doh-profile /family=1.1.1.3:53",
+        ))
+        .unwrap();
+        assert_eq!(Some("0.0.0.0:443".into()), config.doh);
+        assert_eq!(
+            vec![("/family".to_string(), "1.1.1.3:53".to_string())],
+            config.doh_profiles
+        );
+        assert_eq!(None, config.cert);
+
+        let err = Config::from_args(args("-r 8.8.8.8:53 --doh-profile family")).unwrap_err();
+        assert_eq!(
+            "Invalid value `family` for command line flag `--doh-profile`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn from_args_zones() {
+        let config = Config::from_args(args(
+            "-r 8.8.8.8:53 --zone example.com=/etc/zones/db.example --zone 2.0.192.in-addr.arpa.=db.rev",
+        ))
+        .unwrap();
+        assert_eq!(
+            vec![
+                ("example.com".to_string(), "/etc/zones/db.example".into()),
+                ("2.0.192.in-addr.arpa.".to_string(), "db.rev".into()),
+            ],
+            config.zones
+        );
+
+        let err = Config::from_args(args("-r 8.8.8.8:53 --zone example.com")).unwrap_err();
+        assert_eq!(
+            "Invalid value `example.com` for command line flag `--zone`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn from_args_no_resolver() {
+        let err = Config::from_args(args("--dnstap-socket /tmp/tap.sock")).unwrap_err();
+        assert_eq!(DnsError::ResolverNotSpecified.to_string(), err.to_string());
+    }
+
+    #[test]
+    fn from_args_no_value() {
+        let err = Config::from_args(args("-r")).unwrap_err();
+        assert_eq!(
+            DnsError::ArgNoValue("-r".into()).to_string(),
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn from_args_doq_listener() {
+        let config = Config::from_args(args(
+            "-r quic://dns.adguard-dns.com --doq-listen 0.0.0.0:853 --cert /tmp/dns.crt --key /tmp/dns.key --zero-rtt",
+        ))
+        .unwrap();
+        assert_eq!(Some("0.0.0.0:853".into()), config.doq);
+        assert!(config.zero_rtt);
+
+        let err = Config::from_args(args("-r 8.8.8.8:53 --doq-listen 0.0.0.0:853")).unwrap_err();
+        assert_eq!(
+            "Command line flag `--doq-listen` requires `--cert`",
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    net::{IpAddr, SocketAddr},
+    os::unix::net::UnixStream,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+use self::{frame::Writer, proto::Encoder};
+
+mod frame;
+mod proto;
+
+const IDENTITY: &[u8] = b"dns-proxy";
+const VERSION: &[u8] = env!("CARGO_PKG_VERSION").as_bytes();
+
+/// `Dnstap.Type.MESSAGE`, the only kind of payload dnstap defines.
+const DNSTAP_MESSAGE: u64 = 1;
+
+/// Field numbers of the `Dnstap` protobuf message.
+mod dnstap_field {
+    pub const IDENTITY: u32 = 1;
+    pub const VERSION: u32 = 2;
+    pub const MESSAGE: u32 = 14;
+    pub const TYPE: u32 = 15;
+}
+
+/// Field numbers of the `Message` protobuf message.
+mod message_field {
+    pub const TYPE: u32 = 1;
+    pub const SOCKET_FAMILY: u32 = 2;
+    pub const SOCKET_PROTOCOL: u32 = 3;
+    pub const QUERY_ADDRESS: u32 = 4;
+    pub const RESPONSE_ADDRESS: u32 = 5;
+    pub const QUERY_PORT: u32 = 6;
+    pub const RESPONSE_PORT: u32 = 7;
+    pub const QUERY_TIME_SEC: u32 = 8;
+    pub const QUERY_TIME_NSEC: u32 = 9;
+    pub const QUERY_MESSAGE: u32 = 10;
+    pub const RESPONSE_TIME_SEC: u32 = 12;
+    pub const RESPONSE_TIME_NSEC: u32 = 13;
+    pub const RESPONSE_MESSAGE: u32 = 14;
+}
+
+/// Which side of the proxy a logged message was seen on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    ClientQuery = 5,
+    ClientResponse = 6,
+    ForwarderQuery = 7,
+    ForwarderResponse = 8,
+}
+
+impl Kind {
+    fn is_query(self) -> bool {
+        matches!(self, Kind::ClientQuery | Kind::ForwarderQuery)
+    }
+}
+
+/// Transport the logged message travelled over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Udp = 1,
+    /// DNS over TLS.
+    Dot = 3,
+    /// DNS over HTTPS.
+    Doh = 4,
+    /// DNS over QUIC.
+    Doq = 7,
+}
+
+/// dnstap sink: serializes raw DNS wire messages and writes them as Frame Streams data frames.
+///
+/// Logging is best effort, a failing sink never fails the query it is logging.
+pub struct Dnstap(Mutex<Writer>);
+
+impl Dnstap {
+    /// Appends frames to a newly created file.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Dnstap(Mutex::new(Writer::unidirectional(file)?)))
+    }
+
+    /// Streams frames to a listening dnstap collector, e.g. `fstrm_capture` or `dnstap -u`.
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        Ok(Dnstap(Mutex::new(Writer::bidirectional(stream)?)))
+    }
+
+    /// Logs `wire` as seen travelling from `query_addr` to `response_addr` (or back, for
+    /// responses).
+    pub fn log(
+        &self,
+        kind: Kind,
+        protocol: Protocol,
+        query_addr: SocketAddr,
+        response_addr: SocketAddr,
+        wire: &[u8],
+    ) {
+        let payload = encode(
+            kind,
+            protocol,
+            query_addr,
+            response_addr,
+            wire,
+            SystemTime::now(),
+        );
+        let written = match self.0.lock() {
+            Ok(mut writer) => writer.write(&payload),
+            Err(poisoned) => poisoned.into_inner().write(&payload),
+        };
+        if let Err(e) = written {
+            eprintln!("Cannot write dnstap frame: {}", e);
+        }
+    }
+}
+
+fn encode(
+    kind: Kind,
+    protocol: Protocol,
+    query_addr: SocketAddr,
+    response_addr: SocketAddr,
+    wire: &[u8],
+    time: SystemTime,
+) -> Vec<u8> {
+    let family = match query_addr.ip() {
+        IpAddr::V4(_) => 1,
+        IpAddr::V6(_) => 2,
+    };
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (time_sec, time_nsec, message) = if kind.is_query() {
+        use message_field::*;
+        (QUERY_TIME_SEC, QUERY_TIME_NSEC, QUERY_MESSAGE)
+    } else {
+        use message_field::*;
+        (RESPONSE_TIME_SEC, RESPONSE_TIME_NSEC, RESPONSE_MESSAGE)
+    };
+
+    let message = Encoder::default()
+        .varint(message_field::TYPE, kind as u64)
+        .varint(message_field::SOCKET_FAMILY, family)
+        .varint(message_field::SOCKET_PROTOCOL, protocol as u64)
+        .bytes(message_field::QUERY_ADDRESS, &ip_octets(query_addr.ip()))
+        .bytes(
+            message_field::RESPONSE_ADDRESS,
+            &ip_octets(response_addr.ip()),
+        )
+        .varint(message_field::QUERY_PORT, query_addr.port() as u64)
+        .varint(message_field::RESPONSE_PORT, response_addr.port() as u64)
+        .varint(time_sec, time.as_secs())
+        .fixed32(time_nsec, time.subsec_nanos())
+        .bytes(message, wire)
+        .finish();
+
+    Encoder::default()
+        .bytes(dnstap_field::IDENTITY, IDENTITY)
+        .bytes(dnstap_field::VERSION, VERSION)
+        .bytes(dnstap_field::MESSAGE, &message)
+        .varint(dnstap_field::TYPE, DNSTAP_MESSAGE)
+        .finish()
+}
+
+fn ip_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use byteorder::{BigEndian, ReadBytesExt};
+
+    use super::{frame::*, proto::*, *};
+
+    /// Splits a unidirectional Frame Streams file into its data frames.
+    fn read_frames(mut reader: &[u8]) -> Vec<Vec<u8>> {
+        let (kind, fields) = read_control(&mut reader).unwrap();
+        assert_eq!(CONTROL_START, kind);
+        assert_eq!(CONTENT_TYPE, &fields[8..]);
+
+        let mut frames = Vec::new();
+        loop {
+            let len = reader.read_u32::<BigEndian>().unwrap() as usize;
+            if len as u32 == ESCAPE {
+                let kind = reader.read_u32::<BigEndian>().unwrap();
+                assert_eq!(4, kind);
+                assert_eq!(CONTROL_STOP, reader.read_u32::<BigEndian>().unwrap());
+                assert!(reader.is_empty());
+                return frames;
+            }
+            let mut frame = vec![0u8; len];
+            reader.read_exact(&mut frame).unwrap();
+            frames.push(frame);
+        }
+    }
+
+    fn field(fields: &[(u32, Value)], number: u32) -> &Value {
+        &fields.iter().find(|(n, _)| *n == number).unwrap().1
+    }
+
+    #[test]
+    fn log_and_decode() {
+        let client: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let proxy: SocketAddr = "127.0.0.1:2053".parse().unwrap();
+        let upstream: SocketAddr = "[::1]:53".parse().unwrap();
+        let query = b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00";
+
+        let path = std::env::temp_dir().join(format!("dnstap-{}.fstrm", std::process::id()));
+        {
+            let dnstap = Dnstap::create(&path).unwrap();
+            dnstap.log(Kind::ClientQuery, Protocol::Udp, client, proxy, query);
+            dnstap.log(Kind::ForwarderQuery, Protocol::Udp, proxy, upstream, query);
+            dnstap.log(
+                Kind::ForwarderResponse,
+                Protocol::Udp,
+                proxy,
+                upstream,
+                query,
+            );
+            dnstap.log(Kind::ClientResponse, Protocol::Udp, client, proxy, query);
+        }
+        let raw = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let frames = read_frames(&raw);
+        assert_eq!(4, frames.len());
+
+        let kinds = [
+            Kind::ClientQuery,
+            Kind::ForwarderQuery,
+            Kind::ForwarderResponse,
+            Kind::ClientResponse,
+        ];
+        for (frame, kind) in frames.iter().zip(kinds) {
+            let dnstap = decode(frame);
+            assert_eq!(
+                &Value::Bytes(IDENTITY.to_vec()),
+                field(&dnstap, dnstap_field::IDENTITY)
+            );
+            assert_eq!(
+                &Value::Varint(DNSTAP_MESSAGE),
+                field(&dnstap, dnstap_field::TYPE)
+            );
+            let Value::Bytes(message) = field(&dnstap, dnstap_field::MESSAGE) else {
+                panic!("dnstap message is not embedded");
+            };
+            let message = decode(message);
+            assert_eq!(
+                &Value::Varint(kind as u64),
+                field(&message, message_field::TYPE)
+            );
+            let wire = if kind.is_query() {
+                message_field::QUERY_MESSAGE
+            } else {
+                message_field::RESPONSE_MESSAGE
+            };
+            assert_eq!(&Value::Bytes(query.to_vec()), field(&message, wire));
+        }
+
+        let forwarder = decode(&frames[1]);
+        let Value::Bytes(message) = field(&forwarder, dnstap_field::MESSAGE) else {
+            panic!("dnstap message is not embedded");
+        };
+        let message = decode(message);
+        assert_eq!(
+            &Value::Bytes(vec![127, 0, 0, 1]),
+            field(&message, message_field::QUERY_ADDRESS)
+        );
+        assert_eq!(
+            &Value::Bytes(ip_octets(upstream.ip())),
+            field(&message, message_field::RESPONSE_ADDRESS)
+        );
+        assert_eq!(
+            &Value::Varint(53),
+            field(&message, message_field::RESPONSE_PORT)
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+};
+
+use anyhow::{ensure, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::errors::DnsError;
+
+/// Content type negotiated with the receiving end of the stream.
+pub const CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+/// A zero length prefix marks a control frame, anything else is the length of a data frame.
+pub const ESCAPE: u32 = 0x00;
+pub const CONTROL_ACCEPT: u32 = 0x01;
+pub const CONTROL_START: u32 = 0x02;
+pub const CONTROL_STOP: u32 = 0x03;
+pub const CONTROL_READY: u32 = 0x04;
+pub const CONTROL_FINISH: u32 = 0x05;
+pub const FIELD_CONTENT_TYPE: u32 = 0x01;
+
+/// Frame Streams writer.
+///
+/// Unidirectional streams (files) only carry `START`, data frames and `STOP`. Bidirectional
+/// streams (sockets) additionally perform the `READY`/`ACCEPT` handshake before `START` and wait
+/// for `FINISH` after `STOP`.
+pub struct Writer {
+    stream: Box<dyn Write + Send>,
+    reader: Option<UnixStream>,
+}
+
+impl Writer {
+    pub fn unidirectional(stream: impl Write + Send + 'static) -> Result<Self> {
+        let mut writer = Writer {
+            stream: Box::new(stream),
+            reader: None,
+        };
+        writer.control(CONTROL_START, Some(CONTENT_TYPE))?;
+        Ok(writer)
+    }
+
+    pub fn bidirectional(stream: UnixStream) -> Result<Self> {
+        let mut writer = Writer {
+            stream: Box::new(stream.try_clone()?),
+            reader: Some(stream),
+        };
+        writer.control(CONTROL_READY, Some(CONTENT_TYPE))?;
+        writer.expect(CONTROL_ACCEPT)?;
+        writer.control(CONTROL_START, Some(CONTENT_TYPE))?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, payload: &[u8]) -> Result<()> {
+        self.stream.write_u32::<BigEndian>(payload.len() as u32)?;
+        self.stream.write_all(payload)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn control(&mut self, kind: u32, content_type: Option<&[u8]>) -> Result<()> {
+        let mut frame = Vec::new();
+        frame.write_u32::<BigEndian>(kind)?;
+        if let Some(content_type) = content_type {
+            frame.write_u32::<BigEndian>(FIELD_CONTENT_TYPE)?;
+            frame.write_u32::<BigEndian>(content_type.len() as u32)?;
+            frame.extend_from_slice(content_type);
+        }
+        self.stream.write_u32::<BigEndian>(ESCAPE)?;
+        self.stream.write_u32::<BigEndian>(frame.len() as u32)?;
+        self.stream.write_all(&frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn expect(&mut self, kind: u32) -> Result<()> {
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(());
+        };
+        let (act, _) = read_control(reader)?;
+        ensure!(act == kind, DnsError::DnstapHandshake { exp: kind, act });
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let stopped = self
+            .control(CONTROL_STOP, None)
+            .and_then(|_| self.expect(CONTROL_FINISH));
+        if let Err(e) = stopped {
+            eprintln!("Cannot stop dnstap stream: {}", e);
+        }
+    }
+}
+
+/// Reads a control frame, returning its type and its raw fields.
+pub fn read_control(reader: &mut impl Read) -> Result<(u32, Vec<u8>)> {
+    let escape = reader.read_u32::<BigEndian>()?;
+    ensure!(
+        escape == ESCAPE,
+        DnsError::DnstapHandshake {
+            exp: ESCAPE,
+            act: escape
+        }
+    );
+    let len = reader.read_u32::<BigEndian>()? as usize;
+    let kind = reader.read_u32::<BigEndian>()?;
+    let mut fields = vec![0u8; len.saturating_sub(4)];
+    reader.read_exact(&mut fields)?;
+    Ok((kind, fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a control frame without fields, as the receiving end of a bidirectional stream does.
+    fn write_control(writer: &mut impl Write, kind: u32) -> Result<()> {
+        writer.write_u32::<BigEndian>(ESCAPE)?;
+        writer.write_u32::<BigEndian>(4)?;
+        writer.write_u32::<BigEndian>(kind)?;
+        Ok(())
+    }
+
+    #[test]
+    fn unidirectional() {
+        let path = std::env::temp_dir().join(format!("dnstap-frame-{}", std::process::id()));
+        {
+            let mut writer = Writer::unidirectional(std::fs::File::create(&path).unwrap()).unwrap();
+            writer.write(b"payload").unwrap();
+        }
+        let raw = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut reader = &raw[..];
+        let (kind, fields) = read_control(&mut reader).unwrap();
+        assert_eq!(CONTROL_START, kind);
+        assert_eq!(CONTENT_TYPE, &fields[8..]);
+        assert_eq!(7, reader.read_u32::<BigEndian>().unwrap());
+        assert_eq!(b"payload", &reader[..7]);
+        reader = &reader[7..];
+        assert_eq!(CONTROL_STOP, read_control(&mut reader).unwrap().0);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn bidirectional() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let receiver = std::thread::spawn(move || {
+            assert_eq!(CONTROL_READY, read_control(&mut server).unwrap().0);
+            write_control(&mut server, CONTROL_ACCEPT).unwrap();
+            assert_eq!(CONTROL_START, read_control(&mut server).unwrap().0);
+            assert_eq!(2, server.read_u32::<BigEndian>().unwrap());
+            server.read_u16::<BigEndian>().unwrap();
+            assert_eq!(CONTROL_STOP, read_control(&mut server).unwrap().0);
+            write_control(&mut server, CONTROL_FINISH).unwrap();
+        });
+
+        let mut writer = Writer::bidirectional(client).unwrap();
+        writer.write(b"ok").unwrap();
+        drop(writer);
+        receiver.join().unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Minimal protobuf encoding, just enough to serialize the dnstap schema.
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_LEN: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+#[derive(Debug, Default)]
+pub struct Encoder(Vec<u8>);
+
+impl Encoder {
+    pub fn varint(&mut self, field: u32, value: u64) -> &mut Self {
+        self.key(field, WIRE_VARINT);
+        self.raw_varint(value);
+        self
+    }
+
+    pub fn fixed32(&mut self, field: u32, value: u32) -> &mut Self {
+        self.key(field, WIRE_FIXED32);
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Self {
+        self.key(field, WIRE_LEN);
+        self.raw_varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.raw_varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+}
+
+/// Decoded field value, used to verify produced streams.
+#[cfg(test)]
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Varint(u64),
+    Fixed32(u32),
+    Bytes(Vec<u8>),
+}
+
+#[cfg(test)]
+pub fn decode(mut buf: &[u8]) -> Vec<(u32, Value)> {
+    fn varint(buf: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = buf[0];
+            *buf = &buf[1..];
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        value
+    }
+
+    let mut fields = Vec::new();
+    while !buf.is_empty() {
+        let key = varint(&mut buf);
+        let value = match key as u8 & 0x07 {
+            WIRE_VARINT => Value::Varint(varint(&mut buf)),
+            WIRE_FIXED32 => {
+                let (value, rest) = buf.split_at(4);
+                buf = rest;
+                Value::Fixed32(u32::from_le_bytes(value.try_into().unwrap()))
+            }
+            WIRE_LEN => {
+                let len = varint(&mut buf) as usize;
+                let (value, rest) = buf.split_at(len);
+                buf = rest;
+                Value::Bytes(value.to_vec())
+            }
+            other => panic!("Unexpected wire type {}", other),
+        };
+        fields.push(((key >> 3) as u32, value));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode() {
+        let buf = Encoder::default()
+            .varint(1, 300)
+            .fixed32(2, 7)
+            .bytes(3, b"ab")
+            .finish();
+        assert_eq!(
+            vec![0x08, 0xAC, 0x02, 0x15, 0x07, 0x00, 0x00, 0x00, 0x1A, 0x02, b'a', b'b'],
+            buf
+        );
+        assert_eq!(
+            vec![
+                (1, Value::Varint(300)),
+                (2, Value::Fixed32(7)),
+                (3, Value::Bytes(b"ab".to_vec())),
+            ],
+            decode(&buf)
+        );
+    }
+}
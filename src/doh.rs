@@ -0,0 +1,440 @@
+//! DNS over HTTPS server (RFC 8484), over HTTP/1.1 and HTTP/2, either terminating TLS itself or
+//! behind a reverse proxy that does, along with a JSON API at [`json::PATH`].
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    thread,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{
+    engine::{
+        general_purpose::{GeneralPurpose, GeneralPurposeConfig},
+        DecodePaddingMode,
+    },
+    Engine,
+};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{
+    body::{Bytes, Incoming},
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    service::service_fn,
+    Method, Request, Response, StatusCode, Uri,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    certs::Certificate,
+    dnstap::Protocol,
+    handler::Handler,
+    message::{header::ResponseCode, resolver::https::DNS_MESSAGE, Message},
+    tcp,
+};
+
+pub mod json;
+
+/// Path of the default profile.
+pub const DEFAULT_PATH: &str = "/dns-query";
+
+/// base64url, the encoding of the `dns` parameter, which clients should not pad but may.
+const BASE64URL: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Answers DNS queries sent as HTTP requests, `GET` with the query in the `dns` parameter or
+/// `POST` with the query as body.
+///
+/// Every path is a client profile, answered by a [`Handler`] of its own: e.g. `/dns-query` may
+/// forward to the main upstream and `/family` to a filtering one. Requests to the JSON API are
+/// answered by the default profile.
+pub struct DohServer {
+    profiles: HashMap<String, Arc<Handler>>,
+    acceptor: Option<TlsAcceptor>,
+}
+
+impl DohServer {
+    /// Answers requests to [`DEFAULT_PATH`] with `handler`, the one the UDP listener uses.
+    pub fn new(handler: Arc<Handler>) -> Self {
+        DohServer {
+            profiles: HashMap::from([(DEFAULT_PATH.into(), handler)]),
+            acceptor: None,
+        }
+    }
+
+    /// Answers requests to `path` with `handler`.
+    pub fn with_profile(mut self, path: impl Into<String>, handler: Arc<Handler>) -> Self {
+        self.profiles.insert(path.into(), handler);
+        self
+    }
+
+    /// Terminates TLS with `certificate`, reloaded as its files change. Without it, requests are
+    /// served over plain HTTP.
+    pub fn with_tls(mut self, certificate: Arc<Certificate>) -> Result<Self> {
+        let config = certificate.server_config(&[b"h2", b"http/1.1"])?;
+        self.acceptor = Some(TlsAcceptor::from(Arc::new(config)));
+        Ok(self)
+    }
+
+    /// Binds `address` and serves connections from a background thread, returning the bound
+    /// address.
+    pub fn serve(self, address: impl ToSocketAddrs) -> Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let bound = listener.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let listener = {
+            let _runtime = runtime.enter();
+            TcpListener::from_std(listener)?
+        };
+        let this = Arc::new(self);
+        thread::spawn(move || runtime.block_on(this.accept(listener)));
+        Ok(bound)
+    }
+
+    async fn accept(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, client)) => {
+                    let this = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = this.connection(stream, client).await {
+                            eprintln!("DNS over HTTPS connection from {} failed: {:#}", client, e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Cannot accept DNS over HTTPS connection: {}", e),
+            }
+        }
+    }
+
+    async fn connection(self: Arc<Self>, tcp: TcpStream, client: SocketAddr) -> Result<()> {
+        let local = tcp.local_addr()?;
+        let acceptor = self.acceptor.clone();
+        let service = service_fn(move |request| {
+            let this = self.clone();
+            async move { Ok::<_, Infallible>(this.respond(request, client, local).await) }
+        });
+        let http = auto::Builder::new(TokioExecutor::new());
+        match acceptor {
+            Some(acceptor) => {
+                let stream = acceptor.accept(tcp).await?;
+                http.serve_connection(TokioIo::new(stream), service).await
+            }
+            None => http.serve_connection(TokioIo::new(tcp), service).await,
+        }
+        .map_err(|e| anyhow!(e))
+    }
+
+    async fn respond(
+        &self,
+        request: Request<Incoming>,
+        client: SocketAddr,
+        local: SocketAddr,
+    ) -> Response<Full<Bytes>> {
+        let json = request.uri().path() == json::PATH;
+        let answered = if json {
+            self.resolve(request, client, local).await
+        } else {
+            self.answer(request, client, local).await
+        };
+        let response = answered.and_then(|response| {
+            let cache_control = cache_control(&response);
+            if !json {
+                return Ok((DNS_MESSAGE, cache_control, response));
+            }
+            match json::encode(&response) {
+                Ok(body) => Ok((json::MEDIA_TYPE, cache_control, body)),
+                Err(e) => {
+                    eprintln!("Cannot encode DNS response to {} as JSON: {:#}", client, e);
+                    Err(StatusCode::BAD_GATEWAY)
+                }
+            }
+        });
+        match response {
+            Ok((content_type, cache_control, body)) => Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .header(CACHE_CONTROL, cache_control)
+                .body(Full::new(Bytes::from(body))),
+            Err(status) => Response::builder().status(status).body(Full::default()),
+        }
+        .unwrap_or_default()
+    }
+
+    /// The raw response to the query a JSON API request describes, or the HTTP status of the
+    /// failure.
+    async fn resolve(
+        &self,
+        request: Request<Incoming>,
+        client: SocketAddr,
+        local: SocketAddr,
+    ) -> Result<Vec<u8>, StatusCode> {
+        if request.method() != Method::GET {
+            return Err(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        let handler = self.profiles[DEFAULT_PATH].clone();
+        let query = json::query(request.uri())
+            .and_then(|query| query.pack().ok())
+            .ok_or(StatusCode::BAD_REQUEST)?;
+        handle(handler, query, client, local).await
+    }
+
+    /// The raw response to the query of `request`, or the HTTP status of the failure.
+    async fn answer(
+        &self,
+        request: Request<Incoming>,
+        client: SocketAddr,
+        local: SocketAddr,
+    ) -> Result<Vec<u8>, StatusCode> {
+        let handler = self
+            .profiles
+            .get(request.uri().path())
+            .ok_or(StatusCode::NOT_FOUND)?
+            .clone();
+        let query = match *request.method() {
+            Method::GET => dns_parameter(request.uri()).ok_or(StatusCode::BAD_REQUEST)?,
+            Method::POST => {
+                let content_type = request.headers().get(CONTENT_TYPE);
+                if content_type.is_none_or(|value| value != DNS_MESSAGE) {
+                    return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+                }
+                let body = Limited::new(request.into_body(), tcp::MAX_MESSAGE_SIZE);
+                match body.collect().await {
+                    Ok(body) => body.to_bytes().to_vec(),
+                    Err(e) if e.is::<LengthLimitError>() => Err(StatusCode::PAYLOAD_TOO_LARGE)?,
+                    Err(_) => Err(StatusCode::BAD_REQUEST)?,
+                }
+            }
+            _ => return Err(StatusCode::METHOD_NOT_ALLOWED),
+        };
+        handle(handler, query, client, local).await
+    }
+}
+
+/// Answers `query` with `handler`, off the async runtime.
+async fn handle(
+    handler: Arc<Handler>,
+    query: Vec<u8>,
+    client: SocketAddr,
+    local: SocketAddr,
+) -> Result<Vec<u8>, StatusCode> {
+    let handled = tokio::task::spawn_blocking(move || {
+        handler.handle_over(Protocol::Doh, &query, client, local)
+    })
+    .await;
+    match handled {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(e)) => {
+            eprintln!(
+                "Cannot answer DNS over HTTPS query from {}: {:#}",
+                client, e
+            );
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Err(e) => {
+            eprintln!("Cannot answer DNS over HTTPS query from {}: {}", client, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// The query in the `dns` parameter of a `GET` request.
+fn dns_parameter(uri: &Uri) -> Option<Vec<u8>> {
+    let dns = uri
+        .query()?
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("dns="))?;
+    BASE64URL.decode(dns).ok()
+}
+
+/// RFC 8484 section 5.1: a response is fresh no longer than the smallest TTL of its records.
+/// Failures are not cached at all.
+fn cache_control(response: &[u8]) -> String {
+    match Message::unpack(response) {
+        Ok(msg) if msg.header().rcode != ResponseCode::ServerFailure => {
+            format!("max-age={}", msg.min_ttl().unwrap_or(0))
+        }
+        _ => "no-store".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+    use crate::{
+        message::resolver::{
+            https::DohMethod,
+            tls::testing::{handler, pki, query},
+        },
+        Resolver,
+    };
+
+    use super::*;
+
+    /// TTLs of the records upstreams answer with.
+    const TTLS: &[u32] = &[300, 60];
+
+    /// Sends `request` over HTTP/1.1 and returns the response head, lowercased, and body.
+    fn http(address: SocketAddr, request: &str, body: &[u8]) -> (String, Vec<u8>) {
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..end]).to_lowercase();
+        (head, response[end + 4..].to_vec())
+    }
+
+    fn get(path: &str, query: &Message) -> String {
+        let dns = URL_SAFE_NO_PAD.encode(query.pack().unwrap());
+        format!(
+            "GET {}?dns={} HTTP/1.1\r\nHost: dns.test\r\nConnection: close\r\n\r\n",
+            path, dns
+        )
+    }
+
+    #[test]
+    fn answers_over_http2_and_tls() {
+        let pki = pki("doh_server");
+        let (cert, key) = (
+            std::env::temp_dir().join(format!("doh-{}.crt", std::process::id())),
+            std::env::temp_dir().join(format!("doh-{}.key", std::process::id())),
+        );
+        pki.write(&cert, &key);
+        let certificate = Arc::new(Certificate::load(cert, key).unwrap());
+        let address = DohServer::new(handler([1, 2, 3, 4], TTLS))
+            .with_tls(certificate)
+            .unwrap()
+            .serve("127.0.0.1:0")
+            .unwrap();
+        let url = format!("https://127.0.0.1:{}/dns-query", address.port());
+
+        for method in [DohMethod::Get, DohMethod::Post] {
+            let resolver = Resolver::connect_https(&url, &pki.trusted(), method).unwrap();
+            let query = query(&["google.com"]);
+            let response = resolver.resolve(query.clone()).unwrap();
+            assert_eq!(query.get_id(), response.get_id());
+            assert_eq!(&[1, 2, 3, 4], response.answers()[0].data());
+        }
+    }
+
+    #[test]
+    fn answers_over_http1_with_cache_control() {
+        let address = DohServer::new(handler([1, 2, 3, 4], TTLS))
+            .serve("127.0.0.1:0")
+            .unwrap();
+        let query = query(&["google.com"]);
+
+        let (head, body) = http(address, &get(DEFAULT_PATH, &query), b"");
+        assert!(head.starts_with("http/1.1 200 ok"));
+        assert!(head.contains("content-type: application/dns-message"));
+        assert!(head.contains("cache-control: max-age=60"));
+        let response = Message::unpack(&body).unwrap();
+        assert_eq!(query.get_id(), response.get_id());
+        assert_eq!(2, response.answers().len());
+
+        let post = format!(
+            "POST {} HTTP/1.1\r\nHost: dns.test\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            DEFAULT_PATH,
+            DNS_MESSAGE,
+            query.pack().unwrap().len()
+        );
+        let (head, body) = http(address, &post, &query.pack().unwrap());
+        assert!(head.starts_with("http/1.1 200 ok"));
+        assert_eq!(2, Message::unpack(&body).unwrap().answers().len());
+    }
+
+    #[test]
+    fn answers_per_profile() {
+        let address = DohServer::new(handler([1, 2, 3, 4], TTLS))
+            .with_profile("/family", handler([9, 9, 9, 9], TTLS))
+            .serve("127.0.0.1:0")
+            .unwrap();
+        let query = query(&["google.com"]);
+
+        let (_, body) = http(address, &get("/family", &query), b"");
+        let response = Message::unpack(&body).unwrap();
+        assert_eq!(&[9, 9, 9, 9], response.answers()[0].data());
+        let (_, body) = http(address, &get(DEFAULT_PATH, &query), b"");
+        let response = Message::unpack(&body).unwrap();
+        assert_eq!(&[1, 2, 3, 4], response.answers()[0].data());
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let address = DohServer::new(handler([1, 2, 3, 4], TTLS))
+            .serve("127.0.0.1:0")
+            .unwrap();
+        let query = query(&["google.com"]);
+        let close = "Host: dns.test\r\nConnection: close\r\n";
+
+        let (head, _) = http(address, &get("/other", &query), b"");
+        assert!(head.starts_with("http/1.1 404"));
+        let request = format!("GET /dns-query?dns=%%% HTTP/1.1\r\n{}\r\n", close);
+        assert!(http(address, &request, b"").0.starts_with("http/1.1 400"));
+        let request = format!(
+            "PUT /dns-query HTTP/1.1\r\n{}Content-Length: 0\r\n\r\n",
+            close
+        );
+        assert!(http(address, &request, b"").0.starts_with("http/1.1 405"));
+        let request = format!(
+            "POST /dns-query HTTP/1.1\r\n{}Content-Type: text/plain\r\nContent-Length: 0\r\n\r\n",
+            close
+        );
+        assert!(http(address, &request, b"").0.starts_with("http/1.1 415"));
+    }
+
+    #[test]
+    fn failures_are_not_cached() {
+        let failure = Message::new_server_err().pack().unwrap();
+        assert_eq!("no-store", cache_control(&failure));
+        let empty = Message::response(&query(&["google.com"])).build();
+        assert_eq!("max-age=0", cache_control(&empty.pack().unwrap()));
+    }
+
+    #[test]
+    fn answers_json_api() {
+        let address = DohServer::new(handler([1, 2, 3, 4], TTLS))
+            .serve("127.0.0.1:0")
+            .unwrap();
+        let get = |query: &str| {
+            let request = format!(
+                "GET {}?{} HTTP/1.1\r\nHost: dns.test\r\nConnection: close\r\n\r\n",
+                json::PATH,
+                query
+            );
+            http(address, &request, b"")
+        };
+
+        let (head, body) = get("name=google.com&type=A");
+        assert!(head.starts_with("http/1.1 200 ok"));
+        assert!(head.contains("content-type: application/dns-json"));
+        assert!(head.contains("cache-control: max-age=60"));
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(0, response["Status"]);
+        assert_eq!(true, response["RD"]);
+        assert_eq!("google.com.", response["Question"][0]["name"]);
+        assert_eq!(1, response["Answer"][0]["type"]);
+        assert_eq!(300, response["Answer"][0]["TTL"]);
+        assert_eq!("1.2.3.4", response["Answer"][0]["data"]);
+
+        assert!(get("type=A").0.starts_with("http/1.1 400"));
+        assert!(get("name=google.com&type=BOGUS")
+            .0
+            .starts_with("http/1.1 400"));
+    }
+}
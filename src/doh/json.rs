@@ -0,0 +1,241 @@
+//! JSON API of public resolvers such as Google and Cloudflare: `GET /resolve?name=<name>&type=<type>`
+//! answered with the response as a JSON object, for clients that cannot handle wire format.
+
+use hyper::Uri;
+use serde::Serialize;
+
+use crate::message::{
+    answer::Answer,
+    header::{AuthenticData, CheckingDisabled, RecursionAvailable, RecursionDesired, Truncation},
+    labels::Labels,
+    presentation,
+    rdata::RData,
+    rr, Message,
+};
+
+/// Path of the JSON API.
+pub const PATH: &str = "/resolve";
+
+/// Media type of JSON API responses.
+pub const MEDIA_TYPE: &str = "application/dns-json";
+
+/// Response as served by the JSON API.
+///
+/// ```json
+/// {"Status": 0, "TC": false, "RD": true, "RA": true, "AD": false, "CD": false,
+///  "Question": [{"name": "example.com.", "type": 1}],
+///  "Answer": [{"name": "example.com.", "type": 1, "TTL": 300, "data": "93.184.215.14"}]}
+/// ```
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Response {
+    pub status: u16,
+    #[serde(rename = "TC")]
+    pub tc: bool,
+    #[serde(rename = "RD")]
+    pub rd: bool,
+    #[serde(rename = "RA")]
+    pub ra: bool,
+    #[serde(rename = "AD")]
+    pub ad: bool,
+    #[serde(rename = "CD")]
+    pub cd: bool,
+    pub question: Vec<Question>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub answer: Vec<Record>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authority: Vec<Record>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Question {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub qtype: u16,
+}
+
+/// Resource record, its data in presentation format.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Record {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub rtype: u16,
+    #[serde(rename = "TTL")]
+    pub ttl: u32,
+    pub data: String,
+}
+
+impl From<&Message> for Response {
+    fn from(msg: &Message) -> Self {
+        let header = msg.header();
+        let records = |records: &[Answer]| {
+            records
+                .iter()
+                .map(|record| Record {
+                    name: record.name().to_fqdn(),
+                    rtype: record.atype().into(),
+                    ttl: record.ttl(),
+                    data: match record.rdata() {
+                        Ok(rdata) => rdata.to_string(),
+                        Err(_) => RData::Unknown(record.data().to_vec()).to_string(),
+                    },
+                })
+                .collect()
+        };
+        Response {
+            status: header.rcode.into(),
+            tc: header.tc == Truncation::Yes,
+            rd: header.rd == RecursionDesired::Yes,
+            ra: header.ra == RecursionAvailable::Yes,
+            ad: header.ad == AuthenticData::Yes,
+            cd: header.cd == CheckingDisabled::Yes,
+            question: msg
+                .questions()
+                .iter()
+                .map(|question| Question {
+                    name: question.domain.to_fqdn(),
+                    qtype: question.qtype.into(),
+                })
+                .collect(),
+            answer: records(msg.answers()),
+            authority: records(msg.authorities()),
+        }
+    }
+}
+
+/// Query of a request: `name`, `type` as mnemonic or number, `A` by default, and `cd` set to `1`
+/// or `true` to disable DNSSEC validation. Values may be percent-encoded.
+pub(super) fn query(uri: &Uri) -> Option<Message> {
+    let (mut name, mut qtype, mut cd) = (None, rr::Type::A, false);
+    for (key, value) in uri.query()?.split('&').filter_map(|p| p.split_once('=')) {
+        let value = percent_decode(value)?;
+        let value = value.as_str();
+        match key {
+            "name" => name = Some(value.parse::<Labels>().ok()?),
+            "type" => {
+                qtype = match value.parse::<u16>() {
+                    Ok(number) => number.try_into().ok()?,
+                    Err(_) => value.parse().ok()?,
+                }
+            }
+            "cd" => cd = matches!(value, "1" | "true"),
+            _ => {}
+        }
+    }
+    let query = Message::query(name?, qtype).recursion_desired();
+    Some(if cd { query.checking_disabled() } else { query }.build())
+}
+
+/// Decodes the `%XX` escapes of a query value, `None` unless they are valid and make up UTF-8.
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = std::str::from_utf8(rest.get(..2)?).ok()?;
+        bytes.extend(presentation::unhex(hex).ok()?);
+        rest = &rest[2..];
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Body of the response to a request, from the raw response of the handler.
+pub(super) fn encode(response: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let msg = Message::unpack(response)?;
+    Ok(serde_json::to_vec(&Response::from(&msg))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::message::header::ResponseCode;
+
+    use super::*;
+
+    #[test]
+    fn response_fields() {
+        let query = Message::query("example.com".parse().unwrap(), rr::Type::Mx)
+            .recursion_desired()
+            .build();
+        let mx = Answer::new(
+            "example.com".parse().unwrap(),
+            rr::Type::Mx,
+            rr::Class::In,
+            300,
+            b"\x00\x0A\x04mail\x07example\x03com\x00".to_vec(),
+        );
+        let response = Message::response(&query)
+            .recursion_available()
+            .authentic_data()
+            .answer(mx)
+            .build();
+
+        assert_eq!(
+            json!({
+                "Status": 0,
+                "TC": false,
+                "RD": true,
+                "RA": true,
+                "AD": true,
+                "CD": false,
+                "Question": [{"name": "example.com.", "type": 15}],
+                "Answer": [
+                    {"name": "example.com.", "type": 15, "TTL": 300, "data": "10 mail.example.com."}
+                ],
+            }),
+            serde_json::to_value(Response::from(&response)).unwrap()
+        );
+
+        let nxdomain = Message::response(&query)
+            .rcode(ResponseCode::NameError)
+            .build();
+        let value = serde_json::to_value(Response::from(&nxdomain)).unwrap();
+        assert_eq!(3, value["Status"]);
+        assert!(value.get("Answer").is_none());
+    }
+
+    #[test]
+    fn query_parameters() {
+        let uri = |query: &str| format!("{}?{}", PATH, query).parse::<Uri>().unwrap();
+
+        let msg = query(&uri("name=example.com&type=AAAA&cd=1")).unwrap();
+        assert_eq!("example.com", msg.questions()[0].domain.to_string());
+        assert_eq!(rr::Type::Aaaa, msg.questions()[0].qtype);
+        assert_eq!(CheckingDisabled::Yes, msg.header().cd);
+        assert_eq!(RecursionDesired::Yes, msg.header().rd);
+
+        let msg = query(&uri("name=example.com&type=15")).unwrap();
+        assert_eq!(rr::Type::Mx, msg.questions()[0].qtype);
+        assert_eq!(
+            rr::Type::A,
+            query(&uri("name=example.com")).unwrap().questions()[0].qtype
+        );
+
+        assert!(query(&uri("type=A")).is_none());
+        assert!(query(&uri("name=example.com&type=BOGUS")).is_none());
+        assert!(query(&uri("name=example..com")).is_none());
+    }
+
+    #[test]
+    fn percent_encoded_parameters() {
+        let uri = |query: &str| format!("{}?{}", PATH, query).parse::<Uri>().unwrap();
+
+        let msg = query(&uri("name=xn--b%C3%BCcher.example&type=%41AAA")).unwrap();
+        assert_eq!(
+            "xn--b\\195\\188cher.example",
+            msg.questions()[0].domain.to_string()
+        );
+        assert_eq!(rr::Type::Aaaa, msg.questions()[0].qtype);
+        let msg = query(&uri("name=example.com%2E")).unwrap();
+        assert_eq!("example.com", msg.questions()[0].domain.to_string());
+
+        assert!(query(&uri("name=example.com%2")).is_none());
+        assert!(query(&uri("name=example.com%ZZ")).is_none());
+        assert!(query(&uri("name=example%FF.com")).is_none());
+    }
+}
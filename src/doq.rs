@@ -0,0 +1,301 @@
+//! DNS over QUIC listener (RFC 9250): every query comes on a bidirectional stream of its own,
+//! answered on the same stream, so that queries of a connection never wait for one another.
+
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use quinn::{
+    crypto::rustls::QuicServerConfig, Connection, ConnectionError, Endpoint, Incoming, RecvStream,
+    SendStream, ServerConfig, TransportConfig, VarInt,
+};
+
+use crate::{
+    certs::Certificate, dnstap::Protocol, dot::DEFAULT_IDLE_TIMEOUT, errors::DnsError,
+    handler::Handler, message::header::Header, message::resolver::quic::ALPN, tcp,
+};
+
+/// Port of DNS over QUIC servers.
+pub const DEFAULT_PORT: u16 = 853;
+
+/// DOQ_PROTOCOL_ERROR, closing connections that break RFC 9250.
+const PROTOCOL_ERROR: VarInt = VarInt::from_u32(0x2);
+
+/// Accepts DNS over QUIC connections and answers their queries with a [`Handler`], the one the
+/// UDP listener uses.
+pub struct DoqListener {
+    handler: Arc<Handler>,
+    certificate: Arc<Certificate>,
+    zero_rtt: bool,
+    idle_timeout: Duration,
+}
+
+impl DoqListener {
+    /// Presents `certificate`, reloaded as its files change.
+    pub fn new(handler: Arc<Handler>, certificate: Arc<Certificate>) -> Self {
+        DoqListener {
+            handler,
+            certificate,
+            zero_rtt: false,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+
+    /// Accepts queries sent as 0-RTT data, saving returning clients a round trip. Such queries
+    /// may be replayed by an attacker (RFC 9250 section 4.5).
+    pub fn with_zero_rtt(mut self) -> Self {
+        self.zero_rtt = true;
+        self
+    }
+
+    /// Closes connections once no packet came for `timeout`.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Binds `address` and serves connections from a background thread, returning the bound
+    /// address.
+    pub fn serve(self, address: impl ToSocketAddrs) -> Result<SocketAddr> {
+        let mut tls = self.certificate.clone().server_config(&[ALPN])?;
+        if self.zero_rtt {
+            tls.max_early_data_size = u32::MAX;
+        }
+        let mut transport = TransportConfig::default();
+        transport.max_idle_timeout(Some(self.idle_timeout.try_into()?));
+        let mut config = ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls)?));
+        config.transport_config(Arc::new(transport));
+
+        let socket = std::net::UdpSocket::bind(address)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let endpoint = {
+            let _runtime = runtime.enter();
+            Endpoint::new(
+                Default::default(),
+                Some(config),
+                socket,
+                Arc::new(quinn::TokioRuntime),
+            )?
+        };
+        let bound = endpoint.local_addr()?;
+        let this = Arc::new(self);
+        thread::spawn(move || runtime.block_on(this.accept(endpoint, bound)));
+        Ok(bound)
+    }
+
+    async fn accept(self: Arc<Self>, endpoint: Endpoint, local: SocketAddr) {
+        while let Some(incoming) = endpoint.accept().await {
+            let this = self.clone();
+            tokio::spawn(async move {
+                let client = incoming.remote_address();
+                if let Err(e) = this.connection(incoming, local).await {
+                    eprintln!("DNS over QUIC connection from {} failed: {:#}", client, e);
+                }
+            });
+        }
+    }
+
+    /// Answers the queries of a connection, each on its own task, until it is closed.
+    async fn connection(self: Arc<Self>, incoming: Incoming, local: SocketAddr) -> Result<()> {
+        let connecting = incoming.accept()?;
+        let connection = if self.zero_rtt {
+            match connecting.into_0rtt() {
+                Ok((connection, _)) => connection,
+                Err(connecting) => connecting.await?,
+            }
+        } else {
+            connecting.await?
+        };
+        let client = connection.remote_address();
+        loop {
+            let (send, recv) = match connection.accept_bi().await {
+                Ok(stream) => stream,
+                Err(
+                    ConnectionError::ApplicationClosed(_)
+                    | ConnectionError::LocallyClosed
+                    | ConnectionError::TimedOut,
+                ) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            let (this, connection) = (self.clone(), connection.clone());
+            tokio::spawn(async move {
+                if let Err(e) = this.stream(&connection, send, recv, client, local).await {
+                    eprintln!("Cannot answer DNS over QUIC query from {}: {:#}", client, e);
+                }
+            });
+        }
+    }
+
+    async fn stream(
+        &self,
+        connection: &Connection,
+        mut send: SendStream,
+        mut recv: RecvStream,
+        client: SocketAddr,
+        local: SocketAddr,
+    ) -> Result<()> {
+        let framed = recv.read_to_end(tcp::MAX_MESSAGE_SIZE + 2).await?;
+        let query = tcp::recv(&mut &framed[..])?;
+        let id = Header::unpack_id(&query)?;
+        if id != 0 {
+            connection.close(PROTOCOL_ERROR, b"message ID must be 0");
+            bail!(DnsError::NonZeroId(id));
+        }
+
+        let handler = self.handler.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            handler.handle_over(Protocol::Doq, &query, client, local)
+        })
+        .await??;
+        let mut framed = Vec::with_capacity(response.len() + 2);
+        tcp::send(&mut framed, &response)?;
+        send.write_all(&framed).await?;
+        send.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use quinn::{crypto::rustls::QuicClientConfig, ClientConfig};
+
+    use crate::{
+        message::{
+            resolver::tls::{
+                client_config,
+                testing::{handler, pki, query, Pki},
+            },
+            Message,
+        },
+        Resolver,
+    };
+
+    use super::*;
+
+    fn listener(pki: &Pki, test: &str) -> DoqListener {
+        let path = |kind| -> PathBuf {
+            std::env::temp_dir().join(format!("doq-{}-{}.{}", std::process::id(), test, kind))
+        };
+        let (cert, key) = (path("crt"), path("key"));
+        pki.write(&cert, &key);
+        DoqListener::new(
+            handler([1, 2, 3, 4], &[300]),
+            Arc::new(Certificate::load(cert, key).unwrap()),
+        )
+    }
+
+    /// Client endpoint trusting `pki`, sending 0-RTT data whenever it can.
+    fn endpoint(pki: &Pki) -> Endpoint {
+        let mut tls = client_config(&pki.trusted()).unwrap();
+        tls.alpn_protocols = vec![ALPN.to_vec()];
+        tls.enable_early_data = true;
+        let crypto = QuicClientConfig::try_from(Arc::new(tls)).unwrap();
+        let mut endpoint = Endpoint::client(([127, 0, 0, 1], 0).into()).unwrap();
+        endpoint.set_default_client_config(ClientConfig::new(Arc::new(crypto)));
+        endpoint
+    }
+
+    /// Asks for `google.com` with `id` on a stream of `connection`.
+    async fn ask(connection: &Connection, id: u16) -> Result<Message> {
+        let mut wire = query(&["google.com"]).pack()?;
+        wire[..2].copy_from_slice(&id.to_be_bytes());
+        let mut framed = Vec::new();
+        tcp::send(&mut framed, &wire)?;
+
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(&framed).await?;
+        send.finish()?;
+        let framed = recv.read_to_end(tcp::MAX_MESSAGE_SIZE + 2).await?;
+        Message::unpack(&tcp::recv(&mut &framed[..])?)
+    }
+
+    #[test]
+    fn answers_concurrent_queries() {
+        let pki = pki("doq_answers");
+        let address = listener(&pki, "answers").serve("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect_quic(&address.to_string(), &pki.trusted(), false).unwrap();
+
+        let query = query(&["google.com", "example.com"]);
+        let response = resolver.resolve(query.clone()).unwrap();
+        assert_eq!(query.get_id(), response.get_id());
+        assert_eq!(2, response.answers().len());
+        assert!(resolver.resolve(query).is_ok());
+    }
+
+    #[test]
+    fn reconnects_once_idle() {
+        let pki = pki("doq_idle");
+        let address = listener(&pki, "idle")
+            .with_idle_timeout(Duration::from_millis(100))
+            .serve("127.0.0.1:0")
+            .unwrap();
+        let resolver = Resolver::connect_quic(&address.to_string(), &pki.trusted(), true).unwrap();
+
+        assert!(resolver.resolve(query(&["google.com"])).is_ok());
+        thread::sleep(Duration::from_millis(300));
+        assert!(resolver.resolve(query(&["google.com"])).is_ok());
+    }
+
+    #[test]
+    fn rejects_non_zero_ids() {
+        let pki = pki("doq_id");
+        let address = listener(&pki, "id").serve("127.0.0.1:0").unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let endpoint = endpoint(&pki);
+            let connection = endpoint
+                .connect(address, "dns.test")
+                .unwrap()
+                .await
+                .unwrap();
+            assert_eq!(0, ask(&connection, 0).await.unwrap().get_id());
+            assert!(ask(&connection, 1234).await.is_err());
+            let ConnectionError::ApplicationClosed(close) = connection.closed().await else {
+                panic!("connection not closed by the listener");
+            };
+            assert_eq!(PROTOCOL_ERROR, close.error_code);
+        });
+    }
+
+    #[test]
+    fn accepts_zero_rtt_once_enabled() {
+        let pki = pki("doq_0rtt");
+        let enabled = listener(&pki, "0rtt_on").with_zero_rtt();
+        let disabled = listener(&pki, "0rtt_off");
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for (listener, zero_rtt) in [(enabled, true), (disabled, false)] {
+            let address = listener.serve("127.0.0.1:0").unwrap();
+            runtime.block_on(async {
+                // A first connection gets the session ticket.
+                let endpoint = endpoint(&pki);
+                let connection = endpoint
+                    .connect(address, "dns.test")
+                    .unwrap()
+                    .await
+                    .unwrap();
+                ask(&connection, 0).await.unwrap();
+                connection.close(0u32.into(), b"");
+
+                let connecting = endpoint.connect(address, "dns.test").unwrap();
+                match connecting.into_0rtt() {
+                    Ok((connection, accepted)) => {
+                        assert!(zero_rtt);
+                        assert!(ask(&connection, 0).await.is_ok());
+                        assert!(accepted.await);
+                    }
+                    Err(_) => assert!(!zero_rtt),
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! DNS over TLS listener (RFC 7858): messages are framed as over TCP (RFC 7766), several queries
+//! may be in flight on a connection and their responses are sent as soon as they are ready.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+    time::timeout,
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{certs::Certificate, dnstap::Protocol, handler::Handler, tcp};
+
+/// Port of DNS over TLS servers.
+pub const DEFAULT_PORT: u16 = 853;
+
+/// How long a connection may stay without queries before it is closed.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Accepts DNS over TLS connections and answers their queries with a [`Handler`], the one the
+/// UDP listener uses.
+pub struct DotListener {
+    handler: Arc<Handler>,
+    acceptor: TlsAcceptor,
+    idle_timeout: Duration,
+}
+
+impl DotListener {
+    /// Presents `certificate`, reloaded as its files change.
+    pub fn new(handler: Arc<Handler>, certificate: Arc<Certificate>) -> Result<Self> {
+        let config = certificate.server_config(&[b"dot"])?;
+        Ok(DotListener {
+            handler,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        })
+    }
+
+    /// Closes connections once no query came for `timeout` (RFC 7766 section 6.2.3), handshakes
+    /// must complete within it too.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Binds `address` and serves connections from a background thread, returning the bound
+    /// address.
+    pub fn serve(self, address: impl ToSocketAddrs) -> Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let bound = listener.local_addr()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let listener = {
+            let _runtime = runtime.enter();
+            TcpListener::from_std(listener)?
+        };
+        let this = Arc::new(self);
+        thread::spawn(move || runtime.block_on(this.accept(listener)));
+        Ok(bound)
+    }
+
+    async fn accept(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, client)) => {
+                    let this = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = this.connection(stream, client).await {
+                            eprintln!("DNS over TLS connection from {} failed: {:#}", client, e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Cannot accept DNS over TLS connection: {}", e),
+            }
+        }
+    }
+
+    /// Answers the queries of a connection until the client closes it or it is idle, then sends
+    /// the responses still pending before closing it.
+    async fn connection(&self, tcp: TcpStream, client: SocketAddr) -> Result<()> {
+        let local = tcp.local_addr()?;
+        let stream = timeout(self.idle_timeout, self.acceptor.accept(tcp))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        let (mut reader, writer) = tokio::io::split(stream);
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+
+        let mut pending = JoinSet::new();
+        while let Ok(Ok(query)) = timeout(self.idle_timeout, tcp::recv_async(&mut reader)).await {
+            let (handler, writer) = (self.handler.clone(), writer.clone());
+            pending.spawn(async move {
+                let response = tokio::task::spawn_blocking(move || {
+                    handler.handle_over(Protocol::Dot, &query, client, local)
+                })
+                .await??;
+                let mut framed = Vec::with_capacity(response.len() + 2);
+                tcp::send(&mut framed, &response)?;
+                writer.lock().await.write_all(&framed).await?;
+                anyhow::Ok(())
+            });
+            while let Some(answered) = pending.try_join_next() {
+                log(client, answered);
+            }
+        }
+        while let Some(answered) = pending.join_next().await {
+            log(client, answered);
+        }
+        writer.lock().await.shutdown().await?;
+        Ok(())
+    }
+}
+
+fn log(client: SocketAddr, answered: Result<Result<()>, tokio::task::JoinError>) {
+    match answered {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("Cannot answer DNS over TLS query from {}: {:#}", client, e),
+        Err(e) => eprintln!("Cannot answer DNS over TLS query from {}: {}", client, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::io::AsyncReadExt;
+    use tokio_rustls::TlsConnector;
+
+    use crate::{
+        message::resolver::tls::{
+            client_config,
+            testing::{handler, pki, query, Pki},
+        },
+        Resolver,
+    };
+
+    use super::*;
+
+    /// Certificate files of a test.
+    fn files(test: &str) -> (PathBuf, PathBuf) {
+        let path = |kind| {
+            std::env::temp_dir().join(format!("dot-{}-{}.{}", std::process::id(), test, kind))
+        };
+        (path("crt"), path("key"))
+    }
+
+    fn listener(pki: &Pki, test: &str) -> DotListener {
+        let (cert, key) = files(test);
+        pki.write(&cert, &key);
+        let certificate = Arc::new(Certificate::load(cert, key).unwrap());
+        DotListener::new(handler([1, 2, 3, 4], &[300]), certificate).unwrap()
+    }
+
+    #[test]
+    fn answers_pipelined_queries() {
+        let pki = pki("dot_answers");
+        let address = listener(&pki, "answers").serve("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect_tls(&address.to_string(), &pki.trusted()).unwrap();
+
+        let query = query(&["google.com", "example.com"]);
+        let response = resolver.resolve(query.clone()).unwrap();
+        assert_eq!(query.get_id(), response.get_id());
+        assert_eq!(2, response.answers().len());
+    }
+
+    #[test]
+    fn closes_idle_connections() {
+        let pki = pki("dot_idle");
+        let address = listener(&pki, "idle")
+            .with_idle_timeout(Duration::from_millis(100))
+            .serve("127.0.0.1:0")
+            .unwrap();
+        let connector = TlsConnector::from(Arc::new(client_config(&pki.trusted()).unwrap()));
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let read = runtime.block_on(async {
+            let tcp = TcpStream::connect(address).await.unwrap();
+            let name = "dns.test".try_into().unwrap();
+            let mut stream = connector.connect(name, tcp).await.unwrap();
+            timeout(Duration::from_secs(2), stream.read(&mut [0u8; 2])).await
+        });
+        assert_eq!(0, read.unwrap().unwrap());
+    }
+
+    #[test]
+    fn reloads_certificate() {
+        let (old, new) = (pki("dot_old"), pki("dot_new"));
+        let address = listener(&old, "reload").serve("127.0.0.1:0").unwrap();
+        let resolve = |pki: &Pki| {
+            Resolver::connect_tls(&address.to_string(), &pki.trusted())
+                .unwrap()
+                .resolve(query(&["google.com"]))
+        };
+        assert!(resolve(&old).is_ok());
+
+        let (cert, key) = files("reload");
+        new.write(&cert, &key);
+        assert!(resolve(&new).is_ok());
+        assert!(resolve(&old).is_err());
+    }
+
+    #[test]
+    fn keeps_certificate_failing_to_reload() {
+        let pki = pki("dot_broken");
+        let address = listener(&pki, "broken").serve("127.0.0.1:0").unwrap();
+
+        let (cert, _) = files("broken");
+        std::fs::write(cert, "not a certificate").unwrap();
+        let resolver = Resolver::connect_tls(&address.to_string(), &pki.trusted()).unwrap();
+        assert!(resolver.resolve(query(&["google.com"])).is_ok());
+    }
+}
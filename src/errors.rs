@@ -1,16 +1,180 @@
 use std::fmt::Display;
 
-use crate::message::header::Header;
+use packed_struct::PackingError;
 
+use crate::message::{
+    edns::ede::{ExtendedError, InfoCode},
+    header::ResponseCode,
+};
+
+/// Every failure the proxy knows about, each answered with its own response code.
+///
+/// Malformed queries get `FORMERR`, valid queries asking for something the proxy does not support
+/// get `NOTIMP`, queries the proxy will not serve get `REFUSED`, updates to zones the proxy does
+/// not own get `NOTAUTH` and anything going wrong while resolving gets `SERVFAIL`.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum DnsError {
     BufLenNotEq { exp: usize, act: usize },
     BufLenSmall { min: usize, act: usize },
     InvalidEncoding { at: usize },
+    InvalidName(String),
+    MsgTooLong { max: usize, act: usize },
+    UnsupportedType(u16),
+    UnsupportedClass(u16),
+    UnsupportedOpcode(u8),
+    MultipleQuestions(usize),
+    NonZeroId(u16),
+    UnknownMnemonic(String),
+    InvalidTtl(String),
+    InvalidSyntax(String),
+    InvalidZone { file: String, line: usize },
+    InvalidZoneData(String),
+    InvalidPin(String),
+    InvalidUrl(String),
+    Refused(String),
+    NotAuthoritative(String),
+    ArgNoValue(String),
+    ArgUnknown(String),
+    ArgInvalid { flag: String, value: String },
+    ArgRequires { flag: String, requires: String },
+    DnstapHandshake { exp: u32, act: u32 },
     ResolverNotSpecified,
-    ResolverFailed(Header),
-    ResolverNoAnsw,
+    ResolverMalformed,
     ResolverNoRecv,
+    ResolverHttpStatus(u16),
+}
+
+/// Response code answering a query that failed with `err`.
+///
+/// Errors wrapped with a [`DnsError`] as context are mapped according to the context, so that e.g.
+/// a malformed upstream reply is a `SERVFAIL` rather than a `FORMERR`.
+pub fn rcode(err: &anyhow::Error) -> ResponseCode {
+    if let Some(err) = err.downcast_ref::<DnsError>() {
+        err.rcode()
+    } else if err.is::<PackingError>() {
+        ResponseCode::FormatError
+    } else {
+        ResponseCode::ServerFailure
+    }
+}
+
+/// Extended DNS Error explaining why a query failed with `err`, its text is the error message.
+pub fn extended_error(err: &anyhow::Error) -> ExtendedError {
+    let info_code = if let Some(err) = err.downcast_ref::<DnsError>() {
+        err.info_code()
+    } else if let Some(err) = err.downcast_ref::<std::io::Error>() {
+        match err.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                InfoCode::NoReachableAuthority
+            }
+            _ => InfoCode::NetworkError,
+        }
+    } else {
+        InfoCode::Other
+    };
+    ExtendedError::new(info_code, err.to_string())
+}
+
+impl DnsError {
+    pub fn rcode(&self) -> ResponseCode {
+        match self {
+            DnsError::BufLenSmall { .. }
+            | DnsError::InvalidEncoding { .. }
+            | DnsError::InvalidName(_)
+            | DnsError::MultipleQuestions(_)
+            | DnsError::NonZeroId(_) => ResponseCode::FormatError,
+            DnsError::UnsupportedType(_)
+            | DnsError::UnsupportedClass(_)
+            | DnsError::UnsupportedOpcode(_) => ResponseCode::NotImplemented,
+            DnsError::Refused(_) => ResponseCode::Refused,
+            DnsError::NotAuthoritative(_) => ResponseCode::NotAuth,
+            DnsError::BufLenNotEq { .. }
+            | DnsError::MsgTooLong { .. }
+            | DnsError::UnknownMnemonic(_)
+            | DnsError::InvalidTtl(_)
+            | DnsError::InvalidSyntax(_)
+            | DnsError::InvalidZone { .. }
+            | DnsError::InvalidZoneData(_)
+            | DnsError::InvalidPin(_)
+            | DnsError::InvalidUrl(_)
+            | DnsError::ArgNoValue(_)
+            | DnsError::ArgUnknown(_)
+            | DnsError::ArgInvalid { .. }
+            | DnsError::ArgRequires { .. }
+            | DnsError::DnstapHandshake { .. }
+            | DnsError::ResolverNotSpecified
+            | DnsError::ResolverMalformed
+            | DnsError::ResolverNoRecv
+            | DnsError::ResolverHttpStatus(_) => ResponseCode::ServerFailure,
+        }
+    }
+
+    pub fn info_code(&self) -> InfoCode {
+        match self {
+            DnsError::UnsupportedType(_)
+            | DnsError::UnsupportedClass(_)
+            | DnsError::UnsupportedOpcode(_) => InfoCode::NotSupported,
+            DnsError::Refused(_) => InfoCode::Prohibited,
+            DnsError::NotAuthoritative(_) => InfoCode::NotAuthoritative,
+            DnsError::ResolverMalformed => InfoCode::InvalidData,
+            DnsError::ResolverNoRecv | DnsError::ResolverHttpStatus(_) => InfoCode::NetworkError,
+            DnsError::BufLenNotEq { .. }
+            | DnsError::BufLenSmall { .. }
+            | DnsError::InvalidEncoding { .. }
+            | DnsError::InvalidName(_)
+            | DnsError::MultipleQuestions(_)
+            | DnsError::NonZeroId(_)
+            | DnsError::MsgTooLong { .. }
+            | DnsError::UnknownMnemonic(_)
+            | DnsError::InvalidTtl(_)
+            | DnsError::InvalidSyntax(_)
+            | DnsError::InvalidZone { .. }
+            | DnsError::InvalidZoneData(_)
+            | DnsError::InvalidPin(_)
+            | DnsError::InvalidUrl(_)
+            | DnsError::ArgNoValue(_)
+            | DnsError::ArgUnknown(_)
+            | DnsError::ArgInvalid { .. }
+            | DnsError::ArgRequires { .. }
+            | DnsError::DnstapHandshake { .. }
+            | DnsError::ResolverNotSpecified => InfoCode::Other,
+        }
+    }
+
+    /// Name of the variant, used to label error metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DnsError::BufLenNotEq { .. } => "BufLenNotEq",
+            DnsError::BufLenSmall { .. } => "BufLenSmall",
+            DnsError::InvalidEncoding { .. } => "InvalidEncoding",
+            DnsError::InvalidName(_) => "InvalidName",
+            DnsError::MsgTooLong { .. } => "MsgTooLong",
+            DnsError::UnsupportedType(_) => "UnsupportedType",
+            DnsError::UnsupportedClass(_) => "UnsupportedClass",
+            DnsError::UnsupportedOpcode(_) => "UnsupportedOpcode",
+            DnsError::MultipleQuestions(_) => "MultipleQuestions",
+            DnsError::NonZeroId(_) => "NonZeroId",
+            DnsError::UnknownMnemonic(_) => "UnknownMnemonic",
+            DnsError::InvalidTtl(_) => "InvalidTtl",
+            DnsError::InvalidSyntax(_) => "InvalidSyntax",
+            DnsError::InvalidZone { .. } => "InvalidZone",
+            DnsError::InvalidZoneData(_) => "InvalidZoneData",
+            DnsError::InvalidPin(_) => "InvalidPin",
+            DnsError::InvalidUrl(_) => "InvalidUrl",
+            DnsError::Refused(_) => "Refused",
+            DnsError::NotAuthoritative(_) => "NotAuthoritative",
+            DnsError::ArgNoValue(_) => "ArgNoValue",
+            DnsError::ArgUnknown(_) => "ArgUnknown",
+            DnsError::ArgInvalid { .. } => "ArgInvalid",
+            DnsError::ArgRequires { .. } => "ArgRequires",
+            DnsError::DnstapHandshake { .. } => "DnstapHandshake",
+            DnsError::ResolverNotSpecified => "ResolverNotSpecified",
+            DnsError::ResolverMalformed => "ResolverMalformed",
+            DnsError::ResolverNoRecv => "ResolverNoRecv",
+            DnsError::ResolverHttpStatus(_) => "ResolverHttpStatus",
+        }
+    }
 }
 
 impl std::error::Error for DnsError {}
@@ -31,23 +195,184 @@ impl Display for DnsError {
             DnsError::InvalidEncoding { at } => {
                 write!(f, "Invalid domain encoding discovered at byte {}", at)
             }
-            DnsError::ResolverNotSpecified => write!(
+            DnsError::InvalidName(name) => write!(
                 f,
-                "Resolver address is not specified or specifed incorrectly. Usage: `run_server -r|--resolver <address>`"
+                "Invalid domain name `{}`: labels must be 1 to 63 bytes, names at most 255 bytes",
+                name,
+            ),
+            DnsError::MsgTooLong { max, act } => write!(
+                f,
+                "Message is too long: expected at most {} bytes, got {}",
+                max, act,
+            ),
+            DnsError::UnsupportedType(value) => write!(f, "Unsupported type {}", value),
+            DnsError::UnsupportedClass(value) => write!(f, "Unsupported class {}", value),
+            DnsError::UnsupportedOpcode(value) => write!(f, "Unsupported opcode {}", value),
+            DnsError::MultipleQuestions(count) => {
+                write!(f, "Expected a single question, got {}", count)
+            }
+            DnsError::NonZeroId(id) => {
+                write!(f, "Expected message ID 0 over DNS over QUIC, got {}", id)
+            }
+            DnsError::UnknownMnemonic(value) => write!(f, "Unknown type or class `{}`", value),
+            DnsError::InvalidTtl(value) => write!(
+                f,
+                "Invalid TTL `{}`, expected seconds or units such as `1h30m`",
+                value,
+            ),
+            DnsError::InvalidSyntax(reason) => write!(f, "Invalid presentation format: {}", reason),
+            DnsError::InvalidZone { file, line } => {
+                write!(f, "Invalid zone file `{}` at line {}", file, line)
+            }
+            DnsError::InvalidZoneData(reason) => write!(f, "Invalid zone data: {}", reason),
+            DnsError::InvalidPin(value) => write!(
+                f,
+                "Invalid certificate pin `{}`, expected `spki-sha256:<base64>` or `cert-sha256:<base64>`",
+                value,
             ),
-            DnsError::ResolverFailed(header) => write!(
+            DnsError::InvalidUrl(value) => write!(
                 f,
-                "Invalid message received from DNS resolver, expected 1 answer, got: {:?}",
-                header,
+                "Invalid DNS over HTTPS URL `{}`, expected `https://<host>[:port]/<path>`",
+                value,
             ),
-            DnsError::ResolverNoAnsw => write!(
+            DnsError::Refused(reason) => write!(f, "Query refused: {}", reason),
+            DnsError::NotAuthoritative(zone) => {
+                write!(f, "Not authoritative for zone `{}`", zone)
+            }
+            DnsError::ArgNoValue(flag) => write!(f, "Command line flag `{}` requires a value", flag),
+            DnsError::ArgUnknown(flag) => write!(f, "Unknown command line flag `{}`", flag),
+            DnsError::ArgInvalid { flag, value } => {
+                write!(f, "Invalid value `{}` for command line flag `{}`", value, flag)
+            }
+            DnsError::ArgRequires { flag, requires } => {
+                write!(f, "Command line flag `{}` requires `{}`", flag, requires)
+            }
+            DnsError::DnstapHandshake { exp, act } => write!(
+                f,
+                "Unexpected dnstap control frame: expected {:#04x}, got {:#04x}",
+                exp, act,
+            ),
+            DnsError::ResolverNotSpecified => write!(
                 f,
-                "No answer received from DNS resolver, but it promised exactly 1 answer",
+                "Resolver address is not specified or specifed incorrectly. Usage: `run_server -r|--resolver <address>`"
             ),
+            DnsError::ResolverMalformed => {
+                write!(f, "Malformed message received from DNS resolver")
+            }
             DnsError::ResolverNoRecv => write!(
                 f,
                 "Failed to forward message to the DNS resolver, 0 bytes was sent",
             ),
+            DnsError::ResolverHttpStatus(status) => {
+                write!(f, "DNS resolver answered with HTTP status {}", status)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use crate::message::Message;
+
+    use super::*;
+
+    fn unpack_err(raw: &[u8]) -> ResponseCode {
+        rcode(&Message::unpack(raw).unwrap_err())
+    }
+
+    #[test]
+    fn malformed_is_format_error() {
+        // Header cut short.
+        assert_eq!(ResponseCode::FormatError, unpack_err(b"\x04\xD2\x01\x00"));
+        // Question name running past the end of the message.
+        assert_eq!(
+            ResponseCode::FormatError,
+            unpack_err(b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com")
+        );
+        // Question without type and class.
+        assert_eq!(
+            ResponseCode::FormatError,
+            unpack_err(b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com\x00")
+        );
+    }
+
+    #[test]
+    fn unsupported_is_not_implemented() {
+        // Question of type 99.
+        assert_eq!(
+            ResponseCode::NotImplemented,
+            unpack_err(b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com\x00\x00\x63\x00\x01")
+        );
+        // Question of class 2.
+        assert_eq!(
+            ResponseCode::NotImplemented,
+            unpack_err(b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com\x00\x00\x01\x00\x02")
+        );
+        assert_eq!(
+            ResponseCode::NotImplemented,
+            rcode(&anyhow!(DnsError::UnsupportedOpcode(2)))
+        );
+    }
+
+    #[test]
+    fn policy_is_refused() {
+        assert_eq!(
+            ResponseCode::Refused,
+            rcode(&anyhow!(DnsError::Refused("zone transfer".into())))
+        );
+    }
+
+    #[test]
+    fn foreign_zone_is_not_auth() {
+        assert_eq!(
+            ResponseCode::NotAuth,
+            rcode(&anyhow!(DnsError::NotAuthoritative("example.com.".into())))
+        );
+    }
+
+    #[test]
+    fn resolver_failure_is_server_failure() {
+        assert_eq!(
+            ResponseCode::ServerFailure,
+            rcode(&anyhow!(DnsError::ResolverHttpStatus(503)))
+        );
+        let timeout = std::io::Error::from(std::io::ErrorKind::WouldBlock);
+        assert_eq!(ResponseCode::ServerFailure, rcode(&anyhow!(timeout)));
+
+        let malformed = Message::unpack(b"\x04\xD2\x81\x00")
+            .context(DnsError::ResolverMalformed)
+            .unwrap_err();
+        assert_eq!(ResponseCode::ServerFailure, rcode(&malformed));
+    }
+
+    #[test]
+    fn extended_errors() {
+        let refused = extended_error(&anyhow!(DnsError::Refused("zone transfer".into())));
+        assert_eq!(InfoCode::Prohibited, refused.info_code);
+        assert_eq!("Query refused: zone transfer", refused.extra_text);
+
+        let foreign = extended_error(&anyhow!(DnsError::NotAuthoritative("example.com.".into())));
+        assert_eq!(InfoCode::NotAuthoritative, foreign.info_code);
+        assert_eq!(
+            "Not authoritative for zone `example.com.`",
+            foreign.extra_text
+        );
+
+        let timeout = std::io::Error::from(std::io::ErrorKind::WouldBlock);
+        assert_eq!(
+            InfoCode::NoReachableAuthority,
+            extended_error(&anyhow!(timeout)).info_code
+        );
+
+        let malformed = Message::unpack(b"\x04\xD2\x81\x00")
+            .context(DnsError::ResolverMalformed)
+            .unwrap_err();
+        assert_eq!(InfoCode::InvalidData, extended_error(&malformed).info_code);
+        assert_eq!(
+            InfoCode::NotSupported,
+            extended_error(&anyhow!(DnsError::UnsupportedOpcode(2))).info_code
+        );
+    }
+}
@@ -0,0 +1,763 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    dnstap::{self, Dnstap},
+    errors::{self, DnsError},
+    message::{
+        edns::Edns,
+        header::{Header, Opcode, DNS_HEADER_SIZE},
+        labels::Labels,
+        question::Question,
+        resolver::Resolver,
+        rr, Message,
+    },
+    metrics::Metrics,
+    zone::Zone,
+};
+
+/// Offset of the question count in the header.
+const QDCOUNT: usize = 4;
+
+/// Zone transfer types (RFC 1995, RFC 5936): served zones are not handed out whole.
+const IXFR: u16 = 251;
+const AXFR: u16 = 252;
+
+/// Turns raw queries received by a listener into raw responses to send back.
+///
+/// Queries about names of the configured zones are answered from them, whatever their type and
+/// in passthrough mode too, the others are forwarded to the resolver. Zone transfers are refused.
+///
+/// Failures never propagate to the listener: they are answered with the RCODE of their
+/// [`DnsError`], see [`errors::rcode`], and explained by an Extended DNS Error when the query
+/// carried EDNS.
+pub struct Handler {
+    resolver: Resolver,
+    dnstap: Option<Arc<Dnstap>>,
+    metrics: Option<Arc<Metrics>>,
+    notify: OpcodePolicy,
+    update: OpcodePolicy,
+    passthrough: bool,
+    zones: Vec<Arc<Zone>>,
+}
+
+/// What to do with messages whose opcode is not a standard query.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OpcodePolicy {
+    /// Answer `NOTIMP`.
+    #[default]
+    Reject,
+    /// Answer on our own: NOTIFY is acknowledged, UPDATE gets `NOTAUTH` since the proxy takes
+    /// no dynamic updates, not even to the zones it serves.
+    Handle,
+    /// Forward the message upstream verbatim and relay the reply untouched.
+    Proxy,
+}
+
+impl Handler {
+    pub fn new(resolver: Resolver) -> Self {
+        Handler {
+            resolver,
+            dnstap: None,
+            metrics: None,
+            notify: OpcodePolicy::default(),
+            update: OpcodePolicy::default(),
+            passthrough: false,
+            zones: Vec::new(),
+        }
+    }
+
+    /// Answers queries about names of `zone` authoritatively, rather than forwarding them. The
+    /// closest zone answers when zones are nested.
+    pub fn with_zone(mut self, zone: Arc<Zone>) -> Self {
+        self.zones.push(zone);
+        self
+    }
+
+    /// Relays standard queries to the upstream as raw bytes instead of resolving them question by
+    /// question, see [`Resolver::relay`].
+    ///
+    /// Only the header needs to parse, so records, flags and options the parser does not model
+    /// survive the round trip.
+    pub fn with_passthrough(mut self) -> Self {
+        self.passthrough = true;
+        self
+    }
+
+    /// Sets what to do with NOTIFY messages (RFC 1996).
+    pub fn with_notify(mut self, policy: OpcodePolicy) -> Self {
+        self.notify = policy;
+        self
+    }
+
+    /// Sets what to do with UPDATE messages (RFC 2136).
+    pub fn with_update(mut self, policy: OpcodePolicy) -> Self {
+        self.update = policy;
+        self
+    }
+
+    /// Logs client and upstream messages to `dnstap`.
+    pub fn with_dnstap(mut self, dnstap: Arc<Dnstap>) -> Self {
+        self.resolver = self.resolver.with_dnstap(dnstap.clone());
+        self.dnstap = Some(dnstap);
+        self
+    }
+
+    /// Records queries, errors and upstream latency in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.resolver = self.resolver.with_metrics(metrics.clone());
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Handles `query` received from `client` on the UDP listener bound to `local`.
+    pub fn handle(&self, query: &[u8], client: SocketAddr, local: SocketAddr) -> Result<Vec<u8>> {
+        self.handle_over(dnstap::Protocol::Udp, query, client, local)
+    }
+
+    /// Handles `query` received from `client` over `protocol` on the listener bound to `local`.
+    pub fn handle_over(
+        &self,
+        protocol: dnstap::Protocol,
+        query: &[u8],
+        client: SocketAddr,
+        local: SocketAddr,
+    ) -> Result<Vec<u8>> {
+        let _in_flight = self.metrics.as_ref().map(|metrics| metrics.in_flight());
+        let tap = |kind, wire: &[u8]| {
+            if let Some(dnstap) = &self.dnstap {
+                dnstap.log(kind, protocol, client, local, wire);
+            }
+        };
+        tap(dnstap::Kind::ClientQuery, query);
+
+        // Responses over UDP have to fit what the client can receive.
+        let udp = protocol == dnstap::Protocol::Udp;
+        let bytes = match Header::unpack(query) {
+            Ok(header) if header.opcode != Opcode::Query => self.dispatch(header.opcode, query)?,
+            Ok(header) => match self.zone(header, query) {
+                Some((zone, question)) => self.answer(zone, question, query, udp)?,
+                None if self.passthrough => self.relay(query, udp)?,
+                None => self.query(query, udp)?,
+            },
+            Err(_) => self.query(query, udp)?,
+        };
+
+        tap(dnstap::Kind::ClientResponse, &bytes);
+        Ok(bytes)
+    }
+
+    /// Answers a standard query, within the UDP size limit of the client if `udp`.
+    fn query(&self, query: &[u8], udp: bool) -> Result<Vec<u8>> {
+        Ok(match Message::unpack(query) {
+            Ok(query) => self.resolve(query, udp)?,
+            Err(e) => {
+                println!("Cannot unpack message: {}", e);
+                self.error(&e);
+                match Message::unpack_partial(query) {
+                    Some(partial) => Self::failure(&partial, &e),
+                    // Without a header there is nothing to echo, the query is malformed.
+                    None => Message::new_client_err()
+                        .with_id(Header::unpack_id(query).unwrap_or_default()),
+                }
+                .pack()?
+            }
+        })
+    }
+
+    fn resolve(&self, query: Message, udp: bool) -> Result<Vec<u8>> {
+        let mut response = self.resolver.resolve(query.clone()).unwrap_or_else(|err| {
+            eprintln!("Cannot resolve query: {}", err);
+            self.error(&err);
+            Self::failure(&query, &err)
+        });
+        if udp {
+            response.truncate(query.udp_limit());
+        }
+        self.count(&query, &response);
+        response.pack()
+    }
+
+    /// Answers a standard query about a name of `zone`, the question of `raw` ending at `end`,
+    /// within the UDP size limit of the client if `udp`.
+    ///
+    /// Types the parser does not model are answered all the same, with the question echoed as
+    /// received.
+    fn answer(
+        &self,
+        zone: &Zone,
+        (name, qtype, end): (Labels, u16, usize),
+        raw: &[u8],
+        udp: bool,
+    ) -> Result<Vec<u8>> {
+        let query = match Message::unpack(raw) {
+            Ok(query) => query,
+            Err(_) => match Message::unpack_partial(raw) {
+                Some(partial) if partial.questions().is_empty() => partial,
+                // Only the rest of the message is at fault.
+                _ => return self.query(raw, udp),
+            },
+        };
+        let mut response = match qtype {
+            IXFR | AXFR => {
+                let err = anyhow!(DnsError::Refused(format!(
+                    "zone transfer of `{}`",
+                    zone.origin().to_fqdn()
+                )));
+                self.error(&err);
+                Self::failure(&query, &err)
+            }
+            _ => zone.answer_type(&query, &name, qtype),
+        };
+        self.count(&query, &response);
+        if udp {
+            // Leaves room for the question spliced in below.
+            let spliced = match query.questions().is_empty() {
+                true => end - DNS_HEADER_SIZE,
+                false => 0,
+            };
+            response.truncate(Message::udp_limit_of(raw).saturating_sub(spliced));
+        }
+        let mut bytes = response.pack()?;
+        if query.questions().is_empty() {
+            bytes.splice(
+                DNS_HEADER_SIZE..DNS_HEADER_SIZE,
+                raw[DNS_HEADER_SIZE..end].to_vec(),
+            );
+            bytes[QDCOUNT..QDCOUNT + 2].copy_from_slice(&1u16.to_be_bytes());
+        }
+        Ok(bytes)
+    }
+
+    /// Relays a standard query in passthrough mode, within the UDP size limit of the client if
+    /// `udp`.
+    fn relay(&self, raw: &[u8], udp: bool) -> Result<Vec<u8>> {
+        let query = Message::unpack_partial(raw).unwrap_or_default();
+        match self.resolver.relay(raw) {
+            Ok(reply) => {
+                if let (Some(metrics), Ok(header)) = (&self.metrics, Header::unpack(&reply)) {
+                    metrics.query(&query, header.rcode);
+                }
+                Ok(match udp {
+                    true => Message::truncate_wire(reply, Message::udp_limit_of(raw)),
+                    false => reply,
+                })
+            }
+            Err(err) => {
+                eprintln!("Cannot relay query: {}", err);
+                self.error(&err);
+                let response = Self::failure(&query, &err);
+                self.count(&query, &response);
+                response.pack()
+            }
+        }
+    }
+
+    /// Answers a message of any other opcode, according to the configured policies.
+    ///
+    /// Only the header and the question (or zone) section are parsed, the other sections may hold
+    /// records a standard query never does.
+    fn dispatch(&self, opcode: Opcode, raw: &[u8]) -> Result<Vec<u8>> {
+        let policy = match opcode {
+            Opcode::Notify => self.notify,
+            Opcode::Update => self.update,
+            _ => OpcodePolicy::Reject,
+        };
+        let query = Message::unpack_partial(raw).unwrap_or_default();
+
+        let answered = match policy {
+            OpcodePolicy::Proxy => match self.resolver.exchange(raw) {
+                Ok(reply) => return Ok(reply),
+                Err(err) => Err(err),
+            },
+            OpcodePolicy::Handle if opcode == Opcode::Notify => {
+                Ok(Message::response(&query).authoritative().build())
+            }
+            OpcodePolicy::Handle if opcode == Opcode::Update => {
+                let zone = query.questions().first().map(|zone| zone.domain.to_fqdn());
+                Err(DnsError::NotAuthoritative(zone.unwrap_or_default()).into())
+            }
+            _ => Err(DnsError::UnsupportedOpcode(opcode.into()).into()),
+        };
+        let response = answered.unwrap_or_else(|err| {
+            eprintln!("Cannot answer {} message: {}", opcode, err);
+            self.error(&err);
+            Self::failure(&query, &err)
+        });
+        self.count(&query, &response);
+        response.pack()
+    }
+
+    /// Zone serving the question of the standard query `raw`, the closest one when zones are
+    /// nested, along with its name, its type as a number and where it ends. The question is read
+    /// as is, so that queries of types the parser does not model never leave the zone. Messages
+    /// holding several questions are forwarded.
+    fn zone(&self, header: Header, raw: &[u8]) -> Option<(&Zone, (Labels, u16, usize))> {
+        if header.qdcount != 1 {
+            return None;
+        }
+        let mut end = DNS_HEADER_SIZE;
+        let (name, qtype, qclass) = Question::unpack_raw(raw, &mut end).ok()?;
+        let class = rr::Class::try_from(qclass).ok()?;
+        let zone = self
+            .zones
+            .iter()
+            .filter(|zone| zone.serves_name(&name, class))
+            .max_by_key(|zone| zone.origin().as_slice().len())?;
+        Some((zone, (name, qtype, end)))
+    }
+
+    fn count(&self, query: &Message, response: &Message) {
+        if let Some(metrics) = &self.metrics {
+            metrics.query(query, response.header().rcode);
+        }
+    }
+
+    /// Response to `query` failed with `err`.
+    fn failure(query: &Message, err: &anyhow::Error) -> Message {
+        let response = Message::response(query).rcode(errors::rcode(err));
+        match query.edns() {
+            Some(_) => response
+                .edns(Edns::default().with_option(errors::extended_error(err).into()))
+                .build(),
+            None => response.build(),
+        }
+    }
+
+    fn error(&self, err: &anyhow::Error) {
+        if let Some(metrics) = &self.metrics {
+            metrics.error(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use crate::message::{
+        answer::Answer,
+        edns::ede::InfoCode,
+        header::{AuthoritativeAnswer, RecursionDesired, ResponseCode, Truncation},
+        resolver::{
+            tls::testing::{pki, upstream},
+            MultiQuestion,
+        },
+        rr, MAX_UDP_SIZE,
+    };
+
+    use super::*;
+
+    /// Handler forwarding to a socket that never answers.
+    fn unanswered() -> (Handler, UdpSocket) {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect(upstream.local_addr().unwrap()).unwrap();
+        (Handler::new(resolver), upstream)
+    }
+
+    fn handle(handler: &Handler, query: &[u8]) -> Message {
+        let addr = "127.0.0.1:2053".parse().unwrap();
+        Message::unpack(&handler.handle(query, addr, addr).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn server_failure_echoes_question() {
+        let (handler, _upstream) = unanswered();
+        let query = Message::query("google.com".parse().unwrap(), rr::Type::Aaaa)
+            .recursion_desired()
+            .build();
+
+        let response = handle(&handler, &query.pack().unwrap());
+        assert_eq!(query.get_id(), response.get_id());
+        assert_eq!(ResponseCode::ServerFailure, response.header().rcode);
+        assert_eq!(RecursionDesired::Yes, response.header().rd);
+        assert_eq!(query.questions(), response.questions());
+        assert!(response.edns().is_none());
+    }
+
+    #[test]
+    fn server_failure_is_explained() {
+        let (handler, _upstream) = unanswered();
+        let query = Message::query("google.com".parse().unwrap(), rr::Type::A)
+            .edns(Edns::default())
+            .build();
+
+        let response = handle(&handler, &query.pack().unwrap());
+        assert_eq!(ResponseCode::ServerFailure, response.header().rcode);
+        let errors = response
+            .edns()
+            .unwrap()
+            .extended_errors()
+            .collect::<Vec<_>>();
+        assert_eq!(1, errors.len());
+        assert_eq!(InfoCode::NoReachableAuthority, errors[0].info_code);
+    }
+
+    #[test]
+    fn format_error_echoes_question() {
+        let (handler, _upstream) = unanswered();
+        // One question followed by an answer cut short.
+        let raw = b"\x04\xD2\x01\x00\x00\x01\x00\x01\x00\x00\x00\x00\x06google\x03com\x00\x00\x01\x00\x01\xC0\x0C\x00";
+
+        let response = handle(&handler, raw);
+        assert_eq!(1234, response.get_id());
+        assert_eq!(ResponseCode::FormatError, response.header().rcode);
+        assert_eq!(RecursionDesired::Yes, response.header().rd);
+        assert_eq!(1, response.header().qdcount);
+        assert_eq!("google.com", response.questions()[0].domain.to_string());
+    }
+
+    #[test]
+    fn unsupported_opcode_is_not_implemented() {
+        let (handler, _upstream) = unanswered();
+        // STATUS query for google.com A.
+        let raw = b"\x04\xD2\x11\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com\x00\x00\x01\x00\x01";
+
+        let response = handle(&handler, raw);
+        assert_eq!(ResponseCode::NotImplemented, response.header().rcode);
+        assert_eq!(Opcode::Status, response.header().opcode);
+        assert_eq!(1, response.header().qdcount);
+    }
+
+    // NOTIFY for example.com SOA.
+    const NOTIFY: &[u8] =
+        b"\x04\xD2\x20\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x06\x00\x01";
+    // UPDATE of example.com deleting an A record, whose class NONE a standard query never holds.
+    const UPDATE: &[u8] = b"\x04\xD2\x28\x00\x00\x01\x00\x00\x00\x01\x00\x00\x07example\x03com\x00\x00\x06\x00\x01\xC0\x0C\x00\x01\x00\xFE\x00\x00\x00\x00\x00\x04\x01\x02\x03\x04";
+
+    #[test]
+    fn notify_and_update_rejected_by_default() {
+        let (handler, _upstream) = unanswered();
+        for raw in [NOTIFY, UPDATE] {
+            let response = handle(&handler, raw);
+            assert_eq!(ResponseCode::NotImplemented, response.header().rcode);
+            assert_eq!("example.com", response.questions()[0].domain.to_string());
+        }
+    }
+
+    #[test]
+    fn notify_and_update_handled() {
+        let (handler, _upstream) = unanswered();
+        let handler = handler
+            .with_notify(OpcodePolicy::Handle)
+            .with_update(OpcodePolicy::Handle);
+
+        let response = handle(&handler, NOTIFY);
+        assert_eq!(Opcode::Notify, response.header().opcode);
+        assert_eq!(ResponseCode::NoError, response.header().rcode);
+        assert_eq!(AuthoritativeAnswer::Yes, response.header().aa);
+
+        let response = handle(&handler, UPDATE);
+        assert_eq!(Opcode::Update, response.header().opcode);
+        assert_eq!(ResponseCode::NotAuth, response.header().rcode);
+    }
+
+    #[test]
+    fn truncates_udp_responses() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect(upstream.local_addr().unwrap())
+            .unwrap()
+            .with_multi_question(MultiQuestion::Split);
+        let handler = Handler::new(resolver);
+        // Each question is answered with 16 records, which fit a datagram but not two of them.
+        let answering = std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            for _ in 0..4 {
+                let (size, peer) = upstream.recv_from(&mut buf).unwrap();
+                let query = Message::unpack(&buf[..size]).unwrap();
+                let name = &query.questions()[0].domain;
+                let reply = (0..16).fold(Message::response(&query), |reply, i| {
+                    let address = vec![192, 0, 2, i];
+                    reply.answer(Answer::new(
+                        name.clone(),
+                        rr::Type::A,
+                        rr::Class::In,
+                        300,
+                        address,
+                    ))
+                });
+                upstream
+                    .send_to(&reply.build().pack().unwrap(), peer)
+                    .unwrap();
+            }
+        });
+        let query = Message::new_query()
+            .question(Question {
+                domain: "google.com".parse().unwrap(),
+                qtype: rr::Type::A,
+                qclass: rr::Class::In,
+            })
+            .question(Question {
+                domain: "example.com".parse().unwrap(),
+                qtype: rr::Type::A,
+                qclass: rr::Class::In,
+            })
+            .build();
+        let addr = "127.0.0.1:2053".parse().unwrap();
+
+        let bytes = handler.handle(&query.pack().unwrap(), addr, addr).unwrap();
+        assert!(bytes.len() <= MAX_UDP_SIZE);
+        let response = Message::unpack(&bytes).unwrap();
+        assert_eq!(Truncation::Yes, response.header().tc);
+        assert_eq!(query.questions(), response.questions());
+        assert!(response.answers().is_empty());
+
+        // Over TCP based transports, the response is whole.
+        let bytes = handler
+            .handle_over(dnstap::Protocol::Dot, &query.pack().unwrap(), addr, addr)
+            .unwrap();
+        let response = Message::unpack(&bytes).unwrap();
+        assert_eq!(Truncation::No, response.header().tc);
+        assert_eq!(32, response.answers().len());
+        answering.join().unwrap();
+    }
+
+    #[test]
+    fn truncates_stream_upstream_replies() {
+        let pki = pki("handler_truncates");
+        // Some 3 KB of TXT records, which only a stream carries whole.
+        let address = upstream(&pki, |query| {
+            let name = &query.questions()[0].domain;
+            let text = [vec![255], vec![b'x'; 255]].concat();
+            (0..12)
+                .fold(Message::response(query), |reply, _| {
+                    reply.answer(Answer::new(
+                        name.clone(),
+                        rr::Type::Txt,
+                        rr::Class::In,
+                        300,
+                        text.clone(),
+                    ))
+                })
+                .build()
+        });
+        let query = Message::query("google.com".parse().unwrap(), rr::Type::Txt).build();
+        let addr = "127.0.0.1:2053".parse().unwrap();
+
+        for passthrough in [false, true] {
+            let resolver = Resolver::connect_tls(&address.to_string(), &pki.trusted()).unwrap();
+            let handler = match passthrough {
+                true => Handler::new(resolver).with_passthrough(),
+                false => Handler::new(resolver),
+            };
+            let bytes = handler.handle(&query.pack().unwrap(), addr, addr).unwrap();
+            assert!(bytes.len() <= MAX_UDP_SIZE);
+            let response = Message::unpack(&bytes).unwrap();
+            assert_eq!(query.get_id(), response.get_id());
+            assert_eq!(Truncation::Yes, response.header().tc);
+            assert!(response.answers().is_empty());
+        }
+    }
+
+    #[test]
+    fn update_proxied_verbatim() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect(upstream.local_addr().unwrap()).unwrap();
+        let handler = Handler::new(resolver).with_update(OpcodePolicy::Proxy);
+        let relay = std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (size, peer) = upstream.recv_from(&mut buf).unwrap();
+            let mut reply = buf[..size].to_vec();
+            reply[2] |= 0x80;
+            upstream.send_to(&reply, peer).unwrap();
+            buf[..size].to_vec()
+        });
+
+        let addr = "127.0.0.1:2053".parse().unwrap();
+        let response = handler.handle(UPDATE, addr, addr).unwrap();
+        assert_eq!(UPDATE, &relay.join().unwrap()[..]);
+        assert_eq!(UPDATE[2] | 0x80, response[2]);
+        assert_eq!(UPDATE[3..], response[3..]);
+    }
+
+    #[test]
+    fn passthrough_relays_raw_bytes() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect(upstream.local_addr().unwrap()).unwrap();
+        let handler = Handler::new(resolver).with_passthrough();
+        // Query of type 99 with an OPT record, answered with a type 99 record and AD set.
+        let query = b"\x04\xD2\x01\x20\x00\x01\x00\x00\x00\x00\x00\x01\x06google\x03com\x00\x00\x63\x00\x01\x00\x00\x29\x04\xD0\x00\x00\x00\x00\x00\x00";
+        let answer = b"\xC0\x0C\x00\x63\x00\x01\x00\x00\x01\x2C\x00\x03abc";
+        let relay = std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (size, peer) = upstream.recv_from(&mut buf).unwrap();
+            let forwarded = buf[..size].to_vec();
+            let mut reply = forwarded.clone();
+            reply[2..4].copy_from_slice(b"\x81\xA0");
+            reply[7] = 1;
+            reply.extend_from_slice(answer);
+            upstream.send_to(&reply, peer).unwrap();
+            forwarded
+        });
+
+        let addr = "127.0.0.1:2053".parse().unwrap();
+        let response = handler.handle(query, addr, addr).unwrap();
+        let forwarded = relay.join().unwrap();
+        assert_eq!(query[2..], forwarded[2..]);
+        assert_eq!(b"\x04\xD2\x81\xA0", &response[..4]);
+        assert_eq!(answer, &response[response.len() - answer.len()..]);
+    }
+
+    #[test]
+    fn passthrough_failure_is_server_failure() {
+        let (handler, _upstream) = unanswered();
+        let handler = handler.with_passthrough();
+        let query = Message::query("google.com".parse().unwrap(), rr::Type::A).build();
+
+        let response = handle(&handler, &query.pack().unwrap());
+        assert_eq!(query.get_id(), response.get_id());
+        assert_eq!(ResponseCode::ServerFailure, response.header().rcode);
+        assert_eq!(query.questions(), response.questions());
+    }
+
+    #[test]
+    fn unsupported_type_is_not_implemented() {
+        let (handler, _upstream) = unanswered();
+        // google.com of type 99 (SPF).
+        let raw = b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com\x00\x00\x63\x00\x01";
+
+        let response = handle(&handler, raw);
+        assert_eq!(1234, response.get_id());
+        assert_eq!(ResponseCode::NotImplemented, response.header().rcode);
+    }
+
+    /// example.com, with www holding one address and big 32 of them.
+    fn zone() -> Arc<Zone> {
+        let records = "example.com. 300 IN SOA ns.example.com. admin.example.com. 1 2 3 4 60
+            www.example.com. 300 IN A 192.0.2.1"
+            .lines()
+            .map(str::to_string)
+            .chain((0..32).map(|i| format!("big.example.com. 300 IN A 192.0.2.{i}")))
+            .map(|record| record.parse().unwrap())
+            .collect();
+        Arc::new(Zone::new("example.com".parse().unwrap(), records).unwrap())
+    }
+
+    #[test]
+    fn answers_from_zones() {
+        let (handler, _upstream) = unanswered();
+        let handler = handler.with_zone(zone());
+
+        let query = Message::query("WWW.example.com".parse().unwrap(), rr::Type::A).build();
+        let response = handle(&handler, &query.pack().unwrap());
+        assert_eq!(AuthoritativeAnswer::Yes, response.header().aa);
+        assert_eq!(
+            "www.example.com. 300 IN A 192.0.2.1",
+            response.answers()[0].to_string()
+        );
+
+        // Only names outside of the zone reach the upstream, which never answers.
+        let query = Message::query("example.org".parse().unwrap(), rr::Type::A).build();
+        let response = handle(&handler, &query.pack().unwrap());
+        assert_eq!(ResponseCode::ServerFailure, response.header().rcode);
+
+        let handler = handler.with_passthrough();
+        let query = Message::query("missing.example.com".parse().unwrap(), rr::Type::A).build();
+        let response = handle(&handler, &query.pack().unwrap());
+        assert_eq!(ResponseCode::NameError, response.header().rcode);
+    }
+
+    #[test]
+    fn answers_unsupported_types_from_zones() {
+        for passthrough in [false, true] {
+            let (handler, upstream) = unanswered();
+            let mut handler = handler.with_zone(zone());
+            if passthrough {
+                handler = handler.with_passthrough();
+            }
+            let addr = "127.0.0.1:2053".parse().unwrap();
+
+            // www.example.com and missing.example.com of type 65 (HTTPS).
+            let www = b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x03WWW\x07example\x03com\x00\x00\x41\x00\x01";
+            let missing = b"\x04\xD3\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07missing\x07example\x03com\x00\x00\x41\x00\x01";
+            for (raw, rcode) in [
+                (&www[..], ResponseCode::NoError),
+                (&missing[..], ResponseCode::NameError),
+            ] {
+                let response = handler.handle(raw, addr, addr).unwrap();
+                let header = Header::unpack(&response).unwrap();
+                assert_eq!(Header::unpack_id(raw).unwrap(), header.id);
+                assert_eq!(rcode, header.rcode);
+                assert_eq!(AuthoritativeAnswer::Yes, header.aa);
+                assert_eq!((1, 0, 1), (header.qdcount, header.ancount, header.nscount));
+                assert_eq!(raw[DNS_HEADER_SIZE..], response[DNS_HEADER_SIZE..raw.len()]);
+            }
+
+            // Nothing reached the upstream.
+            upstream.set_nonblocking(true).unwrap();
+            assert!(upstream.recv(&mut [0; 512]).is_err());
+        }
+    }
+
+    #[test]
+    fn refuses_zone_transfers() {
+        let (handler, upstream) = unanswered();
+        let handler = handler.with_zone(zone());
+        let addr = "127.0.0.1:2053".parse().unwrap();
+
+        // AXFR and IXFR of example.com, with EDNS.
+        for qtype in [b"\xFC", b"\xFB"] {
+            let raw = [
+                &b"\x04\xD2\x00\x00\x00\x01\x00\x00\x00\x00\x00\x01\x07example\x03com\x00\x00"[..],
+                qtype,
+                b"\x00\x01\x00\x00\x29\x04\xD0\x00\x00\x00\x00\x00\x00",
+            ]
+            .concat();
+            let end = raw.len() - 11;
+            let bytes = handler.handle(&raw, addr, addr).unwrap();
+            let header = Header::unpack(&bytes).unwrap();
+            assert_eq!(ResponseCode::Refused, header.rcode);
+            assert_eq!(raw[DNS_HEADER_SIZE..end], bytes[DNS_HEADER_SIZE..end]);
+            let edns = Edns::unpack(&bytes, &mut end.clone()).unwrap();
+            let errors = edns.extended_errors().collect::<Vec<_>>();
+            assert_eq!(InfoCode::Prohibited, errors[0].info_code);
+            assert_eq!(
+                "Query refused: zone transfer of `example.com.`",
+                errors[0].extra_text
+            );
+        }
+
+        // Nothing reached the upstream.
+        upstream.set_nonblocking(true).unwrap();
+        assert!(upstream.recv(&mut [0; 512]).is_err());
+    }
+
+    #[test]
+    fn truncates_udp_answers_from_zones() {
+        let (handler, _upstream) = unanswered();
+        let handler = handler.with_zone(zone());
+        let addr = "127.0.0.1:2053".parse().unwrap();
+
+        let query = Message::query("big.example.com".parse().unwrap(), rr::Type::A).build();
+        let bytes = handler.handle(&query.pack().unwrap(), addr, addr).unwrap();
+        assert!(bytes.len() <= MAX_UDP_SIZE);
+        let response = Message::unpack(&bytes).unwrap();
+        assert_eq!(Truncation::Yes, response.header().tc);
+        assert!(response.answers().is_empty());
+
+        // Within the payload size the client advertises, the answer is whole.
+        let query = Message::query("big.example.com".parse().unwrap(), rr::Type::A)
+            .edns(Edns::default())
+            .build();
+        let response = handle(&handler, &query.pack().unwrap());
+        assert_eq!(Truncation::No, response.header().tc);
+        assert_eq!(32, response.answers().len());
+
+        // Over TCP based transports, the answer is whole whatever the client advertises.
+        let query = Message::query("big.example.com".parse().unwrap(), rr::Type::A).build();
+        let bytes = handler
+            .handle_over(dnstap::Protocol::Dot, &query.pack().unwrap(), addr, addr)
+            .unwrap();
+        assert_eq!(32, Message::unpack(&bytes).unwrap().answers().len());
+    }
+
+    #[test]
+    fn format_error_without_header() {
+        let (handler, _upstream) = unanswered();
+        let response = handle(&handler, b"\x04\xD2\x01");
+        assert_eq!(1234, response.get_id());
+        assert_eq!(ResponseCode::FormatError, response.header().rcode);
+        assert_eq!(0, response.header().qdcount);
+    }
+}
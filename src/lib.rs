@@ -0,0 +1,34 @@
+//! DNS message parsing and packing, and a forwarding proxy built on top of it.
+//!
+//! ```
+//! use dns_starter_rust::{rr, Message};
+//!
+//! let raw = b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com\x00\x00\x01\x00\x01";
+//! let query = Message::unpack(raw).unwrap();
+//! assert_eq!(1234, query.get_id());
+//! assert_eq!("google.com", query.questions()[0].domain.to_string());
+//! assert_eq!(rr::Type::A, query.questions()[0].qtype);
+//! assert_eq!(&raw[..], &query.pack().unwrap()[..]);
+//! ```
+
+pub mod certs;
+pub mod config;
+pub mod dnstap;
+pub mod doh;
+pub mod doq;
+pub mod dot;
+pub mod errors;
+pub mod handler;
+pub mod message;
+pub mod metrics;
+pub mod tcp;
+pub mod zone;
+
+pub use crate::{
+    errors::DnsError,
+    handler::Handler,
+    message::{
+        answer::Answer, builder::MessageBuilder, edns::Edns, header::Header, labels::Labels,
+        question::Question, rdata::RData, resolver::Resolver, rr, Message,
+    },
+};
@@ -1,64 +1,141 @@
 use std::{
-    fmt::Display,
-    net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::Arc,
 };
 
-use crate::{
-    errors::DnsError,
-    message::{header::Header, resolver::Resolver, Message},
+use anyhow::Result;
+use dns_starter_rust::{
+    certs::Certificate,
+    config::{Config, DnstapTarget},
+    dnstap::Dnstap,
+    doh::DohServer,
+    doq::DoqListener,
+    dot::DotListener,
+    message::{header::Header, Message},
+    metrics::Metrics,
+    zone::Zone,
+    Handler, Resolver,
 };
-use anyhow::{anyhow, bail, Result};
-
-mod errors;
-mod message;
 
 fn main() -> Result<()> {
     let addr = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 2053);
     let udp_socket = UdpSocket::bind(addr)?;
     println!("Successfully bound to address: {:?}", addr);
 
-    let mut args = std::env::args();
-    let dns_server = if args.nth(1).is_some_and(is_resolver_flag) {
-        args.next()
-            .ok_or(anyhow!(DnsError::ResolverNotSpecified))
-            .and_then(Resolver::connect)?
-    } else {
-        bail!(DnsError::ResolverNotSpecified)
+    let config = Config::from_args(std::env::args().skip(1))?;
+    let dnstap = match &config.dnstap {
+        Some(DnstapTarget::File(path)) => Some(Arc::new(Dnstap::create(path)?)),
+        Some(DnstapTarget::Socket(path)) => Some(Arc::new(Dnstap::connect(path)?)),
+        None => None,
+    };
+    let metrics = match &config.metrics {
+        Some(address) => {
+            let metrics = Arc::new(Metrics::new()?);
+            let bound = metrics.clone().serve(address)?;
+            println!("Serving metrics on: {:?}", bound);
+            Some(metrics)
+        }
+        None => None,
+    };
+    let zones = config
+        .zones
+        .iter()
+        .map(|(origin, path)| Ok(Arc::new(Zone::load(path, origin.parse()?)?)))
+        .collect::<Result<Vec<_>>>()?;
+    for zone in &zones {
+        println!("Serving zone: {}", zone.origin().to_fqdn());
+    }
+    let handler = |resolver: &str| -> Result<Arc<Handler>> {
+        let mut handler = Handler::new(connect(&config, resolver)?)
+            .with_notify(config.notify)
+            .with_update(config.update);
+        if config.passthrough {
+            handler = handler.with_passthrough();
+        }
+        if let Some(dnstap) = &dnstap {
+            handler = handler.with_dnstap(dnstap.clone());
+        }
+        if let Some(metrics) = &metrics {
+            handler = handler.with_metrics(metrics.clone());
+        }
+        for zone in &zones {
+            handler = handler.with_zone(zone.clone());
+        }
+        Ok(Arc::new(handler))
     };
 
+    let main = handler(&config.resolver)?;
+    let certificate = match (&config.cert, &config.key) {
+        (Some(cert), Some(key)) => Some(Arc::new(Certificate::load(cert, key)?)),
+        _ => None,
+    };
+    if let (Some(address), Some(certificate)) = (&config.dot, &certificate) {
+        let bound = DotListener::new(main.clone(), certificate.clone())?
+            .with_idle_timeout(config.idle_timeout)
+            .serve(address)?;
+        println!("Serving DNS over TLS on: {:?}", bound);
+    }
+    if let Some(address) = &config.doh {
+        let mut server = DohServer::new(main.clone());
+        for (path, resolver) in &config.doh_profiles {
+            server = server.with_profile(path, handler(resolver)?);
+        }
+        if let Some(certificate) = &certificate {
+            server = server.with_tls(certificate.clone())?;
+        }
+        let bound = server.serve(address)?;
+        println!("Serving DNS over HTTPS on: {:?}", bound);
+    }
+    if let (Some(address), Some(certificate)) = (&config.doq, &certificate) {
+        let mut listener = DoqListener::new(main.clone(), certificate.clone())
+            .with_idle_timeout(config.idle_timeout);
+        if config.zero_rtt {
+            listener = listener.with_zero_rtt();
+        }
+        let bound = listener.serve(address)?;
+        println!("Serving DNS over QUIC on: {:?}", bound);
+    }
+
     let mut buf = [0u8; 512];
     loop {
-        let (size, source) = udp_socket.recv_from(&mut buf)?;
-        match Message::unpack(&buf[0..size]) {
-            Ok(query) => {
-                let id = query.get_id();
-                let bytes = dns_server
-                    .resolve(query)
-                    .and_then(|res| res.pack())
-                    .or_else(pack_server_failure(id))?;
-
-                udp_socket.send_to(&bytes, source)?;
-            }
+        let (size, source) = match udp_socket.recv_from(&mut buf) {
+            Ok(received) => received,
             Err(e) => {
-                println!("Cannot unpack message: {}", e);
-                let id = Header::unpack_id(&buf[0..size]).unwrap_or_default();
-                let msg = Message::new_client_err().with_id(id);
-                udp_socket.send_to(&msg.pack()?, source)?;
+                eprintln!("Cannot receive query: {}", e);
+                continue;
             }
         };
+        let query = &buf[0..size];
+        let bytes = main
+            .handle(query, source, SocketAddr::V4(addr))
+            .or_else(pack_server_failure(query));
+        let sent = bytes.and_then(|bytes| Ok(udp_socket.send_to(&bytes, source)?));
+        if let Err(e) = sent {
+            eprintln!("Cannot answer {}: {}", source, e);
+        }
     }
 }
 
-fn is_resolver_flag(flag: String) -> bool {
-    flag == "-r" || flag == "--resolver"
-}
-
-fn pack_server_failure<E>(id: u16) -> impl FnOnce(E) -> Result<Vec<u8>>
-where
-    E: Display,
-{
-    move |err: E| {
-        eprintln!("Cannot resolve query: {}", err);
+/// Falls back to an empty `SERVFAIL` with the ID of `query` when it could not be handled.
+fn pack_server_failure(query: &[u8]) -> impl FnOnce(anyhow::Error) -> Result<Vec<u8>> + '_ {
+    move |err| {
+        eprintln!("Cannot handle query: {}", err);
+        let id = Header::unpack_id(query).unwrap_or_default();
         Message::new_server_err().with_id(id).pack()
     }
 }
+
+/// Connects to `resolver`, over TLS, HTTPS or QUIC given a `tls://`, `https://` or `quic://`
+/// address.
+fn connect(config: &Config, resolver: &str) -> Result<Resolver> {
+    let resolver = if let Some(address) = resolver.strip_prefix("tls://") {
+        Resolver::connect_tls(address, &config.tls)?
+    } else if let Some(address) = resolver.strip_prefix("quic://") {
+        Resolver::connect_quic(address, &config.tls, config.zero_rtt)?
+    } else if resolver.starts_with("https://") {
+        Resolver::connect_https(resolver, &config.tls, config.doh_method)?
+    } else {
+        Resolver::connect(resolver)?
+    };
+    Ok(resolver.with_multi_question(config.multi_question))
+}
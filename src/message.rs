@@ -1,57 +1,119 @@
 use anyhow::Result;
+use byteorder::{BigEndian, ByteOrder};
 use packed_struct::prelude::*;
 
 use self::{
     answer::Answer,
-    header::{Header, Indicator, ResponseCode, DNS_HEADER_SIZE},
+    edns::Edns,
+    header::{Header, ResponseCode, Truncation, DNS_HEADER_SIZE},
+    labels::Labels,
     question::Question,
 };
 
-mod answer;
+pub mod answer;
+pub mod builder;
+pub mod edns;
 pub mod header;
-mod labels;
-mod question;
+pub mod json;
+pub mod labels;
+pub(crate) mod presentation;
+pub mod question;
+pub mod rdata;
 pub mod resolver;
-mod rr;
+pub mod rr;
 
+/// Largest message a client without EDNS takes over UDP (RFC 1035 section 4.2.1).
+pub const MAX_UDP_SIZE: usize = 512;
+
+/// Offset of RDLENGTH past the owner name of a record.
+const RDLENGTH: usize = 8;
+
+/// DNS message: a header followed by the question, answer, authority and additional sections.
+///
+/// The OPT pseudo-record is kept apart from the additional section as `edns`. Header counts are
+/// derived from the sections when packing, and so is the extended RCODE of the OPT record: the
+/// header holds the whole 12 bit response code.
 #[derive(Debug, Clone, Default)]
 pub struct Message {
     header: Header,
     questions: Vec<Question>,
     answers: Vec<Answer>,
+    authorities: Vec<Answer>,
+    additionals: Vec<Answer>,
+    edns: Option<Edns>,
 }
 
 impl Message {
+    /// Parses a message from its wire format.
     pub fn unpack(query: &[u8]) -> Result<Message> {
-        let header = Header::unpack(query)?;
+        let mut header = Header::unpack(query)?;
 
         let mut ptr = DNS_HEADER_SIZE;
         let questions = (0..header.qdcount)
             .map(|_| Question::unpack(query, &mut ptr))
             .collect::<Result<Vec<_>>>()?;
+        let answers = (0..header.ancount)
+            .map(|_| Answer::unpack(query, &mut ptr))
+            .collect::<Result<Vec<_>>>()?;
+        let authorities = (0..header.nscount)
+            .map(|_| Answer::unpack(query, &mut ptr))
+            .collect::<Result<Vec<_>>>()?;
 
-        let answers = match header.qr {
-            Indicator::Query => Vec::with_capacity(questions.len()),
-            Indicator::Response => (0..header.ancount)
-                .map(|_| Answer::unpack(query, &mut ptr))
-                .collect::<Result<Vec<_>>>()?,
-        };
+        let mut additionals = Vec::new();
+        let mut edns = None;
+        for _ in 0..header.arcount {
+            if Edns::test(query, ptr) {
+                edns = Some(Edns::unpack(query, &mut ptr)?);
+            } else {
+                additionals.push(Answer::unpack(query, &mut ptr)?);
+            }
+        }
+
+        if let Some(edns) = &edns {
+            header.rcode = ResponseCode::from_parts(header.rcode, edns.ext_rcode);
+        }
 
         Ok(Message {
             header,
             questions,
             answers,
+            authorities,
+            additionals,
+            edns,
         })
     }
 
-    pub fn pack(&self) -> Result<Vec<u8>> {
-        let len = DNS_HEADER_SIZE
-            + self.questions.iter().map(Question::len).sum::<usize>()
-            + self.answers.iter().map(Answer::len).sum::<usize>();
+    /// Salvages what it can from a message `unpack` rejects: the header, the question section when
+    /// it parses as a whole and the OPT record when the records before it can be skipped. `None`
+    /// if not even the header parses.
+    pub fn unpack_partial(query: &[u8]) -> Option<Message> {
+        let header = Header::unpack(query).ok()?;
 
-        let mut buf = vec![0u8; len];
+        let mut ptr = DNS_HEADER_SIZE;
+        let questions = (0..header.qdcount)
+            .map(|_| Question::unpack(query, &mut ptr))
+            .collect::<Result<Vec<_>>>()
+            .unwrap_or_default();
+
+        Some(Message {
+            header,
+            questions,
+            edns: Self::opt(query, &header),
+            ..Default::default()
+        })
+    }
+
+    /// Serializes the message into its wire format, without name compression.
+    pub fn pack(&self) -> Result<Vec<u8>> {
+        let records = || {
+            self.answers
+                .iter()
+                .chain(&self.authorities)
+                .chain(&self.additionals)
+        };
+        let mut buf = vec![0u8; self.len()];
         let mut next = DNS_HEADER_SIZE;
-        self.header.pack_to_slice(&mut buf[..next])?;
+        self.counted_header().pack_to_slice(&mut buf[..next])?;
         let mut wrote = next;
 
         for question in &self.questions {
@@ -59,20 +121,136 @@ impl Message {
             question.pack(&mut buf[wrote..wrote + next])?;
             wrote += next;
         }
-        for answer in &self.answers {
-            next = answer.len();
-            answer.pack(&mut buf[wrote..wrote + next])?;
+        for record in records() {
+            next = record.len();
+            record.pack(&mut buf[wrote..wrote + next])?;
             wrote += next;
         }
+        if let Some(edns) = &self.edns {
+            let edns = Edns {
+                ext_rcode: self.header.rcode.extended_bits(),
+                ..edns.clone()
+            };
+            edns.pack(&mut buf[wrote..])?;
+        }
         Ok(buf)
     }
 
+    /// Size of the wire format of the message.
+    fn len(&self) -> usize {
+        DNS_HEADER_SIZE
+            + self.questions.iter().map(Question::len).sum::<usize>()
+            + self
+                .answers
+                .iter()
+                .chain(&self.authorities)
+                .chain(&self.additionals)
+                .map(Answer::len)
+                .sum::<usize>()
+            + self.edns.as_ref().map_or(0, Edns::len)
+    }
+
+    /// Largest response the sender of the message takes over UDP: its EDNS payload size, or 512
+    /// bytes without EDNS or when it advertises less (RFC 6891 section 6.2.5).
+    pub fn udp_limit(&self) -> usize {
+        self.edns.as_ref().map_or(MAX_UDP_SIZE, |edns| {
+            usize::from(edns.udp_payload_size).max(MAX_UDP_SIZE)
+        })
+    }
+
+    /// Makes the message fit in `limit` bytes: the additional records are dropped first, then the
+    /// answer and authority records, setting TC for the client to retry over TCP (RFC 2181
+    /// section 9). EDNS is kept.
+    pub fn truncate(&mut self, limit: usize) {
+        if self.len() <= limit {
+            return;
+        }
+        self.additionals.clear();
+        if self.len() > limit {
+            self.answers.clear();
+            self.authorities.clear();
+            self.header.tc = Truncation::Yes;
+        }
+    }
+
+    /// [`Message::udp_limit`] of a message in wire format, only the records up to its OPT record
+    /// have to parse.
+    pub fn udp_limit_of(wire: &[u8]) -> usize {
+        Self::unpack_partial(wire).unwrap_or_default().udp_limit()
+    }
+
+    /// OPT record of `wire`, found by skipping the records before it.
+    fn opt(wire: &[u8], header: &Header) -> Option<Edns> {
+        let mut ptr = Self::questions_end(wire, header)?;
+        let records = [header.ancount, header.nscount, header.arcount];
+        for _ in 0..records.into_iter().map(usize::from).sum::<usize>() {
+            if Edns::test(wire, ptr) {
+                return Edns::unpack(wire, &mut ptr).ok();
+            }
+            Labels::unpack(wire, &mut ptr).ok()?;
+            let length = wire.get(ptr + RDLENGTH..ptr + RDLENGTH + 2)?;
+            ptr += RDLENGTH + 2 + usize::from(BigEndian::read_u16(length));
+        }
+        None
+    }
+
+    /// Offset of the end of the question section of `wire`.
+    fn questions_end(wire: &[u8], header: &Header) -> Option<usize> {
+        let mut ptr = DNS_HEADER_SIZE;
+        for _ in 0..header.qdcount {
+            Question::unpack_raw(wire, &mut ptr).ok()?;
+        }
+        Some(ptr)
+    }
+
+    /// [`Message::truncate`] for a message in wire format. Messages the parser rejects lose all
+    /// their records, OPT included, keeping only the header and question section.
+    pub fn truncate_wire(wire: Vec<u8>, limit: usize) -> Vec<u8> {
+        if wire.len() <= limit {
+            return wire;
+        }
+        if let Ok(mut message) = Message::unpack(&wire) {
+            message.truncate(limit);
+            if let Ok(packed) = message.pack() {
+                return packed;
+            }
+        }
+        let Ok(mut header) = Header::unpack(&wire) else {
+            return wire;
+        };
+        let end = Self::questions_end(&wire, &header).unwrap_or(DNS_HEADER_SIZE);
+        if end == DNS_HEADER_SIZE {
+            header.qdcount = 0;
+        }
+        header.ancount = 0;
+        header.nscount = 0;
+        header.arcount = 0;
+        header.tc = Truncation::Yes;
+        let mut truncated = wire[..end].to_vec();
+        match header.pack_to_slice(&mut truncated[..DNS_HEADER_SIZE]) {
+            Ok(()) => truncated,
+            Err(_) => wire,
+        }
+    }
+
+    /// Header with section counts matching the sections actually held.
+    fn counted_header(&self) -> Header {
+        let mut header = self.header;
+        header.qdcount = self.questions.len() as u16;
+        header.ancount = self.answers.len() as u16;
+        header.nscount = self.authorities.len() as u16;
+        header.arcount = (self.additionals.len() + self.edns.iter().len()) as u16;
+        header
+    }
+
+    /// Empty response with `FORMERR`.
     pub fn new_client_err() -> Self {
         let mut msg = Self::default();
         msg.header.rcode = ResponseCode::FormatError;
         msg
     }
 
+    /// Empty response with `SERVFAIL`.
     pub fn new_server_err() -> Self {
         let mut msg = Self::default();
         msg.header.rcode = ResponseCode::ServerFailure;
@@ -87,4 +265,134 @@ impl Message {
     pub fn get_id(&self) -> u16 {
         self.header.id
     }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn questions(&self) -> &[Question] {
+        &self.questions
+    }
+
+    pub fn answers(&self) -> &[Answer] {
+        &self.answers
+    }
+
+    pub fn authorities(&self) -> &[Answer] {
+        &self.authorities
+    }
+
+    /// Additional records, without the OPT pseudo-record.
+    pub fn additionals(&self) -> &[Answer] {
+        &self.additionals
+    }
+
+    pub fn edns(&self) -> Option<&Edns> {
+        self.edns.as_ref()
+    }
+
+    /// Smallest TTL of the records, bounding how long the message may be cached.
+    pub fn min_ttl(&self) -> Option<u32> {
+        self.answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+            .map(Answer::ttl)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{header::RecursionDesired, *};
+
+    #[test]
+    fn truncate() {
+        let record = |i| {
+            Answer::new(
+                "google.com".parse().unwrap(),
+                rr::Type::A,
+                rr::Class::In,
+                300,
+                vec![192, 0, 2, i],
+            )
+        };
+        let query = Message::query("google.com".parse().unwrap(), rr::Type::A)
+            .edns(Edns::default())
+            .build();
+        let response = (0..8)
+            .fold(Message::response(&query), |response, i| {
+                response.answer(record(i)).additional(record(i + 8))
+            })
+            .edns(Edns::default())
+            .build();
+        assert_eq!(MAX_UDP_SIZE, Message::default().udp_limit());
+        assert_eq!(1232, query.udp_limit());
+
+        // Additional records go first, without TC.
+        let mut truncated = response.clone();
+        truncated.truncate(300);
+        assert_eq!(8, truncated.answers().len());
+        assert!(truncated.additionals().is_empty());
+        assert_eq!(Truncation::No, truncated.header().tc);
+
+        let mut truncated = response.clone();
+        truncated.truncate(100);
+        assert!(truncated.answers().is_empty());
+        assert_eq!(Truncation::Yes, truncated.header().tc);
+        assert!(truncated.edns().is_some());
+        assert!(truncated.pack().unwrap().len() <= 100);
+    }
+
+    #[test]
+    fn truncate_wire() {
+        // google.com A, answered with a record of a private type holding 1000 bytes, and an OPT
+        // record advertising 600 bytes.
+        let wire = [
+            &b"\x04\xD2\x81\x00\x00\x01\x00\x01\x00\x00\x00\x01\x06google\x03com\x00\x00\x01\x00\x01"[..],
+            b"\xC0\x0C\xFF\x00\x00\x01\x00\x00\x01\x2C\x03\xE8",
+            &[0; 1000],
+            b"\x00\x00\x29\x02\x58\x00\x00\x00\x00\x00\x00",
+        ]
+        .concat();
+        assert!(Message::unpack(&wire).is_err());
+        assert_eq!(600, Message::udp_limit_of(&wire));
+        assert_eq!(
+            MAX_UDP_SIZE,
+            Message::udp_limit_of(&wire[..wire.len() - 11])
+        );
+
+        assert_eq!(wire, Message::truncate_wire(wire.clone(), wire.len()));
+        let truncated = Message::unpack(&Message::truncate_wire(wire, 600)).unwrap();
+        assert_eq!(1234, truncated.get_id());
+        assert_eq!(Truncation::Yes, truncated.header().tc);
+        assert_eq!("google.com", truncated.questions()[0].domain.to_string());
+        assert!(truncated.answers().is_empty());
+    }
+
+    #[test]
+    fn unpack_partial() {
+        // One question followed by an answer cut short.
+        let raw = b"\x04\xD2\x01\x00\x00\x01\x00\x01\x00\x00\x00\x00\x06google\x03com\x00\x00\x01\x00\x01\xC0\x0C\x00";
+        assert!(Message::unpack(raw).is_err());
+
+        let partial = Message::unpack_partial(raw).unwrap();
+        assert_eq!(1234, partial.get_id());
+        assert_eq!(RecursionDesired::Yes, partial.header().rd);
+        assert_eq!("google.com", partial.questions()[0].domain.to_string());
+        assert!(partial.answers().is_empty());
+    }
+
+    #[test]
+    fn unpack_partial_bad_question() {
+        let raw = b"\x04\xD2\x01\x00\x00\x02\x00\x00\x00\x00\x00\x00\x06google\x03com\x00\x00\x01\x00\x01\x03foo";
+        let partial = Message::unpack_partial(raw).unwrap();
+        assert_eq!(1234, partial.get_id());
+        assert!(partial.questions().is_empty());
+    }
+
+    #[test]
+    fn unpack_partial_no_header() {
+        assert!(Message::unpack_partial(b"\x04\xD2\x01").is_none());
+    }
 }
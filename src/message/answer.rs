@@ -1,12 +1,15 @@
-use std::{io::Cursor, mem::size_of};
+use std::{fmt::Display, io::Cursor, mem::size_of};
 
 use crate::errors::DnsError;
 
-use super::{labels::Labels, rr};
+use super::{labels::Labels, rdata::RData, rr};
 
 use anyhow::{ensure, Result};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
+/// Resource record, as carried in the answer section.
+///
+/// `data` is kept in wire format, its interpretation depends on `atype`.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Answer {
     name: Labels,
@@ -21,13 +24,50 @@ const METADATA_SIZE: usize =
     size_of::<rr::Type>() + size_of::<rr::Class>() + size_of::<u32>() + size_of::<u16>();
 
 impl Answer {
-    pub fn unpack(buf: &[u8], ptr: &mut usize) -> Result<Self> {
+    pub fn new(name: Labels, atype: rr::Type, aclass: rr::Class, ttl: u32, data: Vec<u8>) -> Self {
+        Answer {
+            name,
+            atype,
+            aclass,
+            ttl,
+            length: data.len() as u16,
+            data,
+        }
+    }
+
+    pub fn name(&self) -> &Labels {
+        &self.name
+    }
+
+    pub fn atype(&self) -> rr::Type {
+        self.atype
+    }
+
+    pub fn aclass(&self) -> rr::Class {
+        self.aclass
+    }
+
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    /// Raw RDATA of the record.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// RDATA of the record, decoded according to its type.
+    pub fn rdata(&self) -> Result<RData> {
+        RData::unpack(&self.data, 0, self.data.len(), self.atype)
+    }
+
+    pub(crate) fn unpack(buf: &[u8], ptr: &mut usize) -> Result<Self> {
         let name = Labels::unpack(buf, ptr)?;
 
         let mut metadata = Cursor::new(vec![0u8; METADATA_SIZE]);
         let mut at = *ptr;
         ensure!(
-            buf.len() > at + METADATA_SIZE,
+            buf.len() >= at + METADATA_SIZE,
             DnsError::BufLenSmall {
                 min: at + METADATA_SIZE,
                 act: buf.len()
@@ -41,9 +81,8 @@ impl Answer {
         let ttl = metadata.read_u32::<BigEndian>()?;
         let length = metadata.read_u16::<BigEndian>()?;
         at += METADATA_SIZE;
-
-        let mut data = Vec::with_capacity(length as usize);
-        Vec::extend_from_slice(&mut data, &buf[at..at + length as usize]);
+        // Decoding expands compressed names, which would point nowhere once repacked.
+        let data = RData::unpack(buf, at, length as usize, atype)?.pack()?;
         *ptr = at + length as usize;
 
         Ok(Answer {
@@ -51,12 +90,12 @@ impl Answer {
             atype,
             aclass,
             ttl,
-            length,
+            length: data.len() as u16,
             data,
         })
     }
 
-    pub fn pack(&self, buf: &mut [u8]) -> Result<()> {
+    pub(crate) fn pack(&self, buf: &mut [u8]) -> Result<()> {
         ensure!(
             buf.len() == self.len(),
             DnsError::BufLenNotEq {
@@ -76,20 +115,38 @@ impl Answer {
         cursor.write_u16::<BigEndian>(self.aclass.into())?;
         cursor.write_u32::<BigEndian>(self.ttl)?;
         cursor.write_u16::<BigEndian>(self.length)?;
-        (&mut buf[wrote..wrote + next]).copy_from_slice(cursor.get_ref());
+        buf[wrote..wrote + next].copy_from_slice(cursor.get_ref());
         wrote += next;
 
         next = self.data.len();
-        (&mut buf[wrote..wrote + next]).copy_from_slice(&self.data);
+        buf[wrote..wrote + next].copy_from_slice(&self.data);
 
         Ok(())
     }
 
-    pub fn len(&self) -> usize {
+    pub(crate) fn len(&self) -> usize {
         self.name.len() + METADATA_SIZE + self.data.len()
     }
 }
 
+/// Presentation format: `google.com. 300 IN A 8.8.8.8`.
+impl Display for Answer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rdata = self
+            .rdata()
+            .unwrap_or_else(|_| RData::Unknown(self.data.clone()));
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.name.to_fqdn(),
+            self.ttl,
+            self.aclass,
+            self.atype,
+            rdata
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::message::question::Question;
@@ -112,7 +169,7 @@ mod tests {
         };
 
         let mut expect = Vec::from_iter(raw.iter().cloned());
-        expect.extend_from_slice(&vec![0xFF, 0xDD, 0xBB, 0xAA, 0x00, 0x04]);
+        expect.extend_from_slice(&[0xFF, 0xDD, 0xBB, 0xAA, 0x00, 0x04]);
         expect.extend_from_slice(&data);
 
         let mut buf = vec![0u8; answer.len()];
@@ -0,0 +1,222 @@
+use super::{
+    answer::Answer,
+    edns::Edns,
+    header::{
+        AuthenticData, AuthoritativeAnswer, CheckingDisabled, Indicator, RecursionAvailable,
+        RecursionDesired, ResponseCode, Truncation,
+    },
+    labels::Labels,
+    question::Question,
+    rr, Message,
+};
+
+/// Fluent construction of a [`Message`], see [`Message::query`] and [`Message::response`].
+///
+/// Header counts are kept in line with the sections, so they never need to be set by hand.
+///
+/// ```
+/// use dns_starter_rust::{rr, Message};
+///
+/// let query = Message::query("google.com".parse().unwrap(), rr::Type::A)
+///     .id(1234)
+///     .recursion_desired()
+///     .build();
+/// assert_eq!(1, query.header().qdcount);
+/// assert_eq!(
+///     b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com\x00\x00\x01\x00\x01",
+///     &query.pack().unwrap()[..],
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct MessageBuilder(Message);
+
+impl Message {
+    /// Starts a query for `name` of type `qtype` in class `IN`, with a random ID.
+    pub fn query(name: Labels, qtype: rr::Type) -> MessageBuilder {
+        Message::new_query().question(Question {
+            domain: name,
+            qtype,
+            qclass: rr::Class::In,
+        })
+    }
+
+    /// Starts a query without questions, with a random ID.
+    pub fn new_query() -> MessageBuilder {
+        let mut msg = Message::default();
+        msg.header.id = rand::random();
+        msg.header.qr = Indicator::Query;
+        MessageBuilder(msg)
+    }
+
+    /// Starts a response to `query`, echoing its ID, opcode, RD flag and questions.
+    pub fn response(query: &Message) -> MessageBuilder {
+        let mut msg = Message::default();
+        msg.header.id = query.header.id;
+        msg.header.qr = Indicator::Response;
+        msg.header.opcode = query.header.opcode;
+        msg.header.rd = query.header.rd;
+        msg.questions = query.questions.clone();
+        MessageBuilder(msg)
+    }
+}
+
+impl MessageBuilder {
+    pub fn id(mut self, id: u16) -> Self {
+        self.0.header.id = id;
+        self
+    }
+
+    pub fn question(mut self, question: Question) -> Self {
+        self.0.questions.push(question);
+        self
+    }
+
+    pub fn recursion_desired(mut self) -> Self {
+        self.0.header.rd = RecursionDesired::Yes;
+        self
+    }
+
+    pub fn recursion_available(mut self) -> Self {
+        self.0.header.ra = RecursionAvailable::Yes;
+        self
+    }
+
+    pub fn authoritative(mut self) -> Self {
+        self.0.header.aa = AuthoritativeAnswer::Yes;
+        self
+    }
+
+    pub fn authentic_data(mut self) -> Self {
+        self.0.header.ad = AuthenticData::Yes;
+        self
+    }
+
+    pub fn checking_disabled(mut self) -> Self {
+        self.0.header.cd = CheckingDisabled::Yes;
+        self
+    }
+
+    pub fn truncated(mut self) -> Self {
+        self.0.header.tc = Truncation::Yes;
+        self
+    }
+
+    /// Sets the response code, attaching an OPT record if it does not fit in the header.
+    pub fn rcode(mut self, rcode: ResponseCode) -> Self {
+        self.0.header.rcode = rcode;
+        if rcode.is_extended() && self.0.edns.is_none() {
+            self.0.edns = Some(Edns::default());
+        }
+        self
+    }
+
+    pub fn answer(mut self, record: Answer) -> Self {
+        self.0.answers.push(record);
+        self
+    }
+
+    pub fn authority(mut self, record: Answer) -> Self {
+        self.0.authorities.push(record);
+        self
+    }
+
+    pub fn additional(mut self, record: Answer) -> Self {
+        self.0.additionals.push(record);
+        self
+    }
+
+    /// Attaches an OPT pseudo-record, replacing any previous one. Its extended RCODE is ignored,
+    /// see [`MessageBuilder::rcode`].
+    pub fn edns(mut self, edns: Edns) -> Self {
+        self.0.edns = Some(edns);
+        self
+    }
+
+    pub fn build(self) -> Message {
+        let mut msg = self.0;
+        msg.header = msg.counted_header();
+        msg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, data: [u8; 4]) -> Answer {
+        Answer::new(
+            name.parse().unwrap(),
+            rr::Type::A,
+            rr::Class::In,
+            300,
+            data.to_vec(),
+        )
+    }
+
+    #[test]
+    fn query() {
+        let query = Message::query("google.com".parse().unwrap(), rr::Type::Aaaa)
+            .recursion_desired()
+            .edns(Edns::default())
+            .build();
+        assert_eq!(Indicator::Query, query.header().qr);
+        assert_eq!(RecursionDesired::Yes, query.header().rd);
+        assert_eq!(1, query.header().qdcount);
+        assert_eq!(1, query.header().arcount);
+
+        let unpacked = Message::unpack(&query.pack().unwrap()).unwrap();
+        assert_eq!(query.get_id(), unpacked.get_id());
+        assert_eq!(query.questions(), unpacked.questions());
+        assert_eq!(Some(&Edns::default()), unpacked.edns());
+    }
+
+    #[test]
+    fn response() {
+        let query = Message::query("google.com".parse().unwrap(), rr::Type::A)
+            .recursion_desired()
+            .build();
+        let response = Message::response(&query)
+            .recursion_available()
+            .answer(record("google.com", [8, 8, 8, 8]))
+            .answer(record("google.com", [8, 8, 4, 4]))
+            .authority(record("ns.google.com", [1, 1, 1, 1]))
+            .additional(record("ns.google.com", [1, 1, 1, 1]))
+            .edns(Edns::default())
+            .build();
+
+        let header = response.header();
+        assert_eq!(query.get_id(), header.id);
+        assert_eq!(RecursionDesired::Yes, header.rd);
+        assert_eq!(
+            (1, 2, 1, 2),
+            (
+                header.qdcount,
+                header.ancount,
+                header.nscount,
+                header.arcount
+            )
+        );
+
+        let unpacked = Message::unpack(&response.pack().unwrap()).unwrap();
+        assert_eq!(response.answers(), unpacked.answers());
+        assert_eq!(response.authorities(), unpacked.authorities());
+        assert_eq!(response.additionals(), unpacked.additionals());
+        assert!(unpacked.edns().is_some());
+    }
+
+    #[test]
+    fn extended_rcode() {
+        let query = Message::query("google.com".parse().unwrap(), rr::Type::A).build();
+        let response = Message::response(&query)
+            .rcode(ResponseCode::BadCookie)
+            .build();
+        assert!(response.edns().is_some());
+
+        let raw = response.pack().unwrap();
+        // 23 is split into 7 in the header and 1 in the OPT record.
+        assert_eq!(7, raw[3] & 0x0F);
+        let unpacked = Message::unpack(&raw).unwrap();
+        assert_eq!(ResponseCode::BadCookie, unpacked.header().rcode);
+        assert_eq!(1, unpacked.edns().unwrap().ext_rcode);
+    }
+}
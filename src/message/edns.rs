@@ -0,0 +1,211 @@
+use std::{io::Cursor, mem::size_of};
+
+use anyhow::{ensure, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::errors::DnsError;
+
+use super::rr;
+
+pub mod ede;
+
+/// Payload size advertised by default, small enough to avoid IP fragmentation (DNS Flag Day 2020).
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+const ROOT_SIZE: usize = 1;
+const METADATA_SIZE: usize =
+    size_of::<rr::Type>() + size_of::<u16>() + size_of::<u32>() + size_of::<u16>();
+const OPTION_METADATA_SIZE: usize = size_of::<u16>() + size_of::<u16>();
+const DNSSEC_OK: u16 = 0b1000_0000_0000_0000;
+
+/// EDNS(0) data, carried in the OPT pseudo-record of the additional section (RFC 6891).
+///
+/// Field          | Wire location
+/// ---------------+------------------------------------------------------------
+/// UDP payload    | CLASS, largest UDP response the sender can reassemble
+/// Extended RCODE | TTL bits 0..8, upper 8 bits of the 12 bit response code
+/// Version        | TTL bits 8..16
+/// DO             | TTL bit 16, DNSSEC records are desired
+/// Options        | RDATA, sequence of `code`, `length`, `data` triples
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edns {
+    pub udp_payload_size: u16,
+    pub ext_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+/// A single EDNS option, `data` is kept in wire format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        Edns {
+            udp_payload_size: DEFAULT_UDP_PAYLOAD_SIZE,
+            ext_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+}
+
+impl Edns {
+    /// Appends `option`, keeping the ones already present.
+    pub fn with_option(mut self, option: EdnsOption) -> Self {
+        self.options.push(option);
+        self
+    }
+
+    /// Extended DNS Errors carried in the options.
+    pub fn extended_errors(&self) -> impl Iterator<Item = ede::ExtendedError> + '_ {
+        self.options
+            .iter()
+            .filter_map(ede::ExtendedError::from_option)
+    }
+
+    /// Whether the record starting at `ptr` is an OPT record, whose owner is always the root.
+    pub(crate) fn test(buf: &[u8], ptr: usize) -> bool {
+        let opt: u16 = rr::Type::Opt.into();
+        buf.get(ptr..ptr + ROOT_SIZE + size_of::<u16>()) == Some(&[0, 0, opt as u8][..])
+    }
+
+    pub(crate) fn unpack(buf: &[u8], ptr: &mut usize) -> Result<Self> {
+        let mut at = *ptr + ROOT_SIZE;
+        ensure!(
+            buf.len() >= at + METADATA_SIZE,
+            DnsError::BufLenSmall {
+                min: at + METADATA_SIZE,
+                act: buf.len()
+            }
+        );
+        let mut metadata = Cursor::new(&buf[at..at + METADATA_SIZE]);
+        metadata.read_u16::<BigEndian>()?;
+        let udp_payload_size = metadata.read_u16::<BigEndian>()?;
+        let ext_rcode = metadata.read_u8()?;
+        let version = metadata.read_u8()?;
+        let flags = metadata.read_u16::<BigEndian>()?;
+        let length = metadata.read_u16::<BigEndian>()? as usize;
+        at += METADATA_SIZE;
+
+        let end = at + length;
+        ensure!(
+            buf.len() >= end,
+            DnsError::BufLenSmall {
+                min: end,
+                act: buf.len()
+            }
+        );
+        let mut options = Vec::new();
+        while at < end {
+            ensure!(
+                end >= at + OPTION_METADATA_SIZE,
+                DnsError::InvalidEncoding { at }
+            );
+            let mut option = Cursor::new(&buf[at..at + OPTION_METADATA_SIZE]);
+            let code = option.read_u16::<BigEndian>()?;
+            let len = option.read_u16::<BigEndian>()? as usize;
+            at += OPTION_METADATA_SIZE;
+            ensure!(end >= at + len, DnsError::InvalidEncoding { at });
+            options.push(EdnsOption {
+                code,
+                data: buf[at..at + len].to_vec(),
+            });
+            at += len;
+        }
+        *ptr = end;
+
+        Ok(Edns {
+            udp_payload_size,
+            ext_rcode,
+            version,
+            dnssec_ok: flags & DNSSEC_OK != 0,
+            options,
+        })
+    }
+
+    pub(crate) fn pack(&self, buf: &mut [u8]) -> Result<()> {
+        ensure!(
+            buf.len() == self.len(),
+            DnsError::BufLenNotEq {
+                exp: self.len(),
+                act: buf.len()
+            }
+        );
+        let mut cursor = Cursor::new(buf);
+        cursor.write_u8(0)?;
+        cursor.write_u16::<BigEndian>(rr::Type::Opt.into())?;
+        cursor.write_u16::<BigEndian>(self.udp_payload_size)?;
+        cursor.write_u8(self.ext_rcode)?;
+        cursor.write_u8(self.version)?;
+        cursor.write_u16::<BigEndian>(if self.dnssec_ok { DNSSEC_OK } else { 0 })?;
+        cursor.write_u16::<BigEndian>((self.len() - ROOT_SIZE - METADATA_SIZE) as u16)?;
+        for option in &self.options {
+            cursor.write_u16::<BigEndian>(option.code)?;
+            cursor.write_u16::<BigEndian>(option.data.len() as u16)?;
+            std::io::Write::write_all(&mut cursor, &option.data)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        ROOT_SIZE
+            + METADATA_SIZE
+            + self
+                .options
+                .iter()
+                .map(|option| OPTION_METADATA_SIZE + option.data.len())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpack() {
+        let raw = b"\x00\x00\x29\x10\x00\x00\x00\x80\x00\x00\x0C\x00\x0A\x00\x08\x01\x02\x03\x04\x05\x06\x07\x08";
+        assert!(Edns::test(raw, 0));
+        let mut ptr = 0;
+        let edns = Edns::unpack(raw, &mut ptr).unwrap();
+        assert_eq!(raw.len(), ptr);
+        assert_eq!(4096, edns.udp_payload_size);
+        assert!(edns.dnssec_ok);
+        assert_eq!(
+            vec![EdnsOption {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8]
+            }],
+            edns.options
+        );
+    }
+
+    #[test]
+    fn unpack_truncated_option() {
+        let raw = b"\x00\x00\x29\x10\x00\x00\x00\x00\x00\x00\x04\x00\x0A\x00\x08";
+        assert!(Edns::unpack(raw, &mut 0).is_err());
+    }
+
+    #[test]
+    fn pack() {
+        let raw = b"\x00\x00\x29\x04\xD0\x01\x00\x00\x00\x00\x06\x00\x0F\x00\x02\x00\x16";
+        let edns = Edns::unpack(raw, &mut 0).unwrap();
+        assert_eq!(1, edns.ext_rcode);
+        assert_eq!(
+            vec![ede::ExtendedError::new(
+                ede::InfoCode::NoReachableAuthority,
+                ""
+            )],
+            edns.extended_errors().collect::<Vec<_>>()
+        );
+        let mut buf = vec![0u8; edns.len()];
+        edns.pack(&mut buf).unwrap();
+        assert_eq!(raw, &buf[..]);
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt::Display;
+
+use super::EdnsOption;
+
+/// EDNS option code of an Extended DNS Error (RFC 8914).
+pub const OPTION_CODE: u16 = 15;
+
+const INFO_CODE_SIZE: usize = std::mem::size_of::<u16>();
+
+/// Extended DNS Error, explaining why a response carries its response code.
+///
+/// Field      | Wire format
+/// -----------+------------------------------------------------
+/// INFO-CODE  | 16 bits, see [`InfoCode`]
+/// EXTRA-TEXT | remaining bytes, UTF-8 text meant for humans
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedError {
+    pub info_code: InfoCode,
+    pub extra_text: String,
+}
+
+/// INFO-CODE values registered by RFC 8914, unregistered ones are kept as `Unknown`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InfoCode {
+    Other,
+    UnsupportedDnskeyAlgorithm,
+    UnsupportedDsDigestType,
+    StaleAnswer,
+    ForgedAnswer,
+    DnssecIndeterminate,
+    DnssecBogus,
+    SignatureExpired,
+    SignatureNotYetValid,
+    DnskeyMissing,
+    RrsigsMissing,
+    NoZoneKeyBitSet,
+    NsecMissing,
+    CachedError,
+    NotReady,
+    Blocked,
+    Censored,
+    Filtered,
+    Prohibited,
+    StaleNxdomainAnswer,
+    NotAuthoritative,
+    NotSupported,
+    NoReachableAuthority,
+    NetworkError,
+    InvalidData,
+    Unknown(u16),
+}
+
+const INFO_CODES: [(InfoCode, &str); 25] = [
+    (InfoCode::Other, "Other"),
+    (
+        InfoCode::UnsupportedDnskeyAlgorithm,
+        "Unsupported DNSKEY Algorithm",
+    ),
+    (
+        InfoCode::UnsupportedDsDigestType,
+        "Unsupported DS Digest Type",
+    ),
+    (InfoCode::StaleAnswer, "Stale Answer"),
+    (InfoCode::ForgedAnswer, "Forged Answer"),
+    (InfoCode::DnssecIndeterminate, "DNSSEC Indeterminate"),
+    (InfoCode::DnssecBogus, "DNSSEC Bogus"),
+    (InfoCode::SignatureExpired, "Signature Expired"),
+    (InfoCode::SignatureNotYetValid, "Signature Not Yet Valid"),
+    (InfoCode::DnskeyMissing, "DNSKEY Missing"),
+    (InfoCode::RrsigsMissing, "RRSIGs Missing"),
+    (InfoCode::NoZoneKeyBitSet, "No Zone Key Bit Set"),
+    (InfoCode::NsecMissing, "NSEC Missing"),
+    (InfoCode::CachedError, "Cached Error"),
+    (InfoCode::NotReady, "Not Ready"),
+    (InfoCode::Blocked, "Blocked"),
+    (InfoCode::Censored, "Censored"),
+    (InfoCode::Filtered, "Filtered"),
+    (InfoCode::Prohibited, "Prohibited"),
+    (InfoCode::StaleNxdomainAnswer, "Stale NXDOMAIN Answer"),
+    (InfoCode::NotAuthoritative, "Not Authoritative"),
+    (InfoCode::NotSupported, "Not Supported"),
+    (InfoCode::NoReachableAuthority, "No Reachable Authority"),
+    (InfoCode::NetworkError, "Network Error"),
+    (InfoCode::InvalidData, "Invalid Data"),
+];
+
+impl From<u16> for InfoCode {
+    fn from(value: u16) -> Self {
+        INFO_CODES
+            .get(value as usize)
+            .map_or(InfoCode::Unknown(value), |(code, _)| *code)
+    }
+}
+
+impl From<InfoCode> for u16 {
+    fn from(value: InfoCode) -> Self {
+        match value {
+            InfoCode::Unknown(value) => value,
+            _ => INFO_CODES
+                .iter()
+                .position(|(code, _)| *code == value)
+                .unwrap_or_default() as u16,
+        }
+    }
+}
+
+impl Display for InfoCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InfoCode::Unknown(value) => write!(f, "{}", value),
+            _ => write!(f, "{}", INFO_CODES[u16::from(*self) as usize].1),
+        }
+    }
+}
+
+impl ExtendedError {
+    pub fn new(info_code: InfoCode, extra_text: impl Into<String>) -> Self {
+        ExtendedError {
+            info_code,
+            extra_text: extra_text.into(),
+        }
+    }
+
+    /// Reads the error out of `option`, if it is an Extended DNS Error.
+    ///
+    /// Invalid UTF-8 in the text is replaced rather than rejected, the text is informational only.
+    pub fn from_option(option: &EdnsOption) -> Option<Self> {
+        if option.code != OPTION_CODE || option.data.len() < INFO_CODE_SIZE {
+            return None;
+        }
+        let (info_code, extra_text) = option.data.split_at(INFO_CODE_SIZE);
+        Some(ExtendedError {
+            info_code: u16::from_be_bytes([info_code[0], info_code[1]]).into(),
+            extra_text: String::from_utf8_lossy(extra_text).into_owned(),
+        })
+    }
+}
+
+impl From<ExtendedError> for EdnsOption {
+    fn from(value: ExtendedError) -> Self {
+        let mut data = u16::from(value.info_code).to_be_bytes().to_vec();
+        data.extend_from_slice(value.extra_text.as_bytes());
+        EdnsOption {
+            code: OPTION_CODE,
+            data,
+        }
+    }
+}
+
+/// Formatted the way dig prints it, e.g. `22 (No Reachable Authority): (timed out)`.
+impl Display for ExtendedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", u16::from(self.info_code), self.info_code)?;
+        if !self.extra_text.is_empty() {
+            write!(f, ": ({})", self.extra_text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_code() {
+        assert_eq!(InfoCode::Blocked, InfoCode::from(15));
+        assert_eq!(24, u16::from(InfoCode::InvalidData));
+        assert_eq!(InfoCode::Unknown(49152), InfoCode::from(49152));
+        assert_eq!(49152, u16::from(InfoCode::Unknown(49152)));
+    }
+
+    #[test]
+    fn round_trip() {
+        let ede = ExtendedError::new(InfoCode::NoReachableAuthority, "timed out");
+        let option = EdnsOption::from(ede.clone());
+        assert_eq!(b"\x00\x16timed out", &option.data[..]);
+        assert_eq!(Some(ede), ExtendedError::from_option(&option));
+    }
+
+    #[test]
+    fn from_other_option() {
+        let cookie = EdnsOption {
+            code: 10,
+            data: vec![0; 8],
+        };
+        assert_eq!(None, ExtendedError::from_option(&cookie));
+        let short = EdnsOption {
+            code: OPTION_CODE,
+            data: vec![0],
+        };
+        assert_eq!(None, ExtendedError::from_option(&short));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            "22 (No Reachable Authority): (timed out)",
+            ExtendedError::new(InfoCode::NoReachableAuthority, "timed out").to_string()
+        );
+        assert_eq!(
+            "49152 (49152)",
+            ExtendedError::new(InfoCode::Unknown(49152), "").to_string()
+        );
+    }
+}
@@ -3,17 +3,21 @@ use std::mem::size_of;
 use byteorder::{BigEndian, ByteOrder};
 use packed_struct::prelude::*;
 
-/// Packet Identifier (ID)	          | A random ID assigned to query packets. Response packets must reply with the same ID.
-/// Query/Response Indicator (QR)	  | `Response` for a reply packet, `Query` for a question packet.
-/// Operation Code (OPCODE)	          | Specifies the kind of query in a message.
-/// Authoritative Answer (AA)	      | `Yes` if the responding server "owns" the domain queried, i.e., it's authoritative.
-/// Truncation (TC)	                  | `Yes` if the message is larger than 512 bytes. Always `No` in UDP responses.
-/// Recursion Desired (RD)	          | Sender sets this to `Yes` if the server should recursively resolve this query, `No` otherwise.
-/// Recursion Available (RA)	      | Sender sets this to `Yes` if the server supports recursive queries, `No` otherwise.
-/// Reserved (Z)	                  | Used by DNSSEC queries. At inception, it was reserved for future use.
-/// Response Code (RCODE)	          | Response code indicating the status of the response.
-/// Question Count (QDCOUNT)	      | Number of questions in the Question section.
-/// Answer Record Count (ANCOUNT)	  | Number of records in the Answer section.
+use crate::errors::DnsError;
+
+/// Packet Identifier (ID)            | A random ID assigned to query packets. Response packets must reply with the same ID.
+/// Query/Response Indicator (QR)     | `Response` for a reply packet, `Query` for a question packet.
+/// Operation Code (OPCODE)           | Specifies the kind of query in a message.
+/// Authoritative Answer (AA)         | `Yes` if the responding server "owns" the domain queried, i.e., it's authoritative.
+/// Truncation (TC)                   | `Yes` if the message is larger than 512 bytes. Always `No` in UDP responses.
+/// Recursion Desired (RD)            | Sender sets this to `Yes` if the server should recursively resolve this query, `No` otherwise.
+/// Recursion Available (RA)          | Sender sets this to `Yes` if the server supports recursive queries, `No` otherwise.
+/// Reserved (Z)                      | Reserved for future use, must be zero.
+/// Authentic Data (AD)               | `Yes` if all the data in the response was validated with DNSSEC.
+/// Checking Disabled (CD)            | `Yes` if the sender accepts data that failed DNSSEC validation.
+/// Response Code (RCODE)             | Response code indicating the status of the response, see [`ResponseCode`].
+/// Question Count (QDCOUNT)          | Number of questions in the Question section.
+/// Answer Record Count (ANCOUNT)     | Number of records in the Answer section.
 /// Authority Record Count (NSCOUNT)  | Number of records in the Authority section.
 /// Additional Record Count (ARCOUNT) | Number of records in the Additional section.
 #[derive(Clone, Copy, Debug, Default, PackedStruct)]
@@ -23,8 +27,8 @@ pub struct Header {
     pub id: u16,
     #[packed_field(bits = "16", ty = "enum")]
     pub qr: Indicator,
-    #[packed_field(bits = "17..=20")]
-    pub opcode: Integer<u8, packed_bits::Bits<4>>,
+    #[packed_field(bits = "17..=20", ty = "enum")]
+    pub opcode: Opcode,
     #[packed_field(bits = "21", ty = "enum")]
     pub aa: AuthoritativeAnswer,
     #[packed_field(bits = "22", ty = "enum")]
@@ -33,8 +37,12 @@ pub struct Header {
     pub rd: RecursionDesired,
     #[packed_field(bits = "24", ty = "enum")]
     pub ra: RecursionAvailable,
-    #[packed_field(bits = "25..=27")]
-    pub z: Integer<u8, packed_bits::Bits<3>>,
+    #[packed_field(bits = "25")]
+    pub z: Integer<u8, packed_bits::Bits<1>>,
+    #[packed_field(bits = "26", ty = "enum")]
+    pub ad: AuthenticData,
+    #[packed_field(bits = "27", ty = "enum")]
+    pub cd: CheckingDisabled,
     #[packed_field(bits = "28..=31", ty = "enum")]
     pub rcode: ResponseCode,
     #[packed_field(bits = "32..=47")]
@@ -48,7 +56,7 @@ pub struct Header {
 }
 
 /// `Response` for a reply packet, `Query` for a question packet.
-#[derive(PrimitiveEnum, Clone, Copy, Debug, Default)]
+#[derive(PrimitiveEnum, Clone, Copy, Debug, Default, PartialEq)]
 pub enum Indicator {
     Query = 0,
     #[default]
@@ -56,7 +64,7 @@ pub enum Indicator {
 }
 
 /// `Yes` if the responding server "owns" the domain queried, i.e., it's authoritative.
-#[derive(PrimitiveEnum, Clone, Copy, Debug, Default)]
+#[derive(PrimitiveEnum, Clone, Copy, Debug, Default, PartialEq)]
 pub enum AuthoritativeAnswer {
     #[default]
     No = 0,
@@ -64,7 +72,7 @@ pub enum AuthoritativeAnswer {
 }
 
 /// `Yes` if the message is larger than 512 bytes. Always `No` in UDP responses.
-#[derive(PrimitiveEnum, Clone, Copy, Debug, Default)]
+#[derive(PrimitiveEnum, Clone, Copy, Debug, Default, PartialEq)]
 pub enum Truncation {
     #[default]
     No = 0,
@@ -72,7 +80,7 @@ pub enum Truncation {
 }
 
 /// Sender sets this to `Yes` if the server should recursively resolve this query, `No` otherwise.
-#[derive(PrimitiveEnum, Clone, Copy, Debug, Default)]
+#[derive(PrimitiveEnum, Clone, Copy, Debug, Default, PartialEq)]
 pub enum RecursionDesired {
     #[default]
     No = 0,
@@ -80,42 +88,309 @@ pub enum RecursionDesired {
 }
 
 /// Sender sets this to `Yes` if the server supports recursive queries, `No` otherwise.
-#[derive(PrimitiveEnum, Clone, Copy, Debug, Default)]
+#[derive(PrimitiveEnum, Clone, Copy, Debug, Default, PartialEq)]
 pub enum RecursionAvailable {
     #[default]
     No = 0,
     Yes = 1,
 }
 
-/// Response code - this 4 bit field is set as part of responses.  The values have the following
-/// interpretation:
-///
-/// • 0 No error condition
-///
-/// • 1 Format error - The name server was unable to interpret the query.
-///
-/// • 2 Server failure - The name server was unable to process this query due to a problem with the
-///   name server.
-///
-/// • 3 Name Error - Meaningful only for responses from an authoritative name server, this code
-///   signifies that the domain name referenced in the query does not exist.
-///
-/// • 4 Not Implemented - The name server does not support the requested kind of query.
+/// `Yes` if all the data in the response was validated with DNSSEC (RFC 4035).
+#[derive(PrimitiveEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum AuthenticData {
+    #[default]
+    No = 0,
+    Yes = 1,
+}
+
+/// `Yes` if the sender accepts data that failed DNSSEC validation (RFC 4035).
+#[derive(PrimitiveEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum CheckingDisabled {
+    #[default]
+    No = 0,
+    Yes = 1,
+}
+
+/// OPCODE | value and meaning
+/// -------+-----------------------------------------
+/// QUERY  | 0, a standard query
+/// IQUERY | 1, an inverse query, obsolete (RFC 3425)
+/// STATUS | 2, a server status request
+/// NOTIFY | 4, a zone change notification (RFC 1996)
+/// UPDATE | 5, a dynamic update (RFC 2136)
+/// DSO    | 6, DNS stateful operations (RFC 8490)
 ///
-/// • 5 Refused - The name server refuses to perform the specified operation for policy reasons.  For
-///   example, a name server may not wish to provide the information to the particular requester,
-///   or a name server may not wish to perform a particular operation (e.g., zone transfer) for
-///   particular data.
+/// Unassigned values are kept as `Unknown`, so they can be answered with `NOTIMP`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Opcode {
+    #[default]
+    Query,
+    IQuery,
+    Status,
+    Notify,
+    Update,
+    Dso,
+    Unknown(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            6 => Opcode::Dso,
+            _ => Opcode::Unknown(value),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(value: Opcode) -> Self {
+        match value {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::Dso => 6,
+            Opcode::Unknown(value) => value,
+        }
+    }
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = match self {
+            Opcode::Query => "QUERY",
+            Opcode::IQuery => "IQUERY",
+            Opcode::Status => "STATUS",
+            Opcode::Notify => "NOTIFY",
+            Opcode::Update => "UPDATE",
+            Opcode::Dso => "DSO",
+            Opcode::Unknown(value) => return write!(f, "RESERVED{}", value),
+        };
+        write!(f, "{}", mnemonic)
+    }
+}
+
+impl std::str::FromStr for Opcode {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "QUERY" => Ok(Opcode::Query),
+            "IQUERY" => Ok(Opcode::IQuery),
+            "STATUS" => Ok(Opcode::Status),
+            "NOTIFY" => Ok(Opcode::Notify),
+            "UPDATE" => Ok(Opcode::Update),
+            "DSO" => Ok(Opcode::Dso),
+            upper => match upper.strip_prefix("RESERVED").map(str::parse::<u8>) {
+                Some(Ok(value)) => Ok(value.into()),
+                _ => Err(DnsError::UnknownMnemonic(s.into())),
+            },
+        }
+    }
+}
+
+/// Packed by hand rather than derived, since the derive cannot keep unassigned values.
+impl PrimitiveEnum for Opcode {
+    type Primitive = u8;
+
+    fn from_primitive(val: u8) -> Option<Self> {
+        Some(val.into())
+    }
+
+    fn to_primitive(&self) -> u8 {
+        (*self).into()
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+
+    fn from_str_lower(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+}
+
+/// RCODE     | value and meaning
+/// ----------+-----------------------------------------------------------------------
+/// NOERROR   | 0, no error condition
+/// FORMERR   | 1, the name server was unable to interpret the query
+/// SERVFAIL  | 2, the name server was unable to process the query due to a problem with the
+///           | name server
+/// NXDOMAIN  | 3, the domain name referenced in the query does not exist
+/// NOTIMP    | 4, the name server does not support the requested kind of query
+/// REFUSED   | 5, the name server refuses to perform the operation for policy reasons
+/// YXDOMAIN  | 6, a name exists when it should not (RFC 2136)
+/// YXRRSET   | 7, an RR set exists when it should not (RFC 2136)
+/// NXRRSET   | 8, an RR set that should exist does not (RFC 2136)
+/// NOTAUTH   | 9, the server is not authoritative for the zone (RFC 2136)
+/// NOTZONE   | 10, a name is not contained in the zone (RFC 2136)
+/// DSOTYPENI | 11, DSO-TYPE not implemented (RFC 8490)
+/// BADVERS   | 16, bad OPT version (RFC 6891)
+/// BADKEY    | 17, key not recognized (RFC 8945)
+/// BADTIME   | 18, signature out of time window (RFC 8945)
+/// BADMODE   | 19, bad TKEY mode (RFC 2930)
+/// BADNAME   | 20, duplicate key name (RFC 2930)
+/// BADALG    | 21, algorithm not supported (RFC 2930)
+/// BADTRUNC  | 22, bad truncation (RFC 8945)
+/// BADCOOKIE | 23, bad or missing server cookie (RFC 7873)
 ///
-#[derive(PrimitiveEnum, Clone, Copy, Debug, Default, PartialEq)]
+/// The header only holds the lower 4 bits, the upper 8 bits of values above 15 travel in the OPT
+/// record. [`Message`](super::Message) assembles and splits them, so `Header::rcode` of an
+/// unpacked message holds the whole 12 bit value. Unassigned values are kept as `Unknown`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
 pub enum ResponseCode {
     #[default]
-    NoError = 0,
-    FormatError = 1,
-    ServerFailure = 2,
-    NameError = 3,
-    NotImplemented = 4,
-    Refused = 5,
+    NoError,
+    FormatError,
+    ServerFailure,
+    NameError,
+    NotImplemented,
+    Refused,
+    YxDomain,
+    YxRrSet,
+    NxRrSet,
+    NotAuth,
+    NotZone,
+    DsoTypeNi,
+    BadVers,
+    BadKey,
+    BadTime,
+    BadMode,
+    BadName,
+    BadAlg,
+    BadTrunc,
+    BadCookie,
+    Unknown(u16),
+}
+
+const RESPONSE_CODES: [(ResponseCode, u16, &str); 20] = [
+    (ResponseCode::NoError, 0, "NOERROR"),
+    (ResponseCode::FormatError, 1, "FORMERR"),
+    (ResponseCode::ServerFailure, 2, "SERVFAIL"),
+    (ResponseCode::NameError, 3, "NXDOMAIN"),
+    (ResponseCode::NotImplemented, 4, "NOTIMP"),
+    (ResponseCode::Refused, 5, "REFUSED"),
+    (ResponseCode::YxDomain, 6, "YXDOMAIN"),
+    (ResponseCode::YxRrSet, 7, "YXRRSET"),
+    (ResponseCode::NxRrSet, 8, "NXRRSET"),
+    (ResponseCode::NotAuth, 9, "NOTAUTH"),
+    (ResponseCode::NotZone, 10, "NOTZONE"),
+    (ResponseCode::DsoTypeNi, 11, "DSOTYPENI"),
+    (ResponseCode::BadVers, 16, "BADVERS"),
+    (ResponseCode::BadKey, 17, "BADKEY"),
+    (ResponseCode::BadTime, 18, "BADTIME"),
+    (ResponseCode::BadMode, 19, "BADMODE"),
+    (ResponseCode::BadName, 20, "BADNAME"),
+    (ResponseCode::BadAlg, 21, "BADALG"),
+    (ResponseCode::BadTrunc, 22, "BADTRUNC"),
+    (ResponseCode::BadCookie, 23, "BADCOOKIE"),
+];
+
+/// Largest response code, 12 bits wide.
+const MAX_RESPONSE_CODE: u16 = 0x0FFF;
+const HEADER_RCODE_BITS: u16 = 4;
+
+impl ResponseCode {
+    /// Assembles a response code from the 4 bits of the header and the 8 bits of the OPT record.
+    pub fn from_parts(header: ResponseCode, extended: u8) -> Self {
+        ResponseCode::from(
+            u16::from(extended) << HEADER_RCODE_BITS | u16::from(header.header_bits()),
+        )
+    }
+
+    /// Lower 4 bits, carried in the header.
+    pub fn header_bits(self) -> u8 {
+        (u16::from(self) & ((1 << HEADER_RCODE_BITS) - 1)) as u8
+    }
+
+    /// Upper 8 bits, carried in the OPT record.
+    pub fn extended_bits(self) -> u8 {
+        (u16::from(self) >> HEADER_RCODE_BITS) as u8
+    }
+
+    /// Whether the value needs an OPT record to be sent.
+    pub fn is_extended(self) -> bool {
+        self.extended_bits() != 0
+    }
+}
+
+impl From<u16> for ResponseCode {
+    fn from(value: u16) -> Self {
+        let value = value & MAX_RESPONSE_CODE;
+        RESPONSE_CODES
+            .iter()
+            .find(|(_, code, _)| *code == value)
+            .map_or(ResponseCode::Unknown(value), |(rcode, _, _)| *rcode)
+    }
+}
+
+impl From<ResponseCode> for u16 {
+    fn from(value: ResponseCode) -> Self {
+        match value {
+            ResponseCode::Unknown(value) => value,
+            _ => RESPONSE_CODES
+                .iter()
+                .find(|(rcode, _, _)| *rcode == value)
+                .map_or(0, |(_, code, _)| *code),
+        }
+    }
+}
+
+impl std::fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match RESPONSE_CODES.iter().find(|(rcode, _, _)| rcode == self) {
+            Some((_, _, mnemonic)) => write!(f, "{}", mnemonic),
+            None => write!(f, "RESERVED{}", u16::from(*self)),
+        }
+    }
+}
+
+impl std::str::FromStr for ResponseCode {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        RESPONSE_CODES
+            .iter()
+            .find(|(_, _, mnemonic)| *mnemonic == upper)
+            .map(|(rcode, _, _)| *rcode)
+            .or_else(
+                || match upper.strip_prefix("RESERVED").map(str::parse::<u16>) {
+                    Some(Ok(value)) => Some(value.into()),
+                    _ => None,
+                },
+            )
+            .ok_or_else(|| DnsError::UnknownMnemonic(s.into()))
+    }
+}
+
+/// Packed by hand rather than derived, since the derive cannot keep unassigned values. Only the
+/// lower 4 bits fit in the header.
+impl PrimitiveEnum for ResponseCode {
+    type Primitive = u8;
+
+    fn from_primitive(val: u8) -> Option<Self> {
+        Some(ResponseCode::from(u16::from(val)))
+    }
+
+    fn to_primitive(&self) -> u8 {
+        self.header_bits()
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+
+    fn from_str_lower(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
 }
 
 pub const DNS_HEADER_SIZE: usize = 12;
@@ -125,7 +400,7 @@ impl Header {
         if query.len() < DNS_HEADER_SIZE {
             return Err(PackingError::BufferTooSmall);
         }
-        Ok(Header::unpack_from_slice(&query[..DNS_HEADER_SIZE])?)
+        Header::unpack_from_slice(&query[..DNS_HEADER_SIZE])
     }
 
     pub fn unpack_id(query: &[u8]) -> Result<u16, PackingError> {
@@ -137,3 +412,44 @@ impl Header {
         Ok(id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpack_all_codes() {
+        // NOTIFY response with AA, AD and CD set and NOTZONE.
+        let header = Header::unpack(b"\x04\xD2\xA4\x3A\x00\x00\x00\x00\x00\x00\x00\x00").unwrap();
+        assert_eq!(Opcode::Notify, header.opcode);
+        assert_eq!(AuthoritativeAnswer::Yes, header.aa);
+        assert_eq!(AuthenticData::Yes, header.ad);
+        assert_eq!(CheckingDisabled::Yes, header.cd);
+        assert_eq!(ResponseCode::NotZone, header.rcode);
+        assert_eq!(
+            b"\x04\xD2\xA4\x3A\x00\x00\x00\x00\x00\x00\x00\x00",
+            &header.pack().unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_values() {
+        // Opcode 9, RCODE 12.
+        let header = Header::unpack(b"\x04\xD2\x48\x0C\x00\x00\x00\x00\x00\x00\x00\x00").unwrap();
+        assert_eq!(Opcode::Unknown(9), header.opcode);
+        assert_eq!(ResponseCode::Unknown(12), header.rcode);
+        assert_eq!("RESERVED12", header.rcode.to_string());
+        assert_eq!(header.rcode, "RESERVED12".parse().unwrap());
+        assert_eq!(header.opcode, header.opcode.to_string().parse().unwrap());
+        assert_eq!(0x48, header.pack().unwrap()[2]);
+    }
+
+    #[test]
+    fn extended_rcode() {
+        let rcode = ResponseCode::from_parts(ResponseCode::NoError, 1);
+        assert_eq!(ResponseCode::BadVers, rcode);
+        assert_eq!((0, 1), (rcode.header_bits(), rcode.extended_bits()));
+        assert!(!ResponseCode::NotZone.is_extended());
+        assert_eq!(ResponseCode::BadCookie, "badcookie".parse().unwrap());
+    }
+}
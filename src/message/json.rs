@@ -0,0 +1,588 @@
+//! JSON representation of DNS messages (RFC 8427), through `serde`.
+//!
+//! Messages serialize member by member, e.g. `{"ID": 1234, "QR": false, ..., "questionRRs":
+//! [{"NAME": "example.com", "TYPE": 1, "CLASS": 1}], "answerRRs": [], ...}`, with RDATA as
+//! `RDATAHEX` and the OPT pseudo-record among `additionalRRs`. [`MessageOctets`] serializes the
+//! wire format instead, as `{"messageOctetsHEX": "04D2..."}`. Either form deserializes.
+
+use std::io::{Cursor, Write};
+
+use anyhow::{ensure, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use packed_struct::prelude::*;
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::errors::DnsError;
+
+use super::{
+    answer::Answer,
+    edns::Edns,
+    header::{
+        AuthenticData, AuthoritativeAnswer, CheckingDisabled, Header, Indicator, Opcode,
+        RecursionAvailable, RecursionDesired, ResponseCode, Truncation, DNS_HEADER_SIZE,
+    },
+    labels::Labels,
+    presentation,
+    question::Question,
+    rdata::RData,
+    Message,
+};
+
+/// A message serialized as its wire format, hex encoded.
+#[derive(Debug, Clone)]
+pub struct MessageOctets(pub Message);
+
+#[derive(Serialize, Deserialize)]
+struct Octets {
+    #[serde(rename = "messageOctetsHEX")]
+    hex: String,
+}
+
+/// Header members, booleans for flags and integers otherwise.
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+struct HeaderFields {
+    #[serde(rename = "ID")]
+    id: u16,
+    #[serde(rename = "QR")]
+    qr: bool,
+    #[serde(rename = "Opcode")]
+    opcode: u8,
+    #[serde(rename = "AA")]
+    aa: bool,
+    #[serde(rename = "TC")]
+    tc: bool,
+    #[serde(rename = "RD")]
+    rd: bool,
+    #[serde(rename = "RA")]
+    ra: bool,
+    #[serde(rename = "AD")]
+    ad: bool,
+    #[serde(rename = "CD")]
+    cd: bool,
+    #[serde(rename = "RCODE")]
+    rcode: u16,
+    #[serde(rename = "QDCOUNT")]
+    qdcount: u16,
+    #[serde(rename = "ANCOUNT")]
+    ancount: u16,
+    #[serde(rename = "NSCOUNT")]
+    nscount: u16,
+    #[serde(rename = "ARCOUNT")]
+    arcount: u16,
+}
+
+#[derive(Serialize, Deserialize)]
+struct QuestionFields {
+    #[serde(rename = "NAME")]
+    name: Labels,
+    #[serde(rename = "TYPE")]
+    qtype: u16,
+    #[serde(rename = "CLASS")]
+    qclass: u16,
+}
+
+/// Resource record of any type and class, the OPT pseudo-record included.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(rename = "NAME")]
+    name: Labels,
+    #[serde(rename = "TYPE")]
+    rtype: u16,
+    #[serde(rename = "CLASS")]
+    class: u16,
+    #[serde(rename = "TTL")]
+    ttl: u32,
+    #[serde(rename = "RDLENGTH", default)]
+    length: Option<u16>,
+    #[serde(rename = "RDATAHEX", with = "hex")]
+    data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MessageFields {
+    #[serde(flatten)]
+    header: HeaderFields,
+    #[serde(rename = "questionRRs", default)]
+    questions: Vec<QuestionFields>,
+    #[serde(rename = "answerRRs", default)]
+    answers: Vec<Record>,
+    #[serde(rename = "authorityRRs", default)]
+    authorities: Vec<Record>,
+    #[serde(rename = "additionalRRs", default)]
+    additionals: Vec<Record>,
+}
+
+/// Either form of a message, told apart by `messageOctetsHEX`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyForm {
+    Octets(Octets),
+    Fields(MessageFields),
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let records = |records: &[Answer]| records.iter().map(Record::from).collect::<Vec<_>>();
+        let mut additionals = records(&self.additionals);
+        if let Some(edns) = &self.edns {
+            let edns = Edns {
+                ext_rcode: self.header.rcode.extended_bits(),
+                ..edns.clone()
+            };
+            additionals.push(Record::opt(&edns).map_err(ser::Error::custom)?);
+        }
+        MessageFields {
+            header: HeaderFields::from(&self.counted_header()),
+            questions: self.questions.iter().map(QuestionFields::from).collect(),
+            answers: records(&self.answers),
+            authorities: records(&self.authorities),
+            additionals,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Members are laid out in wire format and parsed as such, so that a deserialized message is
+/// exactly what [`Message::unpack`] would give.
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let wire = match AnyForm::deserialize(deserializer)? {
+            AnyForm::Octets(octets) => {
+                presentation::unhex(&octets.hex).map_err(de::Error::custom)?
+            }
+            AnyForm::Fields(fields) => fields.pack().map_err(de::Error::custom)?,
+        };
+        Message::unpack(&wire).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for MessageOctets {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let wire = self.0.pack().map_err(ser::Error::custom)?;
+        Octets {
+            hex: hex::encode(&wire),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageOctets {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let octets = Octets::deserialize(deserializer)?;
+        let wire = presentation::unhex(&octets.hex).map_err(de::Error::custom)?;
+        let msg = Message::unpack(&wire).map_err(de::Error::custom)?;
+        Ok(MessageOctets(msg))
+    }
+}
+
+impl Serialize for Header {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        HeaderFields::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Header {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(HeaderFields::deserialize(deserializer)?.into())
+    }
+}
+
+impl Serialize for Question {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        QuestionFields::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Question {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = QuestionFields::deserialize(deserializer)?;
+        Ok(Question {
+            domain: fields.name,
+            qtype: fields.qtype.try_into().map_err(de::Error::custom)?,
+            qclass: fields.qclass.try_into().map_err(de::Error::custom)?,
+        })
+    }
+}
+
+impl Serialize for Answer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Record::from(self).serialize(serializer)
+    }
+}
+
+/// RDATA is checked against its type, names in it must not be compressed.
+impl<'de> Deserialize<'de> for Answer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let record = Record::deserialize(deserializer)?;
+        let answer = || -> Result<Answer> {
+            let rtype = record.rtype.try_into()?;
+            let data = RData::unpack(record.checked()?, 0, record.data.len(), rtype)?.pack()?;
+            let class = record.class.try_into()?;
+            Ok(Answer::new(
+                record.name.clone(),
+                rtype,
+                class,
+                record.ttl,
+                data,
+            ))
+        };
+        answer().map_err(de::Error::custom)
+    }
+}
+
+/// The dotted name, `.` for the root.
+impl Serialize for Labels {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_slice() {
+            [] => serializer.serialize_str("."),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Labels {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl From<&Header> for HeaderFields {
+    fn from(header: &Header) -> Self {
+        HeaderFields {
+            id: header.id,
+            qr: header.qr == Indicator::Response,
+            opcode: header.opcode.into(),
+            aa: header.aa == AuthoritativeAnswer::Yes,
+            tc: header.tc == Truncation::Yes,
+            rd: header.rd == RecursionDesired::Yes,
+            ra: header.ra == RecursionAvailable::Yes,
+            ad: header.ad == AuthenticData::Yes,
+            cd: header.cd == CheckingDisabled::Yes,
+            rcode: header.rcode.into(),
+            qdcount: header.qdcount,
+            ancount: header.ancount,
+            nscount: header.nscount,
+            arcount: header.arcount,
+        }
+    }
+}
+
+impl From<HeaderFields> for Header {
+    fn from(fields: HeaderFields) -> Self {
+        macro_rules! flag {
+            ($set:expr, $kind:ident) => {
+                if $set {
+                    $kind::Yes
+                } else {
+                    $kind::No
+                }
+            };
+        }
+        Header {
+            id: fields.id,
+            qr: if fields.qr {
+                Indicator::Response
+            } else {
+                Indicator::Query
+            },
+            opcode: Opcode::from(fields.opcode),
+            aa: flag!(fields.aa, AuthoritativeAnswer),
+            tc: flag!(fields.tc, Truncation),
+            rd: flag!(fields.rd, RecursionDesired),
+            ra: flag!(fields.ra, RecursionAvailable),
+            z: 0.into(),
+            ad: flag!(fields.ad, AuthenticData),
+            cd: flag!(fields.cd, CheckingDisabled),
+            rcode: ResponseCode::from(fields.rcode),
+            qdcount: fields.qdcount,
+            ancount: fields.ancount,
+            nscount: fields.nscount,
+            arcount: fields.arcount,
+        }
+    }
+}
+
+impl From<&Question> for QuestionFields {
+    fn from(question: &Question) -> Self {
+        QuestionFields {
+            name: question.domain.clone(),
+            qtype: question.qtype.into(),
+            qclass: question.qclass.into(),
+        }
+    }
+}
+
+impl From<&Answer> for Record {
+    fn from(answer: &Answer) -> Self {
+        Record {
+            name: answer.name().clone(),
+            rtype: answer.atype().into(),
+            class: answer.aclass().into(),
+            ttl: answer.ttl(),
+            length: Some(answer.data().len() as u16),
+            data: answer.data().to_vec(),
+        }
+    }
+}
+
+impl Record {
+    /// The OPT pseudo-record carrying `edns`, read back from its wire format.
+    fn opt(edns: &Edns) -> Result<Self> {
+        let mut wire = vec![0u8; edns.len()];
+        edns.pack(&mut wire)?;
+        let mut ptr = 0;
+        let name = Labels::unpack(&wire, &mut ptr)?;
+        let mut cursor = Cursor::new(&wire[ptr..]);
+        let (rtype, class, ttl) = (
+            cursor.read_u16::<BigEndian>()?,
+            cursor.read_u16::<BigEndian>()?,
+            cursor.read_u32::<BigEndian>()?,
+        );
+        let length = cursor.read_u16::<BigEndian>()?;
+        let at = ptr + cursor.position() as usize;
+        Ok(Record {
+            name,
+            rtype,
+            class,
+            ttl,
+            length: Some(length),
+            data: wire[at..].to_vec(),
+        })
+    }
+
+    /// RDATA, once checked to be as long as `RDLENGTH` says.
+    fn checked(&self) -> Result<&[u8]> {
+        let length = self.data.len();
+        ensure!(
+            self.length
+                .is_none_or(|expected| usize::from(expected) == length),
+            DnsError::BufLenNotEq {
+                exp: self.length.unwrap_or_default().into(),
+                act: length
+            }
+        );
+        ensure!(
+            length <= u16::MAX as usize,
+            DnsError::MsgTooLong {
+                max: u16::MAX as usize,
+                act: length
+            }
+        );
+        Ok(&self.data)
+    }
+
+    fn pack(&self, wire: &mut Vec<u8>) -> Result<()> {
+        let data = self.checked()?;
+        let at = wire.len();
+        wire.resize(at + self.name.len(), 0);
+        self.name.pack(&mut wire[at..])?;
+        wire.write_u16::<BigEndian>(self.rtype)?;
+        wire.write_u16::<BigEndian>(self.class)?;
+        wire.write_u32::<BigEndian>(self.ttl)?;
+        wire.write_u16::<BigEndian>(data.len() as u16)?;
+        wire.write_all(data)?;
+        Ok(())
+    }
+}
+
+impl MessageFields {
+    /// Wire format of the message, section counts following the sections rather than the
+    /// `*COUNT` members.
+    fn pack(&self) -> Result<Vec<u8>> {
+        let mut header = Header::from(self.header);
+        header.rcode = ResponseCode::from(u16::from(header.rcode.header_bits()));
+        header.qdcount = self.questions.len() as u16;
+        header.ancount = self.answers.len() as u16;
+        header.nscount = self.authorities.len() as u16;
+        header.arcount = self.additionals.len() as u16;
+
+        let mut wire = vec![0u8; DNS_HEADER_SIZE];
+        header.pack_to_slice(&mut wire)?;
+        for question in &self.questions {
+            let at = wire.len();
+            wire.resize(at + question.name.len(), 0);
+            question.name.pack(&mut wire[at..])?;
+            wire.write_u16::<BigEndian>(question.qtype)?;
+            wire.write_u16::<BigEndian>(question.qclass)?;
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            record.pack(&mut wire)?;
+        }
+        Ok(wire)
+    }
+}
+
+/// Upper case hex, as in the examples of RFC 8427, either case being read.
+mod hex {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::presentation;
+
+    pub(super) fn encode(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+    }
+
+    pub(super) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(bytes))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<u8>, D::Error> {
+        presentation::unhex(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::message::{answer::Answer, edns::EdnsOption, rr};
+
+    use super::*;
+
+    fn response() -> Message {
+        let query = Message::query("example.com".parse().unwrap(), rr::Type::Mx)
+            .id(1234)
+            .recursion_desired()
+            .build();
+        let mx = Answer::new(
+            "example.com".parse().unwrap(),
+            rr::Type::Mx,
+            rr::Class::In,
+            300,
+            b"\x00\x0A\x04mail\x07example\x03com\x00".to_vec(),
+        );
+        let a = Answer::new(
+            "mail.example.com".parse().unwrap(),
+            rr::Type::A,
+            rr::Class::In,
+            60,
+            vec![192, 0, 2, 1],
+        );
+        Message::response(&query)
+            .recursion_available()
+            .answer(mx)
+            .additional(a)
+            .build()
+    }
+
+    fn round_trip(msg: &Message) -> Message {
+        let json = serde_json::to_string(msg).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn members() {
+        let value = serde_json::to_value(response()).unwrap();
+        assert_eq!(1234, value["ID"]);
+        assert_eq!(true, value["QR"]);
+        assert_eq!(0, value["Opcode"]);
+        assert_eq!(true, value["RD"]);
+        assert_eq!(false, value["AA"]);
+        assert_eq!(0, value["RCODE"]);
+        assert_eq!(1, value["ANCOUNT"]);
+        assert_eq!(1, value["ARCOUNT"]);
+        assert_eq!(
+            json!([{"NAME": "example.com", "TYPE": 15, "CLASS": 1}]),
+            value["questionRRs"]
+        );
+        assert_eq!(
+            json!([{
+                "NAME": "example.com",
+                "TYPE": 15,
+                "CLASS": 1,
+                "TTL": 300,
+                "RDLENGTH": 20,
+                "RDATAHEX": "000A046D61696C076578616D706C6503636F6D00",
+            }]),
+            value["answerRRs"]
+        );
+        assert_eq!(json!([]), value["authorityRRs"]);
+    }
+
+    #[test]
+    fn round_trips_through_members() {
+        let msg = response();
+        assert_eq!(msg.pack().unwrap(), round_trip(&msg).pack().unwrap());
+    }
+
+    #[test]
+    fn round_trips_extended_rcode_through_opt() {
+        let query = Message::query("example.com".parse().unwrap(), rr::Type::A).build();
+        let msg = Message::response(&query)
+            .rcode(ResponseCode::BadCookie)
+            .edns(Edns::default().with_option(EdnsOption {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            }))
+            .build();
+
+        let value = serde_json::to_value(&msg).unwrap();
+        assert_eq!(23, value["RCODE"]);
+        assert_eq!(41, value["additionalRRs"][0]["TYPE"]);
+        assert_eq!(".", value["additionalRRs"][0]["NAME"]);
+
+        let back = round_trip(&msg);
+        assert_eq!(ResponseCode::BadCookie, back.header().rcode);
+        assert_eq!(msg.edns().unwrap().options, back.edns().unwrap().options);
+        assert_eq!(msg.pack().unwrap(), back.pack().unwrap());
+    }
+
+    #[test]
+    fn octets_form() {
+        let msg = response();
+        let value = serde_json::to_value(MessageOctets(msg.clone())).unwrap();
+        let hex = value["messageOctetsHEX"].as_str().unwrap();
+        assert_eq!(hex::encode(&msg.pack().unwrap()), hex);
+
+        let back: Message = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(msg.pack().unwrap(), back.pack().unwrap());
+        let lower = json!({"messageOctetsHEX": hex.to_lowercase()});
+        let back: MessageOctets = serde_json::from_value(lower).unwrap();
+        assert_eq!(msg.pack().unwrap(), back.0.pack().unwrap());
+    }
+
+    #[test]
+    fn parts() {
+        let header = *response().header();
+        let back: Header = serde_json::from_value(serde_json::to_value(header).unwrap()).unwrap();
+        assert_eq!(header.id, back.id);
+        assert_eq!(header.rd, back.rd);
+        assert_eq!(header.ancount, back.ancount);
+
+        let question: Question =
+            serde_json::from_value(json!({"NAME": "example.com.", "TYPE": 28, "CLASS": 1}))
+                .unwrap();
+        assert_eq!(rr::Type::Aaaa, question.qtype);
+        assert_eq!("example.com", question.domain.to_string());
+
+        let answer = response().answers()[0].clone();
+        let back: Answer = serde_json::from_value(serde_json::to_value(&answer).unwrap()).unwrap();
+        assert_eq!(answer, back);
+
+        let root: Labels = serde_json::from_value(json!(".")).unwrap();
+        assert_eq!(json!("."), serde_json::to_value(root).unwrap());
+    }
+
+    #[test]
+    fn rejects_inconsistent_records() {
+        let record = |length: u16, hex: &str| json!({"NAME": "a.example", "TYPE": 1, "CLASS": 1, "TTL": 60, "RDLENGTH": length, "RDATAHEX": hex});
+        assert!(serde_json::from_value::<Answer>(record(4, "C0000201")).is_ok());
+        assert!(serde_json::from_value::<Answer>(record(5, "C0000201")).is_err());
+        assert!(serde_json::from_value::<Answer>(record(3, "C00002")).is_err());
+        assert!(serde_json::from_value::<Answer>(record(4, "C00002ZZ")).is_err());
+        assert!(serde_json::from_value::<Message>(json!({"messageOctetsHEX": "04D2"})).is_err());
+
+        let missing: Value = json!({"ID": 1, "answerRRs": [record(5, "C0000201")]});
+        assert!(serde_json::from_value::<Message>(missing).is_err());
+    }
+}
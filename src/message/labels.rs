@@ -1,19 +1,104 @@
 use anyhow::{ensure, Result};
-use std::{fmt::Display, io::Write, ops::DerefMut};
+use std::{fmt::Display, io::Write, ops::DerefMut, str::FromStr};
 
-use crate::{errors::DnsError, message::labels::pointer::DomainPointer};
+use crate::{
+    errors::DnsError,
+    message::{labels::pointer::DomainPointer, presentation},
+};
 
 mod pointer;
 
 const TERMINATOR_BYTE: u8 = 0x00;
 const TERMINATOR_BYTE_SIZE: usize = 1;
 const DOMAIN_NAME_LEN_BYTE_SIZE: usize = 1;
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
 
+/// Domain name as a sequence of labels, e.g. `["google", "com"]` for `google.com`.
+///
+/// The root domain has no labels.
 #[derive(Debug, Default, PartialEq)]
 pub struct Labels(Vec<String>);
 
 impl Labels {
-    pub fn unpack(buf: &[u8], ptr: &mut usize) -> Result<Self> {
+    /// Labels of the name, from the leftmost one to the top level domain.
+    pub fn as_slice(&self) -> &[String] {
+        &self.0
+    }
+
+    /// Fully qualified presentation of the name, with the trailing dot: `google.com.` or `.`.
+    pub fn to_fqdn(&self) -> String {
+        format!("{}.", self)
+    }
+
+    /// Presentation of the name relative to `origin`: `@` for `origin` itself, the leading labels
+    /// for names below it and the fully qualified name otherwise.
+    pub fn relative_to(&self, origin: &Labels) -> String {
+        let Some(split) = self.0.len().checked_sub(origin.0.len()) else {
+            return self.to_fqdn();
+        };
+        let (prefix, suffix) = self.0.split_at(split);
+        let within = suffix
+            .iter()
+            .zip(&origin.0)
+            .all(|(label, origin)| label.eq_ignore_ascii_case(origin));
+        match (within, prefix.is_empty()) {
+            (false, _) => self.to_fqdn(),
+            (true, true) => "@".into(),
+            (true, false) => Labels(prefix.to_vec()).to_string(),
+        }
+    }
+
+    /// Parses a name in presentation format, relative to `origin` unless it ends with a dot. `@`
+    /// is `origin` itself, `\.` a dot within a label and `\DDD` any byte, as long as the labels
+    /// remain valid UTF-8: names are held as strings.
+    pub fn parse_relative(s: &str, origin: &Labels) -> Result<Self, DnsError> {
+        match s {
+            "@" => return Ok(origin.clone()),
+            "." => return Ok(Labels::default()),
+            _ => {}
+        }
+        let invalid = || DnsError::InvalidName(s.into());
+        let bytes = s.as_bytes();
+        let (mut labels, mut label, mut absolute) = (Vec::new(), Vec::new(), false);
+        let mut at = 0;
+        while at < bytes.len() {
+            match bytes[at] {
+                b'\\' => {
+                    let (byte, size) =
+                        presentation::escaped(&bytes[at + 1..]).ok_or_else(invalid)?;
+                    label.push(byte);
+                    at += size;
+                }
+                b'.' => {
+                    labels.push(std::mem::take(&mut label));
+                    absolute = at + 1 == bytes.len();
+                }
+                byte => label.push(byte),
+            }
+            at += 1;
+        }
+        if !absolute {
+            labels.push(label);
+        }
+
+        let labels = labels.into_iter().map(String::from_utf8);
+        let mut labels = Labels(labels.collect::<Result<_, _>>().map_err(|_| invalid())?);
+        if !absolute {
+            labels.0.extend(origin.0.iter().cloned());
+        }
+        let valid = labels
+            .0
+            .iter()
+            .all(|label| !label.is_empty() && label.len() <= MAX_LABEL_LEN);
+        if !valid || labels.len() > MAX_NAME_LEN {
+            return Err(invalid());
+        }
+        Ok(labels)
+    }
+
+    /// Reads a possibly compressed name starting at `ptr`, advancing `ptr` past it.
+    pub(crate) fn unpack(buf: &[u8], ptr: &mut usize) -> Result<Self> {
         let mut words = Vec::new();
         Labels::scan(&mut words, buf, ptr)?;
         Ok(Labels(words))
@@ -25,7 +110,7 @@ impl Labels {
     {
         loop {
             let mut at: usize = *ptr;
-            let letter: u8 = buf[at];
+            let letter: u8 = *buf.get(at).ok_or(DnsError::InvalidEncoding { at })?;
 
             if letter == TERMINATOR_BYTE {
                 *ptr += TERMINATOR_BYTE_SIZE;
@@ -33,20 +118,24 @@ impl Labels {
             }
             if DomainPointer::test(letter) {
                 let ptr = DomainPointer::try_from((buf, ptr))?;
+                // Pointers may only refer to prior occurrences, which rules out loops.
+                ensure!(*ptr < at, DnsError::InvalidEncoding { at });
                 return Labels::scan(words, buf, ptr);
             }
 
             at += DOMAIN_NAME_LEN_BYTE_SIZE;
             let word_len = letter as usize;
+            ensure!(
+                at + word_len < buf.len(),
+                DnsError::InvalidEncoding { at: at + word_len }
+            );
             words.push(String::from_utf8_lossy(&buf[at..at + word_len]).into());
             at += word_len;
-
-            ensure!(at < buf.len(), DnsError::InvalidEncoding { at });
             *ptr = at;
         }
     }
 
-    pub fn pack(&self, buf: &mut [u8]) -> Result<()> {
+    pub(crate) fn pack(&self, buf: &mut [u8]) -> Result<()> {
         ensure!(
             buf.len() == self.len(),
             DnsError::BufLenNotEq {
@@ -67,7 +156,8 @@ impl Labels {
         Ok(())
     }
 
-    pub fn len(&self) -> usize {
+    /// Size of the uncompressed wire encoding of the name.
+    pub(crate) fn len(&self) -> usize {
         self.0
             .iter()
             .map(|s| s.len() + DOMAIN_NAME_LEN_BYTE_SIZE)
@@ -76,9 +166,37 @@ impl Labels {
     }
 }
 
+/// Labels joined with dots, without the trailing one. Dots within labels and other special
+/// characters are escaped with a backslash, unprintable bytes as `\DDD`.
 impl Display for Labels {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0.join("."))
+        for (i, label) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            for &byte in label.as_bytes() {
+                match byte {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                        write!(f, "\\{}", byte as char)?
+                    }
+                    0x21..=0x7E => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a dotted name such as `google.com` or `google.com.`, `.` being the root.
+impl FromStr for Labels {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Ok(Labels::default());
+        }
+        Labels::parse_relative(s, &Labels::default())
     }
 }
 
@@ -121,6 +239,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn from_str() {
+        assert_eq!(
+            vec!["google", "com"],
+            "google.com".parse::<Labels>().unwrap().0
+        );
+        assert_eq!(
+            vec!["google", "com"],
+            "google.com.".parse::<Labels>().unwrap().0
+        );
+        assert_eq!(1, ".".parse::<Labels>().unwrap().len());
+        assert!("google..com".parse::<Labels>().is_err());
+        assert!(format!("{}.com", "a".repeat(64)).parse::<Labels>().is_err());
+    }
+
+    #[test]
+    fn parse_relative() {
+        let origin = "example.com".parse().unwrap();
+        let parse = |s| Labels::parse_relative(s, &origin).unwrap().0;
+        assert_eq!(vec!["www", "example", "com"], parse("www"));
+        assert_eq!(vec!["www", "example", "org"], parse("www.example.org."));
+        assert_eq!(vec!["example", "com"], parse("@"));
+        assert_eq!(vec!["a.b", "example", "com"], parse(r"a\.b"));
+        assert_eq!(vec!["a b", "example", "com"], parse(r"a\032b"));
+        assert!(Labels::parse_relative(r"a\", &origin).is_err());
+        assert!(Labels::parse_relative("a..", &origin).is_err());
+        assert_eq!(vec!["é", "example", "com"], parse(r"\195\169"));
+        // Escapes are not allowed to make up invalid UTF-8.
+        assert!(Labels::parse_relative(r"a\200b", &origin).is_err());
+    }
+
+    #[test]
+    fn relative_to() {
+        let origin = "example.com".parse().unwrap();
+        let relative = |s: &str| s.parse::<Labels>().unwrap().relative_to(&origin);
+        assert_eq!("www", relative("www.Example.com"));
+        assert_eq!(r"a\.b.c", relative(r"a\.b.c.example.com"));
+        assert_eq!("@", relative("EXAMPLE.com"));
+        assert_eq!("example.org.", relative("example.org"));
+        assert_eq!("com.", relative("com"));
+        assert_eq!(
+            "www",
+            relative(&Labels::parse_relative("www", &origin).unwrap().to_string())
+        );
+    }
+
+    #[test]
+    fn display_escapes() {
+        let labels = Labels(vec!["a.b".into(), "c d\\".into(), "é".into()]);
+        assert_eq!(r"a\.b.c\032d\\.\195\169", labels.to_string());
+        assert_eq!(labels, labels.to_string().parse().unwrap());
+    }
+
     #[test]
     fn pack() {
         let raw = b"\x06google\x03com\x00";
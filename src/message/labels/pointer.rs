@@ -10,7 +10,7 @@ use crate::errors::DnsError;
 const MASK_U8: u8 = 0b1100_0000;
 const MASK_U16: u16 = 0b1100_0000_0000_0000;
 
-pub struct DomainPointer(usize);
+pub(crate) struct DomainPointer(usize);
 
 impl DomainPointer {
     pub fn test(word: u8) -> bool {
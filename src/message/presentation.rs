@@ -0,0 +1,745 @@
+//! Presentation format (RFC 1035 section 5.1) of messages, as dig prints them, and the tokens,
+//! escapes and TTLs it shares with master files.
+
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::errors::DnsError;
+
+use super::{
+    answer::Answer,
+    edns::{ede::ExtendedError, Edns, EdnsOption},
+    header::{
+        AuthenticData, AuthoritativeAnswer, CheckingDisabled, Indicator, RecursionAvailable,
+        RecursionDesired, Truncation,
+    },
+    labels::Labels,
+    question::Question,
+    rr, Message,
+};
+
+/// Piece of a line, escapes left in place.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+}
+
+/// Splits `line` into tokens, up to the comment starting at the first unquoted `;`.
+pub(crate) fn tokenize(line: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        match c {
+            ';' => break,
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => {
+                            text.push(c);
+                            if c == '\\' {
+                                text.extend(chars.next().map(|(_, c)| c));
+                            }
+                        }
+                        None => bail!(DnsError::InvalidSyntax(format!(
+                            "unterminated string `{}`",
+                            &line[at..]
+                        ))),
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut text = String::from(c);
+                if c == '\\' {
+                    text.extend(chars.next().map(|(_, c)| c));
+                }
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ';' | '(' | ')' | '"') {
+                        break;
+                    }
+                    chars.next();
+                    text.push(c);
+                    if c == '\\' {
+                        text.extend(chars.next().map(|(_, c)| c));
+                    }
+                }
+                tokens.push(Token::Word(text));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Byte of the escape following a backslash at the start of `rest`, `\DDD` or `\X`, and the
+/// number of bytes it takes.
+pub(crate) fn escaped(rest: &[u8]) -> Option<(u8, usize)> {
+    match rest {
+        [a, b, c, ..] if [a, b, c].iter().all(|d| d.is_ascii_digit()) => {
+            let value = [a, b, c]
+                .iter()
+                .fold(0u16, |value, d| value * 10 + u16::from(**d - b'0'));
+            Some((u8::try_from(value).ok()?, 3))
+        }
+        [byte, ..] => Some((*byte, 1)),
+        [] => None,
+    }
+}
+
+/// Bytes of a character string, with its escapes resolved.
+pub(crate) fn unescape(text: &str) -> Result<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        if bytes[at] == b'\\' {
+            let (byte, size) = escaped(&bytes[at + 1..])
+                .ok_or_else(|| DnsError::InvalidSyntax(format!("bad escape in `{}`", text)))?;
+            unescaped.push(byte);
+            at += 1 + size;
+        } else {
+            unescaped.push(bytes[at]);
+            at += 1;
+        }
+    }
+    Ok(unescaped)
+}
+
+/// Escapes a character string: quotes and backslashes with a backslash, unprintable bytes as
+/// `\DDD`.
+pub(crate) fn escape(string: &[u8]) -> String {
+    string
+        .iter()
+        .map(|&byte| match byte {
+            b'"' | b'\\' => format!("\\{}", byte as char),
+            0x20..=0x7E => (byte as char).to_string(),
+            _ => format!("\\{:03}", byte),
+        })
+        .collect()
+}
+
+/// Parses a TTL given in seconds, or in units as in `1w2d`, `1h30m` or `90s`.
+pub(crate) fn parse_ttl(text: &str) -> Result<u32, DnsError> {
+    let invalid = || DnsError::InvalidTtl(text.into());
+    let (mut ttl, mut number, mut units) = (0u32, None::<u32>, false);
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(
+                number
+                    .unwrap_or_default()
+                    .checked_mul(10)
+                    .and_then(|number| number.checked_add(digit))
+                    .ok_or_else(invalid)?,
+            );
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let seconds = number.take().ok_or_else(invalid)?.checked_mul(unit);
+        ttl = seconds
+            .and_then(|seconds| ttl.checked_add(seconds))
+            .ok_or_else(invalid)?;
+        units = true;
+    }
+    match (number, units) {
+        (Some(seconds), false) => Ok(seconds),
+        (None, true) => Ok(ttl),
+        _ => Err(invalid()),
+    }
+}
+
+/// Fields of a record or question, consumed from left to right; parentheses are ignored, as they
+/// only let fields span lines.
+pub(crate) struct Fields<'a> {
+    tokens: Vec<&'a Token>,
+    at: usize,
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn new(tokens: &'a [Token]) -> Self {
+        Fields {
+            tokens: tokens
+                .iter()
+                .filter(|token| !matches!(token, Token::Open | Token::Close))
+                .collect(),
+            at: 0,
+        }
+    }
+
+    /// The next field if it is unquoted, without consuming it.
+    pub(crate) fn peek(&self) -> Option<&'a str> {
+        match self.tokens.get(self.at) {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.at == self.tokens.len()
+    }
+
+    /// The next field, which must be unquoted.
+    pub(crate) fn word(&mut self) -> Result<&'a str, DnsError> {
+        let word = match self.tokens.get(self.at) {
+            Some(Token::Word(word)) => word,
+            Some(token) => return Err(DnsError::InvalidSyntax(format!("unexpected {:?}", token))),
+            None => return Err(DnsError::InvalidSyntax("missing field".into())),
+        };
+        self.at += 1;
+        Ok(word)
+    }
+
+    /// The next field, quoted or not, as a character string.
+    pub(crate) fn string(&mut self) -> Result<Vec<u8>> {
+        let text = match self.tokens.get(self.at) {
+            Some(Token::Word(text) | Token::Quoted(text)) => text,
+            _ => bail!(DnsError::InvalidSyntax("missing string".into())),
+        };
+        self.at += 1;
+        unescape(text)
+    }
+
+    pub(crate) fn parse<T: FromStr>(&mut self) -> Result<T, DnsError> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| DnsError::InvalidSyntax(format!("bad field `{}`", word)))
+    }
+
+    pub(crate) fn ttl(&mut self) -> Result<u32, DnsError> {
+        parse_ttl(self.word()?)
+    }
+
+    /// The next field as a name, relative to `origin` unless it ends with a dot.
+    pub(crate) fn name(&mut self, origin: &Labels) -> Result<Labels, DnsError> {
+        Labels::parse_relative(self.word()?, origin)
+    }
+
+    /// TTL and class, both optional and in either order, followed by the type: what comes after
+    /// the owner of a record.
+    pub(crate) fn record_type(
+        &mut self,
+    ) -> Result<(Option<u32>, Option<rr::Class>, rr::Type), DnsError> {
+        let (mut ttl, mut class) = (None, None);
+        loop {
+            let word = self.word()?;
+            if ttl.is_none() && word.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(word)?);
+            } else if let (None, Ok(parsed)) = (class, word.parse()) {
+                class = Some(parsed);
+            } else {
+                return Ok((ttl, class, word.parse()?));
+            }
+        }
+    }
+
+    /// The fields not consumed yet, as tokens.
+    pub(crate) fn rest(self) -> impl Iterator<Item = &'a Token> {
+        self.tokens.into_iter().skip(self.at)
+    }
+
+    /// Fails unless every field was consumed.
+    pub(crate) fn end(&self) -> Result<(), DnsError> {
+        match self.tokens.get(self.at) {
+            None => Ok(()),
+            Some(Token::Word(text) | Token::Quoted(text)) => Err(DnsError::InvalidSyntax(format!(
+                "unexpected field `{}`",
+                text
+            ))),
+            Some(token) => Err(DnsError::InvalidSyntax(format!("unexpected {:?}", token))),
+        }
+    }
+}
+
+/// Formatted the way dig prints it, e.g.
+///
+/// ```text
+/// ;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 1234
+/// ;; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 0
+///
+/// ;; QUESTION SECTION:
+/// ;example.com. IN A
+///
+/// ;; ANSWER SECTION:
+/// example.com. 300 IN A 192.0.2.1
+/// ```
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let header = self.counted_header();
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            header.opcode, header.rcode, header.id
+        )?;
+        let flags = FLAGS
+            .iter()
+            .filter(|(_, get, _)| get(self))
+            .map(|(flag, _, _)| *flag)
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            flags.join(" "),
+            header.qdcount,
+            header.ancount,
+            header.nscount,
+            header.arcount
+        )?;
+
+        if let Some(edns) = &self.edns {
+            write!(f, "\n\n;; OPT PSEUDOSECTION:")?;
+            let flags = if edns.dnssec_ok { " do" } else { "" };
+            write!(
+                f,
+                "\n; EDNS: version: {}, flags:{}; udp: {}",
+                edns.version, flags, edns.udp_payload_size
+            )?;
+            for option in &edns.options {
+                match ExtendedError::from_option(option) {
+                    Some(ede) => write!(f, "\n; EDE: {}", ede)?,
+                    None => {
+                        write!(f, "\n; OPTION {}: ", option.code)?;
+                        option
+                            .data
+                            .iter()
+                            .try_for_each(|byte| write!(f, "{:02X}", byte))?
+                    }
+                }
+            }
+        }
+
+        write!(f, "\n\n;; QUESTION SECTION:")?;
+        for question in &self.questions {
+            write!(f, "\n;{}", question)?;
+        }
+        for (name, records) in [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.additionals),
+        ] {
+            if !records.is_empty() {
+                write!(f, "\n\n;; {} SECTION:", name)?;
+            }
+            for record in records {
+                write!(f, "\n{}", record)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+type Flag = (&'static str, fn(&Message) -> bool, fn(&mut Message));
+
+/// Header flags in the order dig prints them.
+const FLAGS: [Flag; 7] = [
+    (
+        "qr",
+        |msg| msg.header.qr == Indicator::Response,
+        |msg| msg.header.qr = Indicator::Response,
+    ),
+    (
+        "aa",
+        |msg| msg.header.aa == AuthoritativeAnswer::Yes,
+        |msg| msg.header.aa = AuthoritativeAnswer::Yes,
+    ),
+    (
+        "tc",
+        |msg| msg.header.tc == Truncation::Yes,
+        |msg| msg.header.tc = Truncation::Yes,
+    ),
+    (
+        "rd",
+        |msg| msg.header.rd == RecursionDesired::Yes,
+        |msg| msg.header.rd = RecursionDesired::Yes,
+    ),
+    (
+        "ra",
+        |msg| msg.header.ra == RecursionAvailable::Yes,
+        |msg| msg.header.ra = RecursionAvailable::Yes,
+    ),
+    (
+        "ad",
+        |msg| msg.header.ad == AuthenticData::Yes,
+        |msg| msg.header.ad = AuthenticData::Yes,
+    ),
+    (
+        "cd",
+        |msg| msg.header.cd == CheckingDisabled::Yes,
+        |msg| msg.header.cd = CheckingDisabled::Yes,
+    ),
+];
+
+/// Parses what `Display` prints. Other comment lines, such as dig's `;; Got answer:`, are
+/// skipped, and section counts are derived from the records.
+impl FromStr for Message {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut msg = Message::default();
+        msg.header.qr = Indicator::Query;
+        let mut section = None;
+        for (number, line) in s.lines().enumerate() {
+            msg.parse_line(line, &mut section)
+                .with_context(|| format!("Line {}: `{}`", number + 1, line))?;
+        }
+        Ok(msg)
+    }
+}
+
+impl Message {
+    fn parse_line(&mut self, line: &str, section: &mut Option<String>) -> Result<()> {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix(";; ->>HEADER<<- ") {
+            for field in header.split(", ") {
+                match field.split_once(": ") {
+                    Some(("opcode", opcode)) => self.header.opcode = opcode.parse()?,
+                    Some(("status", status)) => self.header.rcode = status.parse()?,
+                    Some(("id", id)) => self.header.id = id.parse()?,
+                    _ => bail!(DnsError::InvalidSyntax(format!("header field `{}`", field))),
+                }
+            }
+        } else if let Some(flags) = line.strip_prefix(";; flags:") {
+            let flags = flags.split(';').next().unwrap_or_default();
+            for flag in flags.split_whitespace() {
+                let (_, _, set) = FLAGS
+                    .iter()
+                    .find(|(name, _, _)| *name == flag)
+                    .ok_or_else(|| DnsError::InvalidSyntax(format!("flag `{}`", flag)))?;
+                set(self);
+            }
+        } else if line == ";; OPT PSEUDOSECTION:" {
+            self.edns = Some(Edns::default());
+        } else if let Some(fields) = line.strip_prefix("; EDNS: ") {
+            let edns = self.edns.get_or_insert_with(Edns::default);
+            for field in fields.split([';', ',']).map(str::trim) {
+                match field.split_once(':') {
+                    Some(("version", version)) => edns.version = version.trim().parse()?,
+                    Some(("flags", flags)) => edns.dnssec_ok = flags.trim() == "do",
+                    Some(("udp", udp)) => edns.udp_payload_size = udp.trim().parse()?,
+                    _ => bail!(DnsError::InvalidSyntax(format!("EDNS field `{}`", field))),
+                }
+            }
+        } else if let Some(ede) = line.strip_prefix("; EDE: ") {
+            let edns = self.edns.get_or_insert_with(Edns::default);
+            edns.options.push(parse_extended_error(ede)?.into());
+        } else if let Some(option) = line.strip_prefix("; OPTION ") {
+            let (code, data) = option
+                .split_once(':')
+                .ok_or_else(|| DnsError::InvalidSyntax(format!("option `{}`", option)))?;
+            let edns = self.edns.get_or_insert_with(Edns::default);
+            edns.options.push(EdnsOption {
+                code: code.parse()?,
+                data: unhex(data.trim())?,
+            });
+        } else if let Some(name) = line
+            .strip_prefix(";; ")
+            .and_then(|line| line.strip_suffix(" SECTION:"))
+        {
+            *section = Some(name.to_string());
+        } else if section.as_deref() == Some("QUESTION") {
+            if !line.is_empty() {
+                self.questions.push(line.parse()?);
+            }
+        } else if !line.is_empty() && !line.starts_with(';') {
+            let record = line.parse()?;
+            match section.as_deref() {
+                Some("ANSWER") => self.answers.push(record),
+                Some("AUTHORITY") => self.authorities.push(record),
+                Some("ADDITIONAL") => self.additionals.push(record),
+                _ => bail!(DnsError::InvalidSyntax(
+                    "record outside of a section".into()
+                )),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads back an Extended DNS Error as printed: `22 (No Reachable Authority): (timed out)`.
+fn parse_extended_error(text: &str) -> Result<ExtendedError> {
+    let invalid = || DnsError::InvalidSyntax(format!("extended error `{}`", text));
+    let (code, rest) = text.split_once(' ').ok_or_else(invalid)?;
+    let code: u16 = code.parse().map_err(|_| invalid())?;
+    let extra_text = match rest.split_once("): (") {
+        Some((_, extra)) => extra.strip_suffix(')').ok_or_else(invalid)?,
+        None => "",
+    };
+    Ok(ExtendedError::new(code.into(), extra_text))
+}
+
+/// Bytes of a hexadecimal string, in either case.
+pub(crate) fn unhex(text: &str) -> Result<Vec<u8>> {
+    let pairs = text.as_bytes().chunks_exact(2);
+    ensure!(
+        pairs.remainder().is_empty(),
+        DnsError::InvalidSyntax(format!("odd hexadecimal `{}`", text))
+    );
+    pairs
+        .map(|pair| {
+            ensure!(
+                pair.iter().all(u8::is_ascii_hexdigit),
+                DnsError::InvalidSyntax(format!("bad hexadecimal `{}`", text))
+            );
+            Ok(u8::from_str_radix(std::str::from_utf8(pair)?, 16)?)
+        })
+        .collect()
+}
+
+/// Parses a question as dig prints it, `google.com. IN A`, the leading `;` being optional. The
+/// class defaults to `IN`.
+impl FromStr for Question {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s.strip_prefix(';').unwrap_or(s))?;
+        let mut fields = Fields::new(&tokens);
+        let domain = fields.name(&Labels::default())?;
+        let (ttl, qclass, qtype) = fields.record_type()?;
+        ensure!(
+            ttl.is_none(),
+            DnsError::InvalidSyntax("questions have no TTL".into())
+        );
+        fields.end()?;
+        Ok(Question {
+            domain,
+            qtype,
+            qclass: qclass.unwrap_or_default(),
+        })
+    }
+}
+
+/// Parses a record, `google.com. 300 IN A 8.8.8.8`, names being absolute whether or not they end
+/// with a dot. The TTL accepts units and may follow the class, which defaults to `IN`.
+impl FromStr for Answer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut fields = Fields::new(&tokens);
+        let origin = Labels::default();
+        let name = fields.name(&origin)?;
+        let (ttl, class, rtype) = fields.record_type()?;
+        let ttl = ttl.ok_or_else(|| DnsError::InvalidSyntax(format!("missing TTL in `{}`", s)))?;
+        let rdata = super::rdata::RData::from_fields(rtype, fields, &origin)?;
+        Ok(Answer::new(
+            name,
+            rtype,
+            class.unwrap_or_default(),
+            ttl,
+            rdata.pack()?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::{
+        edns::ede::InfoCode,
+        header::ResponseCode,
+        rdata::{RData, Soa},
+    };
+
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let word = |text: &str| Token::Word(text.into());
+        assert_eq!(
+            vec![
+                word("@"),
+                word("IN"),
+                word("TXT"),
+                Token::Open,
+                Token::Quoted(r#"a \"b\" ; c"#.into()),
+                word(r"d\;e"),
+                Token::Close,
+            ],
+            tokenize(r#"@ IN TXT ("a \"b\" ; c" d\;e) ; comment"#).unwrap()
+        );
+        assert!(tokenize(r#"TXT "open"#).is_err());
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(b"a;b\x00\xFF".to_vec(), unescape(r"a\;b\000\255").unwrap());
+        assert!(unescape(r"\256").is_err());
+        assert!(unescape("trailing\\").is_err());
+        assert_eq!(r#"say \"hi\"\009"#, escape(b"say \"hi\"\t"));
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(vec![0xC0, 0x00, 0x02, 0xAB], unhex("C00002ab").unwrap());
+        assert!(unhex("").unwrap().is_empty());
+        for invalid in ["C00", "+1", "0x", "é1", "1é"] {
+            assert!(unhex(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn ttls() {
+        assert_eq!(300, parse_ttl("300").unwrap());
+        assert_eq!(5400, parse_ttl("1h30m").unwrap());
+        assert_eq!(8 * 86400 + 2, parse_ttl("1W1d2S").unwrap());
+        for invalid in ["", "h", "1h30", "1x", "-1", "4294967296", "7102w"] {
+            assert!(parse_ttl(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn answers() {
+        let answer: Answer = "example.com. 1h IN A 192.0.2.1".parse().unwrap();
+        assert_eq!("example.com", answer.name().to_string());
+        assert_eq!(3600, answer.ttl());
+        assert_eq!(RData::A([192, 0, 2, 1].into()), answer.rdata().unwrap());
+        assert_eq!("example.com. 3600 IN A 192.0.2.1", answer.to_string());
+        assert_eq!(answer, "example.com IN 3600 A 192.0.2.1".parse().unwrap());
+
+        let soa: Answer =
+            "example.com. 300 IN SOA ns.example.com. admin.example.com. ( 1 2h 1h 2w 5m )"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            RData::Soa(Soa {
+                mname: "ns.example.com".parse().unwrap(),
+                rname: "admin.example.com".parse().unwrap(),
+                serial: 1,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 300,
+            }),
+            soa.rdata().unwrap()
+        );
+
+        assert!("example.com. IN A 192.0.2.1".parse::<Answer>().is_err());
+        assert!("example.com. 300 IN A 192.0.2.1 extra"
+            .parse::<Answer>()
+            .is_err());
+    }
+
+    #[test]
+    fn answers_round_trip() {
+        for record in [
+            "example.com. 300 IN A 192.0.2.1",
+            "example.com. 300 IN AAAA 2001:db8::1",
+            "example.com. 300 IN NS ns\\.1.example.com.",
+            "www.example.com. 300 IN CNAME example.com.",
+            "1.2.0.192.in-addr.arpa. 300 IN PTR example.com.",
+            "example.com. 300 IN MX 10 mail.example.com.",
+            "example.com. 300 IN SOA ns.example.com. admin.example.com. 1 7200 3600 1209600 300",
+            r#"example.com. 300 IN TXT "v=spf1 -all" "say \"hi\"\009""#,
+            "_sip._udp.example.com. 300 CH SRV 1 2 5060 sip.example.com.",
+            "example.com. 0 IN OPT \\# 4 000A0000",
+            "example.com. 0 IN OPT \\# 0",
+        ] {
+            let answer: Answer = record.parse().unwrap();
+            assert_eq!(record, answer.to_string());
+        }
+
+        let generic: Answer = "example.com. 300 IN A \\# 4 C0 000201".parse().unwrap();
+        assert_eq!("example.com. 300 IN A 192.0.2.1", generic.to_string());
+        assert!("example.com. 300 IN A \\# 3 C00002"
+            .parse::<Answer>()
+            .is_err());
+        assert!("example.com. 300 IN A \\# 5 C0000201"
+            .parse::<Answer>()
+            .is_err());
+    }
+
+    #[test]
+    fn questions() {
+        let question: Question = ";example.com. IN MX".parse().unwrap();
+        assert_eq!(rr::Type::Mx, question.qtype);
+        assert_eq!(question, "example.com MX".parse().unwrap());
+        assert_eq!(question, "example.com. TYPE15".parse().unwrap());
+        assert_eq!(question, question.to_string().parse().unwrap());
+        assert!("example.com. 300 IN MX".parse::<Question>().is_err());
+        assert!("example.com. BOGUS".parse::<Question>().is_err());
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let query = Message::query("example.com".parse().unwrap(), rr::Type::A)
+            .id(1234)
+            .recursion_desired()
+            .edns(Edns::default())
+            .build();
+        let ns = "example.com. 300 IN NS ns.example.com.";
+        let glue = "ns.example.com. 300 IN A 192.0.2.53";
+        let response = Message::response(&query)
+            .recursion_available()
+            .rcode(ResponseCode::BadCookie)
+            .answer("example.com. 300 IN A 192.0.2.1".parse().unwrap())
+            .authority(ns.parse().unwrap())
+            .additional(glue.parse().unwrap())
+            .edns(
+                Edns::default()
+                    .with_option(
+                        ExtendedError::new(InfoCode::NoReachableAuthority, "timed out").into(),
+                    )
+                    .with_option(EdnsOption {
+                        code: 10,
+                        data: vec![0xAB, 0xCD],
+                    }),
+            )
+            .build();
+
+        let text = response.to_string();
+        assert_eq!(
+            format!(
+                "{}\n{}\n\n{}\n{}\n{}\n{}\n\n{}\n{}\n\n{}\n{}\n\n{}\n{}\n\n{}\n{}",
+                ";; ->>HEADER<<- opcode: QUERY, status: BADCOOKIE, id: 1234",
+                ";; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 1, ADDITIONAL: 2",
+                ";; OPT PSEUDOSECTION:",
+                "; EDNS: version: 0, flags:; udp: 1232",
+                "; EDE: 22 (No Reachable Authority): (timed out)",
+                "; OPTION 10: ABCD",
+                ";; QUESTION SECTION:",
+                ";example.com. IN A",
+                ";; ANSWER SECTION:",
+                "example.com. 300 IN A 192.0.2.1",
+                ";; AUTHORITY SECTION:",
+                ns,
+                ";; ADDITIONAL SECTION:",
+                glue,
+            ),
+            text
+        );
+
+        let parsed: Message = format!(";; Got answer:\n{}\n", text).parse().unwrap();
+        assert_eq!(text, parsed.to_string());
+        assert_eq!(response.pack().unwrap(), parsed.pack().unwrap());
+
+        let query_text = query.to_string();
+        assert!(query_text.contains(";; flags: rd; QUERY: 1"));
+        assert_eq!(
+            query.pack().unwrap(),
+            query_text.parse::<Message>().unwrap().pack().unwrap()
+        );
+    }
+
+    #[test]
+    fn messages_with_errors() {
+        let err = ";; QUESTION SECTION:\n;example.com. IN BOGUS"
+            .parse::<Message>()
+            .unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Line 2: `;example.com. IN BOGUS`"));
+        assert!("example.com. 300 IN A 192.0.2.1"
+            .parse::<Message>()
+            .is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{io::Cursor, mem::size_of};
+use std::{fmt::Display, io::Cursor, mem::size_of};
 
 use anyhow::{ensure, Result};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
@@ -7,6 +7,7 @@ use crate::errors::DnsError;
 
 use super::{labels::Labels, rr};
 
+/// Entry of the question section: the name, type and class being asked for.
 #[derive(Debug, Default, PartialEq)]
 pub struct Question {
     pub domain: Labels,
@@ -17,7 +18,18 @@ pub struct Question {
 const METADATA_SIZE: usize = size_of::<rr::Type>() + size_of::<rr::Class>();
 
 impl Question {
-    pub fn unpack(buf: &[u8], ptr: &mut usize) -> Result<Self> {
+    pub(crate) fn unpack(buf: &[u8], ptr: &mut usize) -> Result<Self> {
+        let (domain, qtype, qclass) = Question::unpack_raw(buf, ptr)?;
+        Ok(Question {
+            domain,
+            qtype: qtype.try_into()?,
+            qclass: qclass.try_into()?,
+        })
+    }
+
+    /// Reads the name of the question at `ptr` along with its type and class as numbers, which
+    /// need not be ones the parser models, and advances `ptr` past it.
+    pub(crate) fn unpack_raw(buf: &[u8], ptr: &mut usize) -> Result<(Labels, u16, u16)> {
         let labels = Labels::unpack(buf, ptr)?;
         let mut metadata = Cursor::new(vec![0u8; METADATA_SIZE]);
         let from = *ptr;
@@ -32,14 +44,14 @@ impl Question {
         metadata.get_mut().clone_from_slice(&buf[from..to]);
         *ptr = to;
 
-        Ok(Question {
-            domain: labels,
-            qtype: metadata.read_u16::<BigEndian>()?.try_into()?,
-            qclass: metadata.read_u16::<BigEndian>()?.try_into()?,
-        })
+        Ok((
+            labels,
+            metadata.read_u16::<BigEndian>()?,
+            metadata.read_u16::<BigEndian>()?,
+        ))
     }
 
-    pub fn pack(&self, buf: &mut [u8]) -> Result<()> {
+    pub(crate) fn pack(&self, buf: &mut [u8]) -> Result<()> {
         ensure!(
             buf.len() == self.len(),
             DnsError::BufLenNotEq {
@@ -54,15 +66,28 @@ impl Question {
         cursor.write_u16::<BigEndian>(self.qclass.into())?;
 
         let len = self.domain.len();
-        (&mut buf[len..len + METADATA_SIZE]).copy_from_slice(cursor.get_ref());
+        buf[len..len + METADATA_SIZE].copy_from_slice(cursor.get_ref());
         Ok(())
     }
 
-    pub fn len(&self) -> usize {
+    pub(crate) fn len(&self) -> usize {
         self.domain.len() + METADATA_SIZE
     }
 }
 
+/// Presentation format: `google.com. IN A`.
+impl Display for Question {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.domain.to_fqdn(),
+            self.qclass,
+            self.qtype
+        )
+    }
+}
+
 impl Clone for Question {
     fn clone(&self) -> Self {
         Question {
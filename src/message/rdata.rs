@@ -0,0 +1,447 @@
+use std::{
+    fmt::Display,
+    io::Cursor,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use anyhow::{ensure, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::errors::DnsError;
+
+use super::{
+    labels::Labels,
+    presentation::{self, escape, Fields},
+    rr,
+};
+
+/// Typed RDATA of a resource record.
+///
+/// Types without a dedicated variant, such as OPT, are kept as raw `Unknown` bytes.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum RData {
+    A(Ipv4Addr),
+    Ns(Labels),
+    Cname(Labels),
+    Soa(Soa),
+    Ptr(Labels),
+    Mx {
+        preference: u16,
+        exchange: Labels,
+    },
+    Txt(Vec<Vec<u8>>),
+    Aaaa(Ipv6Addr),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: Labels,
+    },
+    Unknown(Vec<u8>),
+}
+
+/// Start of authority: the primary name server, the responsible mailbox and zone timers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Soa {
+    pub mname: Labels,
+    pub rname: Labels,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+impl RData {
+    /// Reads `len` bytes of RDATA of type `rtype` starting at `at`.
+    ///
+    /// `buf` must be the whole message, since names in RDATA may point anywhere into it.
+    pub(crate) fn unpack(buf: &[u8], at: usize, len: usize, rtype: rr::Type) -> Result<Self> {
+        let end = at + len;
+        ensure!(
+            buf.len() >= end,
+            DnsError::BufLenSmall {
+                min: end,
+                act: buf.len()
+            }
+        );
+        let mut ptr = at;
+        let fixed = |ptr: &mut usize, size: usize| -> Result<Cursor<&[u8]>> {
+            ensure!(*ptr + size <= end, DnsError::InvalidEncoding { at: *ptr });
+            let cursor = Cursor::new(&buf[*ptr..*ptr + size]);
+            *ptr += size;
+            Ok(cursor)
+        };
+
+        let rdata = match rtype {
+            rr::Type::A => {
+                let mut octets = [0u8; 4];
+                std::io::Read::read_exact(&mut fixed(&mut ptr, 4)?, &mut octets)?;
+                RData::A(octets.into())
+            }
+            rr::Type::Aaaa => {
+                let mut octets = [0u8; 16];
+                std::io::Read::read_exact(&mut fixed(&mut ptr, 16)?, &mut octets)?;
+                RData::Aaaa(octets.into())
+            }
+            rr::Type::Ns => RData::Ns(Labels::unpack(buf, &mut ptr)?),
+            rr::Type::Cname => RData::Cname(Labels::unpack(buf, &mut ptr)?),
+            rr::Type::Ptr => RData::Ptr(Labels::unpack(buf, &mut ptr)?),
+            rr::Type::Mx => RData::Mx {
+                preference: fixed(&mut ptr, 2)?.read_u16::<BigEndian>()?,
+                exchange: Labels::unpack(buf, &mut ptr)?,
+            },
+            rr::Type::Soa => {
+                let mname = Labels::unpack(buf, &mut ptr)?;
+                let rname = Labels::unpack(buf, &mut ptr)?;
+                let mut timers = fixed(&mut ptr, 20)?;
+                RData::Soa(Soa {
+                    mname,
+                    rname,
+                    serial: timers.read_u32::<BigEndian>()?,
+                    refresh: timers.read_u32::<BigEndian>()?,
+                    retry: timers.read_u32::<BigEndian>()?,
+                    expire: timers.read_u32::<BigEndian>()?,
+                    minimum: timers.read_u32::<BigEndian>()?,
+                })
+            }
+            rr::Type::Srv => {
+                let mut metadata = fixed(&mut ptr, 6)?;
+                RData::Srv {
+                    priority: metadata.read_u16::<BigEndian>()?,
+                    weight: metadata.read_u16::<BigEndian>()?,
+                    port: metadata.read_u16::<BigEndian>()?,
+                    target: Labels::unpack(buf, &mut ptr)?,
+                }
+            }
+            rr::Type::Txt => {
+                let mut strings = Vec::new();
+                while ptr < end {
+                    let len = buf[ptr] as usize;
+                    ptr += 1;
+                    ensure!(ptr + len <= end, DnsError::InvalidEncoding { at: ptr });
+                    strings.push(buf[ptr..ptr + len].to_vec());
+                    ptr += len;
+                }
+                RData::Txt(strings)
+            }
+            _ => {
+                ptr = end;
+                RData::Unknown(buf[at..end].to_vec())
+            }
+        };
+        ensure!(ptr == end, DnsError::InvalidEncoding { at: ptr });
+        Ok(rdata)
+    }
+
+    /// Wire format of the RDATA, names are never compressed.
+    pub(crate) fn pack(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match self {
+            RData::A(ip) => buf.extend_from_slice(&ip.octets()),
+            RData::Aaaa(ip) => buf.extend_from_slice(&ip.octets()),
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => put_name(&mut buf, name)?,
+            RData::Mx {
+                preference,
+                exchange,
+            } => {
+                buf.write_u16::<BigEndian>(*preference)?;
+                put_name(&mut buf, exchange)?;
+            }
+            RData::Soa(soa) => {
+                put_name(&mut buf, &soa.mname)?;
+                put_name(&mut buf, &soa.rname)?;
+                for timer in [soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum] {
+                    buf.write_u32::<BigEndian>(timer)?;
+                }
+            }
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                buf.write_u16::<BigEndian>(*priority)?;
+                buf.write_u16::<BigEndian>(*weight)?;
+                buf.write_u16::<BigEndian>(*port)?;
+                put_name(&mut buf, target)?;
+            }
+            RData::Txt(strings) => {
+                for string in strings {
+                    buf.push(string.len() as u8);
+                    buf.extend_from_slice(string);
+                }
+            }
+            RData::Unknown(data) => buf.extend_from_slice(data),
+        }
+        Ok(buf)
+    }
+
+    /// Parses RDATA of type `rtype` in presentation format, as `Display` prints it; names are
+    /// absolute whether or not they end with a dot.
+    ///
+    /// Any type also accepts the generic `\\# <length> <hex>` format of RFC 3597.
+    pub fn parse(rtype: rr::Type, s: &str) -> Result<Self> {
+        let tokens = presentation::tokenize(s)?;
+        RData::from_fields(rtype, Fields::new(&tokens), &Labels::default())
+    }
+
+    /// RDATA of type `rtype` out of the remaining `fields`, names relative to `origin`.
+    pub(crate) fn from_fields(
+        rtype: rr::Type,
+        mut fields: Fields,
+        origin: &Labels,
+    ) -> Result<Self> {
+        let rdata = RData::read_fields(rtype, &mut fields, origin)
+            .and_then(|rdata| {
+                fields.end()?;
+                Ok(rdata)
+            })
+            .with_context(|| format!("Invalid {} record data", rtype))?;
+        Ok(rdata)
+    }
+
+    fn read_fields(rtype: rr::Type, fields: &mut Fields, origin: &Labels) -> Result<Self> {
+        if fields.peek() == Some(r"\#") {
+            fields.word()?;
+            let len: usize = fields.parse()?;
+            let mut data = Vec::with_capacity(len);
+            while !fields.is_empty() {
+                data.extend(presentation::unhex(fields.word()?)?);
+            }
+            ensure!(
+                data.len() == len,
+                DnsError::InvalidSyntax(format!("expected {} bytes, got {}", len, data.len()))
+            );
+            return RData::unpack(&data, 0, len, rtype);
+        }
+
+        let rdata = match rtype {
+            rr::Type::A => RData::A(fields.parse()?),
+            rr::Type::Aaaa => RData::Aaaa(fields.parse()?),
+            rr::Type::Ns => RData::Ns(fields.name(origin)?),
+            rr::Type::Cname => RData::Cname(fields.name(origin)?),
+            rr::Type::Ptr => RData::Ptr(fields.name(origin)?),
+            rr::Type::Mx => RData::Mx {
+                preference: fields.parse()?,
+                exchange: fields.name(origin)?,
+            },
+            rr::Type::Soa => RData::Soa(Soa {
+                mname: fields.name(origin)?,
+                rname: fields.name(origin)?,
+                serial: fields.parse()?,
+                refresh: fields.ttl()?,
+                retry: fields.ttl()?,
+                expire: fields.ttl()?,
+                minimum: fields.ttl()?,
+            }),
+            rr::Type::Srv => RData::Srv {
+                priority: fields.parse()?,
+                weight: fields.parse()?,
+                port: fields.parse()?,
+                target: fields.name(origin)?,
+            },
+            rr::Type::Txt => {
+                let mut strings = vec![fields.string()?];
+                while !fields.is_empty() {
+                    strings.push(fields.string()?);
+                }
+                for string in &strings {
+                    ensure!(
+                        string.len() <= u8::MAX as usize,
+                        DnsError::InvalidSyntax(format!(
+                            "character string of {} bytes",
+                            string.len()
+                        ))
+                    );
+                }
+                RData::Txt(strings)
+            }
+            _ => {
+                return Err(DnsError::InvalidSyntax(format!(
+                    "{} data must be in the generic `\\# <length> <hex>` format",
+                    rtype
+                ))
+                .into())
+            }
+        };
+        Ok(rdata)
+    }
+}
+
+fn put_name(buf: &mut Vec<u8>, name: &Labels) -> Result<()> {
+    let from = buf.len();
+    buf.resize(from + name.len(), 0);
+    name.pack(&mut buf[from..])
+}
+
+impl RData {
+    /// Displays the record data with names relative to `origin`, as written in master files.
+    pub fn relative_to<'a>(&'a self, origin: &'a Labels) -> impl Display + 'a {
+        Relative(self, origin)
+    }
+
+    fn present(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        name: &dyn Fn(&Labels) -> String,
+    ) -> std::fmt::Result {
+        match self {
+            RData::A(ip) => write!(f, "{}", ip),
+            RData::Aaaa(ip) => write!(f, "{}", ip),
+            RData::Ns(target) | RData::Cname(target) | RData::Ptr(target) => {
+                write!(f, "{}", name(target))
+            }
+            RData::Mx {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, name(exchange)),
+            RData::Soa(soa) => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                name(&soa.mname),
+                name(&soa.rname),
+                soa.serial,
+                soa.refresh,
+                soa.retry,
+                soa.expire,
+                soa.minimum,
+            ),
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, name(target)),
+            RData::Txt(strings) => {
+                let quoted = strings
+                    .iter()
+                    .map(|string| format!("\"{}\"", escape(string)))
+                    .collect::<Vec<_>>();
+                write!(f, "{}", quoted.join(" "))
+            }
+            RData::Unknown(data) => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                }
+                data.iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+            }
+        }
+    }
+}
+
+impl Display for RData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.present(f, &Labels::to_fqdn)
+    }
+}
+
+struct Relative<'a>(&'a RData, &'a Labels);
+
+impl Display for Relative<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.present(f, &|name| name.relative_to(self.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpack_compressed_name() {
+        // google.com, then a CNAME RDATA `www` + pointer to google.com at offset 0.
+        let raw = b"\x06google\x03com\x00\x03www\xC0\x00";
+        let rdata = RData::unpack(raw, 12, 6, rr::Type::Cname).unwrap();
+        assert_eq!(RData::Cname("www.google.com".parse().unwrap()), rdata);
+        assert_eq!(b"\x03www\x06google\x03com\x00", &rdata.pack().unwrap()[..]);
+    }
+
+    #[test]
+    fn unpack_length_mismatch() {
+        let raw = b"\x7F\x00\x00\x01\x00";
+        assert!(RData::unpack(raw, 0, 5, rr::Type::A).is_err());
+        assert!(RData::unpack(raw, 0, 3, rr::Type::A).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let rdatas = [
+            RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            RData::Aaaa("2001:db8::1".parse().unwrap()),
+            RData::Mx {
+                preference: 10,
+                exchange: "mail.example.com".parse().unwrap(),
+            },
+            RData::Soa(Soa {
+                mname: "ns.example.com".parse().unwrap(),
+                rname: "admin.example.com".parse().unwrap(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 300,
+            }),
+            RData::Srv {
+                priority: 1,
+                weight: 2,
+                port: 443,
+                target: "svc.example.com".parse().unwrap(),
+            },
+            RData::Txt(vec![b"hello".to_vec(), b"world".to_vec()]),
+        ];
+        let types = [
+            rr::Type::A,
+            rr::Type::Aaaa,
+            rr::Type::Mx,
+            rr::Type::Soa,
+            rr::Type::Srv,
+            rr::Type::Txt,
+        ];
+        for (rdata, rtype) in rdatas.iter().zip(types) {
+            let raw = rdata.pack().unwrap();
+            assert_eq!(rdata, &RData::unpack(&raw, 0, raw.len(), rtype).unwrap());
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            "10 mail.example.com.",
+            RData::Mx {
+                preference: 10,
+                exchange: "mail.example.com".parse().unwrap()
+            }
+            .to_string()
+        );
+        assert_eq!(
+            r#""say \"hi\"\009""#,
+            RData::Txt(vec![b"say \"hi\"\t".to_vec()]).to_string()
+        );
+        assert_eq!("\\# 2 0A0B", RData::Unknown(vec![10, 11]).to_string());
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            RData::Mx {
+                preference: 10,
+                exchange: "mail.example.com".parse().unwrap()
+            },
+            RData::parse(rr::Type::Mx, "10 mail.example.com").unwrap()
+        );
+        assert_eq!(
+            RData::Txt(vec![b"a b".to_vec(), b"c".to_vec()]),
+            RData::parse(rr::Type::Txt, r#""a b" c"#).unwrap()
+        );
+        assert!(RData::parse(rr::Type::Txt, &format!("\"{}\"", "a".repeat(256))).is_err());
+        assert!(RData::parse(rr::Type::Mx, "mail.example.com").is_err());
+        assert!(RData::parse(rr::Type::Opt, "0").is_err());
+        assert_eq!(
+            RData::Unknown(vec![0, 10, 0, 0]),
+            RData::parse(rr::Type::Opt, "\\# 4 000A0000").unwrap()
+        );
+    }
+}
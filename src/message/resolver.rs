@@ -1,62 +1,533 @@
-use std::net::{ToSocketAddrs, UdpSocket};
+use std::{
+    future::Future,
+    io,
+    net::ToSocketAddrs,
+    panic,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{bail, Context, Result};
+use tokio::runtime::Runtime;
 
 use crate::{
-    errors::DnsError,
-    message::header::{Indicator, ResponseCode},
+    dnstap::Dnstap,
+    errors::{self, DnsError},
+    message::{
+        edns::{ede, Edns},
+        header::{
+            AuthenticData, AuthoritativeAnswer, Header, RecursionAvailable, ResponseCode,
+            Truncation,
+        },
+    },
+    metrics::Metrics,
 };
 
+use self::{
+    https::{DohMethod, Https},
+    quic::Quic,
+    tls::{Tls, TlsOptions},
+    udp::Udp,
+};
 use super::{question::Question, Message};
 
-const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+pub mod https;
+pub mod quic;
+pub mod tls;
+mod udp;
+
+const ID_SIZE: usize = std::mem::size_of::<u16>();
 
-pub struct Resolver(UdpSocket);
+/// Longest an exchange over an encrypted transport may take, connecting and handshaking included.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Forwards queries to an upstream resolver, over UDP, TLS, HTTPS or QUIC.
+pub struct Resolver {
+    transport: Box<dyn Transport>,
+    dnstap: Option<Arc<Dnstap>>,
+    metrics: Option<Arc<Metrics>>,
+    multi_question: MultiQuestion,
+}
+
+/// What to do with messages holding more than one question, which most upstreams answer with
+/// `FORMERR`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MultiQuestion {
+    /// Look every question up on its own, concurrently, and merge the replies.
+    #[default]
+    Split,
+    /// Forward the message as is, in a single exchange.
+    Forward,
+    /// Answer `FORMERR` without asking the upstream.
+    Reject,
+}
+
+/// Way of exchanging messages with the upstream.
+///
+/// Implementations are shared by concurrent lookups, each exchange must only ever return the
+/// reply to its own query.
+trait Transport: Send + Sync {
+    /// Sends `query` and returns the reply carrying the same ID, logging both to `dnstap`.
+    fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>>;
+
+    /// Address of the upstream, labelling its metrics.
+    fn name(&self) -> String;
+}
 
 impl Resolver {
+    /// Binds an ephemeral local socket and connects it to the upstream at `address`.
     pub fn connect(address: impl ToSocketAddrs) -> Result<Self> {
-        let socket = UdpSocket::bind("localhost:0")?;
-        socket.set_read_timeout(Some(READ_TIMEOUT))?;
-        socket.connect(address)?;
-        Ok(Resolver(socket))
-    }
-
-    pub fn resolve(&self, mut msg: Message) -> Result<Message> {
-        let mut buf = [0u8; 512];
-        let mut template = Message {
-            header: msg.header.clone(),
-            questions: vec![Question::default()],
-            answers: vec![],
+        Ok(Self::new(Box::new(Udp::connect(address)?)))
+    }
+
+    /// Forwards over DNS over TLS (RFC 7858) to the upstream at `address`, `host:port` with the
+    /// port defaulting to 853. The connection is opened on the first query and then reused.
+    pub fn connect_tls(address: &str, options: &TlsOptions) -> Result<Self> {
+        Ok(Self::new(Box::new(Tls::connect(address, options)?)))
+    }
+
+    /// Forwards over DNS over HTTPS (RFC 8484) to the upstream at `url`, e.g.
+    /// `https://dns.google/dns-query`, authenticated as `options` says.
+    pub fn connect_https(url: &str, options: &TlsOptions, method: DohMethod) -> Result<Self> {
+        Ok(Self::new(Box::new(Https::connect(url, options, method)?)))
+    }
+
+    /// Forwards over DNS over QUIC (RFC 9250) to the upstream at `address`, `host:port` with the
+    /// port defaulting to 853. With `zero_rtt`, queries following a reconnection are sent as
+    /// 0-RTT data, which an attacker may replay.
+    pub fn connect_quic(address: &str, options: &TlsOptions, zero_rtt: bool) -> Result<Self> {
+        Ok(Self::new(Box::new(Quic::connect(
+            address, options, zero_rtt,
+        )?)))
+    }
+
+    fn new(transport: Box<dyn Transport>) -> Self {
+        Resolver {
+            transport,
+            dnstap: None,
+            metrics: None,
+            multi_question: MultiQuestion::default(),
+        }
+    }
+
+    /// Logs every message exchanged with the upstream as `FORWARDER_QUERY`/`FORWARDER_RESPONSE`.
+    pub fn with_dnstap(mut self, dnstap: Arc<Dnstap>) -> Self {
+        self.dnstap = Some(dnstap);
+        self
+    }
+
+    /// Records the round trip time of every exchange with the upstream.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets what to do with messages holding more than one question.
+    pub fn with_multi_question(mut self, policy: MultiQuestion) -> Self {
+        self.multi_question = policy;
+        self
+    }
+
+    /// Resolves the questions of `msg` upstream and returns the response to `msg` carrying the
+    /// answer, authority and additional records of the replies.
+    ///
+    /// With several questions, see [`MultiQuestion`], a failed lookup does not abort the others:
+    /// the response carries what the others found, with the response code of the failure. Only
+    /// when every lookup fails is the first error returned.
+    ///
+    /// The response keeps the ID and questions of `msg`, its AA, TC, RA, AD and CD flags and its
+    /// response code come from the upstream replies, see [`merge`]. It carries EDNS, along with
+    /// the Extended DNS Errors of the replies, only if `msg` does, advertising the payload size of
+    /// `msg` both upstream and back. The response may not fit that size, see
+    /// [`Message::truncate`].
+    pub fn resolve(&self, msg: Message) -> Result<Message> {
+        let lookups = match (self.multi_question, msg.questions.len()) {
+            (_, 0) => vec![],
+            (_, 1) | (MultiQuestion::Forward, _) => vec![msg.questions.clone()],
+            (MultiQuestion::Split, _) => msg.questions.iter().map(|q| vec![q.clone()]).collect(),
+            (MultiQuestion::Reject, count) => bail!(DnsError::MultipleQuestions(count)),
+        };
+        // The options of the client, such as its cookie or padding, are not the proxy's to send,
+        // but the replies have to fit what it can receive.
+        let edns = msg.edns.as_ref().map(|edns| Edns {
+            udp_payload_size: edns.udp_payload_size,
+            dnssec_ok: edns.dnssec_ok,
+            ..Edns::default()
+        });
+        let lookup = |questions| self.lookup(msg.header, questions, edns.clone());
+        let replies = match &lookups[..] {
+            [questions] => vec![lookup(questions)],
+            _ => thread::scope(|scope| {
+                let lookups = lookups
+                    .iter()
+                    .map(|questions| scope.spawn(|| lookup(questions)))
+                    .collect::<Vec<_>>();
+                lookups
+                    .into_iter()
+                    .map(|lookup| lookup.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                    .collect()
+            }),
         };
-        template.header.qdcount = 1;
-
-        for question in &msg.questions {
-            template.questions[0] = question.clone();
-            let sent = self.0.send(&template.pack()?)?;
-            ensure!(sent > 0, DnsError::ResolverNoRecv);
-
-            let size = self.0.recv(&mut buf)?;
-            let Message {
-                header,
-                questions: _,
-                mut answers,
-            } = Message::unpack(&buf[0..size])?;
-
-            if header.rcode != ResponseCode::NoError {
-                msg.header.rcode = header.rcode;
-                break;
-            }
-            ensure!(header.ancount > 0, DnsError::ResolverFailed(header));
 
-            let answer = match answers.pop() {
-                Some(answer) => answer,
-                None => bail!(DnsError::ResolverNoAnsw),
+        let mut response = Message::response(&msg).build();
+        response.edns = edns;
+        let mut upstream: Option<Header> = None;
+        let mut failure = None;
+        for reply in replies {
+            let rcode = match reply {
+                Ok(reply) => {
+                    upstream =
+                        Some(upstream.map_or(reply.header, |merged| merge(merged, reply.header)));
+                    response.answers.extend(reply.answers);
+                    response.authorities.extend(reply.authorities);
+                    response.additionals.extend(reply.additionals);
+                    if let (Some(edns), Some(reply)) = (&mut response.edns, reply.edns) {
+                        let errors = reply.options.into_iter();
+                        edns.options
+                            .extend(errors.filter(|option| option.code == ede::OPTION_CODE));
+                    }
+                    reply.header.rcode
+                }
+                Err(err) => {
+                    eprintln!("Cannot look question up: {}", err);
+                    if let Some(metrics) = &self.metrics {
+                        metrics.error(&err);
+                    }
+                    let rcode = errors::rcode(&err);
+                    failure.get_or_insert(err);
+                    rcode
+                }
             };
-            msg.answers.push(answer);
-            msg.header.ancount += 1;
+            if response.header.rcode == ResponseCode::NoError {
+                response.header.rcode = rcode;
+            }
+        }
+
+        match (upstream, failure) {
+            (Some(upstream), _) => {
+                response.header.aa = upstream.aa;
+                response.header.tc = upstream.tc;
+                response.header.ra = upstream.ra;
+                response.header.ad = upstream.ad;
+                response.header.cd = upstream.cd;
+            }
+            (None, Some(failure)) => return Err(failure),
+            (None, None) => {}
+        }
+        // The client is expected to retry over TCP, partial answers are dropped.
+        if response.header.tc == Truncation::Yes {
+            response.answers.clear();
+            response.authorities.clear();
+            response.additionals.clear();
+        }
+        response.header = response.counted_header();
+        Ok(response)
+    }
+
+    /// Sends `questions` upstream in a single exchange, along with `edns`.
+    fn lookup(
+        &self,
+        header: Header,
+        questions: &[Question],
+        edns: Option<Edns>,
+    ) -> Result<Message> {
+        let query = Message {
+            header,
+            questions: questions.to_vec(),
+            edns,
+            ..Default::default()
+        };
+        let reply = self.exchange(&query.pack()?)?;
+        Message::unpack(&reply).context(DnsError::ResolverMalformed)
+    }
+
+    /// Sends `query` upstream as is and returns the raw reply carrying the same ID.
+    ///
+    /// Late replies to earlier queries are skipped.
+    pub fn exchange(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        let reply = self.transport.exchange(query, self.dnstap.as_deref())?;
+        if let Some(metrics) = &self.metrics {
+            metrics.upstream(&self.transport.name(), started.elapsed());
+        }
+        Ok(reply)
+    }
+
+    /// Forwards `query` untouched but for a fresh random ID, and returns the upstream reply
+    /// untouched but for the ID of `query`.
+    ///
+    /// The random ID keeps clients from picking the IDs the proxy uses upstream.
+    pub fn relay(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let id = Header::unpack_id(query)?;
+        let mut forwarded = query.to_vec();
+        forwarded[..ID_SIZE].copy_from_slice(&rand::random::<u16>().to_be_bytes());
+
+        let mut reply = self.exchange(&forwarded)?;
+        reply[..ID_SIZE].copy_from_slice(&id.to_be_bytes());
+        Ok(reply)
+    }
+}
+
+/// Runtime of an encrypted transport, driving its connections in the background.
+fn runtime() -> Result<Runtime> {
+    Ok(tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()?)
+}
+
+/// Runs `exchange` to completion on `runtime`, failing with `TimedOut` after [`TIMEOUT`].
+fn block_on<T>(runtime: &Runtime, exchange: impl Future<Output = Result<T>>) -> Result<T> {
+    runtime.block_on(async {
+        tokio::time::timeout(TIMEOUT, exchange)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "upstream timed out"))?
+    })
+}
+
+/// Folds the header of another upstream reply into `merged`: AA, RA and AD only hold if they hold
+/// for every reply, TC holds if it does for any. CD echoes the query, so any reply will do.
+fn merge(mut merged: Header, reply: Header) -> Header {
+    if reply.aa == AuthoritativeAnswer::No {
+        merged.aa = AuthoritativeAnswer::No;
+    }
+    if reply.ra == RecursionAvailable::No {
+        merged.ra = RecursionAvailable::No;
+    }
+    if reply.ad == AuthenticData::No {
+        merged.ad = AuthenticData::No;
+    }
+    if reply.tc == Truncation::Yes {
+        merged.tc = Truncation::Yes;
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::UdpSocket,
+        thread::{self, JoinHandle},
+    };
+
+    use crate::message::{
+        answer::Answer,
+        builder::MessageBuilder,
+        edns::{
+            ede::{ExtendedError, InfoCode},
+            EdnsOption,
+        },
+        rr,
+    };
+
+    use super::{udp::READ_TIMEOUT, *};
+
+    /// Resolver forwarding to an upstream answering `count` queries with `reply`, except for
+    /// names under `unanswered.` which are never answered.
+    fn upstream(
+        count: usize,
+        reply: impl Fn(MessageBuilder) -> MessageBuilder + Send + 'static,
+    ) -> (Resolver, JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect(socket.local_addr().unwrap()).unwrap();
+        let upstream = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            for _ in 0..count {
+                let (size, peer) = socket.recv_from(&mut buf).unwrap();
+                let query = Message::unpack(&buf[..size]).unwrap();
+                if query.questions()[0]
+                    .domain
+                    .to_string()
+                    .ends_with("unanswered")
+                {
+                    continue;
+                }
+                let answer = Answer::new(
+                    query.questions()[0].domain.clone(),
+                    rr::Type::A,
+                    rr::Class::In,
+                    300,
+                    vec![8, 8, 8, 8],
+                );
+                let response = reply(Message::response(&query).answer(answer)).build();
+                socket.send_to(&response.pack().unwrap(), peer).unwrap();
+            }
+        });
+        (resolver, upstream)
+    }
+
+    fn query(names: &[&str]) -> Message {
+        let mut query = Message::new_query().recursion_desired();
+        for name in names {
+            query = query.question(Question {
+                domain: name.parse().unwrap(),
+                qtype: rr::Type::A,
+                qclass: rr::Class::In,
+            });
         }
+        query.build()
+    }
+
+    #[test]
+    fn keeps_upstream_flags() {
+        let (resolver, upstream) = upstream(1, |reply| {
+            reply.recursion_available().authoritative().authentic_data()
+        });
+        let query = query(&["google.com"]);
+
+        let response = resolver.resolve(query.clone()).unwrap();
+        upstream.join().unwrap();
+        let header = response.header();
+        assert_eq!(query.get_id(), header.id);
+        assert_eq!(RecursionAvailable::Yes, header.ra);
+        assert_eq!(AuthoritativeAnswer::Yes, header.aa);
+        assert_eq!(AuthenticData::Yes, header.ad);
+        assert_eq!(Truncation::No, header.tc);
+        assert_eq!(1, response.answers().len());
+    }
+
+    #[test]
+    fn keeps_upstream_additionals_not_client_options() {
+        let (resolver, answering) = upstream(1, |reply| {
+            let error = ExtendedError::new(InfoCode::StaleAnswer, "");
+            let glue = Answer::new(
+                "ns.google.com".parse().unwrap(),
+                rr::Type::A,
+                rr::Class::In,
+                300,
+                vec![8, 8, 4, 4],
+            );
+            reply
+                .additional(glue)
+                .edns(Edns::default().with_option(error.into()))
+        });
+        let cookie = EdnsOption {
+            code: 10,
+            data: vec![1; 8],
+        };
+        let client = Edns {
+            udp_payload_size: 4096,
+            ..Edns::default()
+        };
+        let with_cookie = Message::new_query()
+            .question(query(&["google.com"]).questions()[0].clone())
+            .edns(client.with_option(cookie))
+            .build();
+
+        let response = resolver.resolve(with_cookie).unwrap();
+        answering.join().unwrap();
+        assert_eq!(1, response.additionals().len());
+        assert_eq!(
+            "ns.google.com",
+            response.additionals()[0].name().to_string()
+        );
+        let edns = response.edns().unwrap();
+        assert_eq!(4096, edns.udp_payload_size);
+        assert_eq!(
+            vec![InfoCode::StaleAnswer],
+            edns.extended_errors()
+                .map(|error| error.info_code)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, edns.options.len());
+
+        let (resolver, answering) = upstream(1, |reply| reply);
+        let response = resolver.resolve(query(&["google.com"])).unwrap();
+        answering.join().unwrap();
+        assert!(response.edns().is_none());
+    }
+
+    #[test]
+    fn merges_flags_of_every_question() {
+        let replies = std::sync::atomic::AtomicUsize::new(0);
+        let (resolver, upstream) = upstream(2, move |reply| {
+            let reply = reply.recursion_available();
+            // Only the first reply is authoritative.
+            match replies.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => reply.authoritative(),
+                _ => reply,
+            }
+        });
+
+        let response = resolver
+            .resolve(query(&["google.com", "example.com"]))
+            .unwrap();
+        upstream.join().unwrap();
+        assert_eq!(RecursionAvailable::Yes, response.header().ra);
+        assert_eq!(AuthoritativeAnswer::No, response.header().aa);
+        assert_eq!(2, response.answers().len());
+    }
+
+    #[test]
+    fn keeps_truncation() {
+        let (resolver, upstream) = upstream(1, |reply| reply.truncated());
+
+        let response = resolver.resolve(query(&["google.com"])).unwrap();
+        upstream.join().unwrap();
+        assert_eq!(Truncation::Yes, response.header().tc);
+        assert!(response.answers().is_empty());
+    }
+
+    #[test]
+    fn failed_lookup_keeps_the_others() {
+        let (resolver, upstream) = upstream(2, |reply| reply);
+        let response = resolver
+            .resolve(query(&["a.unanswered", "google.com"]))
+            .unwrap();
+        upstream.join().unwrap();
+
+        assert_eq!(ResponseCode::ServerFailure, response.header().rcode);
+        assert_eq!(2, response.header().qdcount);
+        assert_eq!(1, response.header().ancount);
+        assert_eq!("google.com", response.answers()[0].name().to_string());
+    }
+
+    #[test]
+    fn every_lookup_failing_is_an_error() {
+        let (resolver, upstream) = upstream(3, |reply| reply);
+        // Timeouts run concurrently, so they do not add up.
+        let started = Instant::now();
+        let err = resolver
+            .resolve(query(&["a.unanswered", "b.unanswered", "c.unanswered"]))
+            .unwrap_err();
+        assert!(started.elapsed() < READ_TIMEOUT * 2);
+        upstream.join().unwrap();
+        assert!(err.is::<std::io::Error>());
+    }
+
+    #[test]
+    fn multiple_questions_forwarded() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect(socket.local_addr().unwrap())
+            .unwrap()
+            .with_multi_question(MultiQuestion::Forward);
+        let upstream = thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (size, peer) = socket.recv_from(&mut buf).unwrap();
+            let query = Message::unpack(&buf[..size]).unwrap();
+            let response = Message::response(&query)
+                .rcode(ResponseCode::FormatError)
+                .build();
+            socket.send_to(&response.pack().unwrap(), peer).unwrap();
+            query.questions().len()
+        });
+
+        let response = resolver
+            .resolve(query(&["google.com", "example.com"]))
+            .unwrap();
+        assert_eq!(2, upstream.join().unwrap());
+        assert_eq!(ResponseCode::FormatError, response.header().rcode);
+    }
 
-        msg.header.qr = Indicator::Response;
-        Ok(msg)
+    #[test]
+    fn multiple_questions_rejected() {
+        let resolver = Resolver::connect("127.0.0.1:9")
+            .unwrap()
+            .with_multi_question(MultiQuestion::Reject);
+        let err = resolver
+            .resolve(query(&["google.com", "example.com"]))
+            .unwrap_err();
+        assert_eq!(ResponseCode::FormatError, errors::rcode(&err));
     }
 }
@@ -0,0 +1,374 @@
+//! DNS over HTTPS upstreams (RFC 8484), over HTTP/2 connections pooled by the HTTP client so
+//! that concurrent queries share a connection.
+
+use anyhow::{ensure, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Bytes,
+    header::{ACCEPT, CONTENT_TYPE},
+    Request, StatusCode, Uri,
+};
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{
+        connect::{HttpConnector, HttpInfo},
+        Client,
+    },
+    rt::TokioExecutor,
+};
+use rustls::pki_types::ServerName;
+use tokio::runtime::Runtime;
+
+use crate::{
+    dnstap::{self, Dnstap},
+    errors::DnsError,
+    message::header::{Header, DNS_HEADER_SIZE},
+};
+
+use super::{
+    tls::{client_config, TlsOptions},
+    Transport, ID_SIZE,
+};
+
+/// Media type of DNS messages in HTTP bodies.
+pub const DNS_MESSAGE: &str = "application/dns-message";
+
+/// How queries are sent to a DNS over HTTPS upstream.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DohMethod {
+    /// `GET` with the query base64url encoded in the `dns` parameter, cacheable by HTTP caches.
+    Get,
+    /// `POST` with the query as body.
+    #[default]
+    Post,
+}
+
+/// DNS over HTTPS to the upstream at `uri`, e.g. `https://dns.google/dns-query`.
+pub(super) struct Https {
+    runtime: Runtime,
+    uri: Uri,
+    method: DohMethod,
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+}
+
+impl Https {
+    pub(super) fn connect(url: &str, options: &TlsOptions, method: DohMethod) -> Result<Self> {
+        let uri: Uri = url.parse().map_err(|_| DnsError::InvalidUrl(url.into()))?;
+        ensure!(
+            uri.scheme_str() == Some("https") && uri.host().is_some(),
+            DnsError::InvalidUrl(url.into())
+        );
+
+        let builder = HttpsConnectorBuilder::new()
+            .with_tls_config(client_config(options)?)
+            .https_only();
+        let builder = match &options.server_name {
+            Some(name) => {
+                let name = ServerName::try_from(name.clone())
+                    .map_err(|_| DnsError::InvalidName(name.into()))?;
+                builder.with_server_name_resolver(FixedServerNameResolver::new(name))
+            }
+            None => builder,
+        };
+        let client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build(builder.enable_http2().build());
+        Ok(Https {
+            runtime: super::runtime()?,
+            uri,
+            method,
+            client,
+        })
+    }
+
+    /// Sends `query` with ID 0 and restores its ID in the reply, leaving both untouched otherwise.
+    async fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+        let id = Header::unpack_id(query)?;
+        // RFC 8484 section 4.1: ID 0 makes identical queries identical requests, hence cacheable.
+        let mut body = query.to_vec();
+        body[..ID_SIZE].fill(0);
+
+        let request = match self.method {
+            DohMethod::Get => {
+                let separator = if self.uri.query().is_some() { '&' } else { '?' };
+                let uri = format!(
+                    "{}{}dns={}",
+                    self.uri,
+                    separator,
+                    URL_SAFE_NO_PAD.encode(&body)
+                );
+                Request::get(uri)
+                    .header(ACCEPT, DNS_MESSAGE)
+                    .body(Full::default())?
+            }
+            DohMethod::Post => Request::post(&self.uri)
+                .header(ACCEPT, DNS_MESSAGE)
+                .header(CONTENT_TYPE, DNS_MESSAGE)
+                .body(Full::new(Bytes::from(body.clone())))?,
+        };
+        let response = self.client.request(request).await?;
+        ensure!(
+            response.status() == StatusCode::OK,
+            DnsError::ResolverHttpStatus(response.status().as_u16())
+        );
+        ensure!(
+            response
+                .headers()
+                .get(CONTENT_TYPE)
+                .is_some_and(|value| value.as_bytes().starts_with(DNS_MESSAGE.as_bytes())),
+            DnsError::ResolverMalformed
+        );
+        let info = response.extensions().get::<HttpInfo>().cloned();
+        let wire = response.into_body().collect().await?.to_bytes();
+
+        if let (Some(dnstap), Some(info)) = (dnstap, info) {
+            for (kind, wire) in [
+                (dnstap::Kind::ForwarderQuery, &body[..]),
+                (dnstap::Kind::ForwarderResponse, &wire[..]),
+            ] {
+                let (local, peer) = (info.local_addr(), info.remote_addr());
+                dnstap.log(kind, dnstap::Protocol::Doh, local, peer, wire);
+            }
+        }
+
+        ensure!(wire.len() >= DNS_HEADER_SIZE, DnsError::ResolverMalformed);
+        let mut reply = wire.to_vec();
+        reply[..ID_SIZE].copy_from_slice(&id.to_be_bytes());
+        Ok(reply)
+    }
+}
+
+impl Transport for Https {
+    fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+        super::block_on(&self.runtime, Https::exchange(self, query, dnstap))
+    }
+
+    fn name(&self) -> String {
+        self.uri.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+    };
+
+    use hyper::{body::Incoming, server::conn::http2, service::service_fn, Method, Response};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::{
+        errors,
+        message::{
+            answer::Answer,
+            header::ResponseCode,
+            resolver::tls::testing::{pki, query, Pki},
+            rr, Message,
+        },
+        Resolver,
+    };
+
+    use super::*;
+
+    /// Request received by the stub upstream.
+    #[derive(Debug, Clone)]
+    struct Seen {
+        method: Method,
+        uri: Uri,
+        content_type: Option<String>,
+        id: u16,
+    }
+
+    type Log = Arc<Mutex<Vec<Seen>>>;
+
+    /// DNS over HTTPS server answering every query with an A record, or with HTTP status 503 for
+    /// names starting with `fail`.
+    ///
+    /// Returns its URL, how many connections it accepted and the requests it received.
+    fn upstream(pki: &Pki) -> (String, Arc<AtomicUsize>, Log) {
+        let acceptor = TlsAcceptor::from(Arc::new(pki.server_config(&[b"h2"])));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let log = Log::default();
+
+        let (counter, seen) = (accepted.clone(), log.clone());
+        thread::spawn(move || {
+            runtime.block_on(async move {
+                loop {
+                    let (tcp, _) = listener.accept().await.unwrap();
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let Ok(stream) = acceptor.accept(tcp).await else {
+                        continue;
+                    };
+                    let seen = seen.clone();
+                    let service = service_fn(move |request| answer(request, seen.clone()));
+                    tokio::spawn(
+                        http2::Builder::new(TokioExecutor::new())
+                            .serve_connection(TokioIo::new(stream), service),
+                    );
+                }
+            })
+        });
+        let url = format!("https://127.0.0.1:{}/dns-query", address.port());
+        (url, accepted, log)
+    }
+
+    async fn answer(
+        request: Request<Incoming>,
+        seen: Log,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        let method = request.method().clone();
+        let uri = request.uri().clone();
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|value| value.to_str().unwrap().to_string());
+        let wire = match method {
+            Method::GET => {
+                let dns = uri.query().and_then(|query| query.strip_prefix("dns="));
+                URL_SAFE_NO_PAD.decode(dns.unwrap()).unwrap()
+            }
+            _ => request.collect().await.unwrap().to_bytes().to_vec(),
+        };
+        seen.lock().unwrap().push(Seen {
+            method,
+            uri,
+            content_type,
+            id: Header::unpack_id(&wire).unwrap(),
+        });
+        let Ok(query) = Message::unpack(&wire) else {
+            return Ok(reply(echo(wire)));
+        };
+
+        let name = query.questions()[0].domain.clone();
+        if name.to_string().starts_with("fail") {
+            let response = Response::builder().status(StatusCode::SERVICE_UNAVAILABLE);
+            return Ok(response.body(Full::default()).unwrap());
+        }
+        let answer = Answer::new(name, rr::Type::A, rr::Class::In, 300, vec![127, 0, 0, 1]);
+        let response = Message::response(&query).answer(answer).build();
+        Ok(reply(response.pack().unwrap()))
+    }
+
+    fn reply(wire: Vec<u8>) -> Response<Full<Bytes>> {
+        let response = Response::builder()
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .body(Full::new(Bytes::from(wire)));
+        response.unwrap()
+    }
+
+    /// `query` turned into its own response by setting QR.
+    fn echo(mut query: Vec<u8>) -> Vec<u8> {
+        query[2] |= 0x80;
+        query
+    }
+
+    fn resolver(pki: &Pki, url: &str, method: DohMethod) -> Resolver {
+        Resolver::connect_https(url, &pki.trusted(), method).unwrap()
+    }
+
+    #[test]
+    fn post() {
+        let pki = pki("doh_post");
+        let (url, _, log) = upstream(&pki);
+        let resolver = resolver(&pki, &url, DohMethod::Post);
+
+        let query = query(&["google.com"]);
+        let response = resolver.resolve(query.clone()).unwrap();
+        assert_eq!(query.get_id(), response.get_id());
+        assert_eq!("google.com", response.answers()[0].name().to_string());
+
+        let seen = log.lock().unwrap()[0].clone();
+        assert_eq!(Method::POST, seen.method);
+        assert_eq!("/dns-query", seen.uri.path());
+        assert_eq!(Some(DNS_MESSAGE.into()), seen.content_type);
+        assert_eq!(0, seen.id);
+    }
+
+    #[test]
+    fn get() {
+        let pki = pki("doh_get");
+        let (url, _, log) = upstream(&pki);
+        let resolver = resolver(&pki, &url, DohMethod::Get);
+
+        let response = resolver.resolve(query(&["google.com"])).unwrap();
+        assert_eq!("google.com", response.answers()[0].name().to_string());
+
+        let seen = log.lock().unwrap()[0].clone();
+        assert_eq!(Method::GET, seen.method);
+        assert_eq!("/dns-query", seen.uri.path());
+        // base64url, without padding.
+        let dns = seen.uri.query().unwrap().strip_prefix("dns=").unwrap();
+        assert!(!dns.contains(['=', '+', '/']));
+        assert_eq!(0, seen.id);
+    }
+
+    #[test]
+    fn relays_unsupported_types_untouched() {
+        let pki = pki("doh_relay");
+        let (url, _, log) = upstream(&pki);
+        let resolver = resolver(&pki, &url, DohMethod::Post);
+
+        // A query for google.com HTTPS (65), a type the typed parser rejects.
+        let mut query = query(&["google.com"]).pack().unwrap();
+        let qtype = query.len() - 4;
+        query[qtype..qtype + 2].copy_from_slice(&65u16.to_be_bytes());
+        assert!(Message::unpack(&query).is_err());
+
+        let reply = resolver.relay(&query).unwrap();
+        assert_eq!(echo(query.clone()), reply);
+        assert_eq!(0, log.lock().unwrap()[0].id);
+    }
+
+    #[test]
+    fn pools_connections() {
+        let pki = pki("doh_pool");
+        let (url, accepted, log) = upstream(&pki);
+        let resolver = resolver(&pki, &url, DohMethod::Post);
+
+        resolver.resolve(query(&["google.com"])).unwrap();
+        let response = resolver
+            .resolve(query(&["example.com", "example.org"]))
+            .unwrap();
+        assert_eq!(2, response.answers().len());
+        assert_eq!(3, log.lock().unwrap().len());
+        assert_eq!(1, accepted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn http_error_is_server_failure() {
+        let pki = pki("doh_error");
+        let (url, _, _) = upstream(&pki);
+        let resolver = resolver(&pki, &url, DohMethod::Post);
+
+        let err = resolver.resolve(query(&["fail.example.com"])).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(DnsError::ResolverHttpStatus(503))
+        ));
+        assert_eq!(ResponseCode::ServerFailure, errors::rcode(&err));
+    }
+
+    #[test]
+    fn https_urls_only() {
+        let options = TlsOptions::default();
+        for url in [
+            "http://dns.google/dns-query",
+            "dns.google",
+            "https:///dns-query",
+        ] {
+            assert!(Https::connect(url, &options, DohMethod::Post).is_err());
+        }
+    }
+}
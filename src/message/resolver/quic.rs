@@ -0,0 +1,162 @@
+//! DNS over QUIC upstreams (RFC 9250): a single connection, opened on the first query and kept
+//! open, carries every query on a stream of its own, so that a lost packet only delays the query
+//! it belongs to.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{Context, Result};
+use quinn::{crypto::rustls::QuicClientConfig, ClientConfig, Connection, Endpoint};
+use tokio::runtime::Runtime;
+
+use crate::{
+    dnstap::{self, Dnstap},
+    errors::DnsError,
+    message::header::Header,
+    tcp,
+};
+
+use super::{
+    tls::{client_config, split_host_port, TlsOptions},
+    Transport, ID_SIZE,
+};
+
+/// ALPN of DNS over QUIC.
+pub const ALPN: &[u8] = b"doq";
+
+/// DNS over QUIC to the upstream at `host:port`.
+pub(super) struct Quic {
+    runtime: Runtime,
+    host: String,
+    port: u16,
+    server_name: String,
+    config: ClientConfig,
+    connection: tokio::sync::Mutex<Option<(Connection, SocketAddr)>>,
+}
+
+impl Quic {
+    pub(super) fn connect(address: &str, options: &TlsOptions, zero_rtt: bool) -> Result<Self> {
+        let (host, port) = split_host_port(address)?;
+        let mut tls = client_config(options)?;
+        tls.alpn_protocols = vec![ALPN.to_vec()];
+        tls.enable_early_data = zero_rtt;
+        let config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(Arc::new(tls))?));
+        Ok(Quic {
+            runtime: super::runtime()?,
+            server_name: options.server_name.clone().unwrap_or_else(|| host.clone()),
+            host,
+            port,
+            config,
+            connection: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Exchanges on the current connection, and on a new one should a reused connection turn
+    /// out to have been closed by the upstream in the meantime, or its 0-RTT data rejected.
+    async fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+        let (connection, local, confirmed) = self.connection().await?;
+        match exchange_on(&connection, local, query, dnstap).await {
+            Err(err) if !confirmed => {
+                eprintln!("Reconnecting to QUIC upstream: {}", err);
+                connection.close(0u32.into(), b"");
+                let (connection, local, _) = self.connection().await?;
+                exchange_on(&connection, local, query, dnstap).await
+            }
+            result => result,
+        }
+    }
+
+    /// The open connection along with its local address, and whether it completed a handshake
+    /// this exchange can trust: a new connection without 0-RTT.
+    async fn connection(&self) -> Result<(Connection, SocketAddr, bool)> {
+        let mut current = self.connection.lock().await;
+        if let Some((connection, local)) = current.as_ref() {
+            if connection.close_reason().is_none() {
+                return Ok((connection.clone(), *local, false));
+            }
+        }
+        let peer = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await?
+            .next()
+            .ok_or_else(|| DnsError::InvalidName(self.host.clone()))?;
+        let local: SocketAddr = match peer {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let endpoint = Endpoint::client(local)?;
+        let local = endpoint.local_addr()?;
+        let connecting = endpoint.connect_with(self.config.clone(), peer, &self.server_name)?;
+        // Without a session ticket allowing early data the handshake completes first.
+        let (connection, confirmed) = match connecting.into_0rtt() {
+            Ok((connection, _)) => (connection, false),
+            Err(connecting) => {
+                let connection = connecting
+                    .await
+                    .with_context(|| format!("QUIC handshake with {} failed", peer))?;
+                (connection, true)
+            }
+        };
+        *current = Some((connection.clone(), local));
+        Ok((connection, local, confirmed))
+    }
+}
+
+impl Transport for Quic {
+    fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+        super::block_on(&self.runtime, Quic::exchange(self, query, dnstap))
+    }
+
+    fn name(&self) -> String {
+        format!("quic://{}:{}", self.host, self.port)
+    }
+}
+
+/// Sends `query` on a stream of its own, with ID 0 as RFC 9250 section 4.2.1 requires, and
+/// restores its ID in the reply.
+async fn exchange_on(
+    connection: &Connection,
+    local: SocketAddr,
+    query: &[u8],
+    dnstap: Option<&Dnstap>,
+) -> Result<Vec<u8>> {
+    let id = Header::unpack_id(query)?;
+    let mut framed = Vec::with_capacity(query.len() + 2);
+    tcp::send(&mut framed, query)?;
+    framed[2..2 + ID_SIZE].fill(0);
+
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&framed).await?;
+    send.finish()?;
+    tap(
+        dnstap,
+        connection,
+        local,
+        dnstap::Kind::ForwarderQuery,
+        &framed[2..],
+    );
+
+    let framed = recv.read_to_end(tcp::MAX_MESSAGE_SIZE + 2).await?;
+    let mut reply = tcp::recv(&mut &framed[..]).context(DnsError::ResolverMalformed)?;
+    Header::unpack_id(&reply).context(DnsError::ResolverMalformed)?;
+    tap(
+        dnstap,
+        connection,
+        local,
+        dnstap::Kind::ForwarderResponse,
+        &reply,
+    );
+    reply[..ID_SIZE].copy_from_slice(&id.to_be_bytes());
+    Ok(reply)
+}
+
+fn tap(
+    dnstap: Option<&Dnstap>,
+    connection: &Connection,
+    local: SocketAddr,
+    kind: dnstap::Kind,
+    wire: &[u8],
+) {
+    if let Some(dnstap) = dnstap {
+        let peer = connection.remote_address();
+        dnstap.log(kind, dnstap::Protocol::Doq, local, peer, wire);
+    }
+}
@@ -0,0 +1,749 @@
+//! DNS over TLS upstreams (RFC 7858): a single connection, opened on the first query and kept
+//! open, carries every query, with replies matched to queries by ID as they come (RFC 7766
+//! pipelining).
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::digest::{digest, SHA256};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::WebPkiSupportedAlgorithms,
+    crypto::{ring::default_provider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+    server::ParsedCertificate,
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use tokio::{
+    io::{AsyncWriteExt, ReadHalf, WriteHalf},
+    net::TcpStream,
+    runtime::Runtime,
+    sync::oneshot,
+    task::AbortHandle,
+};
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+use crate::{
+    dnstap::{self, Dnstap},
+    errors::DnsError,
+    message::header::Header,
+    tcp,
+};
+
+use super::{Transport, ID_SIZE};
+
+/// Port of DNS over TLS servers.
+pub const DEFAULT_PORT: u16 = 853;
+
+/// How a TLS upstream is authenticated.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsOptions {
+    /// Name sent as SNI and checked against the certificate, rather than the host of the
+    /// upstream address, e.g. `dns.google` for `tls://8.8.8.8`.
+    pub server_name: Option<String>,
+    /// Certificates of the upstream, when not empty the upstream is authenticated by its
+    /// certificate matching one of them rather than by CAs (RFC 7858 out-of-band key-pinned
+    /// privacy profile).
+    pub pins: Vec<Pin>,
+    /// PEM file of the CAs to trust rather than the bundled Mozilla roots.
+    pub ca: Option<PathBuf>,
+}
+
+/// SHA-256 digest of the certificate of an upstream, or of its public key (SPKI) which survives
+/// certificate renewals.
+///
+/// Written `spki-sha256:<base64>`, the format of RFC 7469 and `kdig --tls-pin`, or
+/// `cert-sha256:<base64>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pin {
+    Spki([u8; 32]),
+    Cert([u8; 32]),
+}
+
+impl Pin {
+    /// Whether `cert`, or its public key, has the pinned digest.
+    pub fn matches(&self, cert: &CertificateDer) -> bool {
+        match self {
+            Pin::Spki(pin) => ParsedCertificate::try_from(cert).is_ok_and(|parsed| {
+                digest(&SHA256, parsed.subject_public_key_info().as_ref()).as_ref() == pin
+            }),
+            Pin::Cert(pin) => digest(&SHA256, cert.as_ref()).as_ref() == pin,
+        }
+    }
+}
+
+impl FromStr for Pin {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DnsError::InvalidPin(s.into());
+        let (kind, encoded) = s.split_once(':').ok_or_else(invalid)?;
+        let digest = STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|digest| <[u8; 32]>::try_from(digest).ok())
+            .ok_or_else(invalid)?;
+        match kind {
+            "spki-sha256" => Ok(Pin::Spki(digest)),
+            "cert-sha256" => Ok(Pin::Cert(digest)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Accepts the certificate of the upstream iff it matches a pin, CAs and names are not consulted.
+#[derive(Debug)]
+struct PinVerifier {
+    pins: Vec<Pin>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinVerifier {
+    /// Only the end entity is matched: without a validated chain, intermediates prove nothing.
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.pins.iter().any(|pin| pin.matches(end_entity)) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(CertificateError::ApplicationVerificationFailure.into())
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// DNS over TLS, on a connection shared by every exchange and reopened once the upstream closes
+/// it.
+pub(super) struct Tls {
+    runtime: Runtime,
+    host: String,
+    port: u16,
+    server_name: ServerName<'static>,
+    connector: TlsConnector,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+}
+
+impl Tls {
+    pub(super) fn connect(address: &str, options: &TlsOptions) -> Result<Self> {
+        let (host, port) = split_host_port(address)?;
+        let mut config = client_config(options)?;
+        config.alpn_protocols = vec![b"dot".to_vec()];
+        let server_name = options.server_name.as_deref().unwrap_or(&host);
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|_| DnsError::InvalidName(server_name.into()))?;
+        Ok(Tls {
+            runtime: super::runtime()?,
+            host,
+            port,
+            server_name,
+            connector: TlsConnector::from(Arc::new(config)),
+            connection: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Exchanges on the current connection, and on a new one should a reused connection turn
+    /// out to have been closed by the upstream in the meantime.
+    async fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+        let (connection, fresh) = self.connection().await?;
+        match connection.exchange(query, dnstap).await {
+            Err(err) if !fresh => {
+                eprintln!("Reconnecting to TLS upstream: {}", err);
+                connection.close();
+                let (connection, _) = self.connection().await?;
+                connection.exchange(query, dnstap).await
+            }
+            result => result,
+        }
+    }
+
+    /// The open connection, or a new one along with `true`.
+    async fn connection(&self) -> Result<(Arc<Connection>, bool)> {
+        let mut current = self.connection.lock().await;
+        if let Some(connection) = current.as_ref().filter(|c| !c.is_closed()) {
+            return Ok((connection.clone(), false));
+        }
+        let tcp = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let (local, peer) = (tcp.local_addr()?, tcp.peer_addr()?);
+        let stream = self
+            .connector
+            .connect(self.server_name.clone(), tcp)
+            .await
+            .with_context(|| format!("TLS handshake with {} failed", peer))?;
+        let connection = Connection::new(stream, local, peer);
+        *current = Some(connection.clone());
+        Ok((connection, true))
+    }
+}
+
+impl Transport for Tls {
+    fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+        super::block_on(&self.runtime, Tls::exchange(self, query, dnstap))
+    }
+
+    fn name(&self) -> String {
+        format!("tls://{}:{}", self.host, self.port)
+    }
+}
+
+type Pending = Mutex<HashMap<u16, oneshot::Sender<Vec<u8>>>>;
+
+/// TLS connection to the upstream, on which queries are written as they come while a reader task
+/// hands each reply to the query with its ID.
+///
+/// Queries get IDs unique on the connection, so that clients picking the same ID never get each
+/// other's replies.
+struct Connection {
+    writer: tokio::sync::Mutex<WriteHalf<TlsStream<TcpStream>>>,
+    pending: Pending,
+    next_id: AtomicU16,
+    closed: AtomicBool,
+    reader: AbortHandle,
+    local: SocketAddr,
+    peer: SocketAddr,
+}
+
+impl Connection {
+    fn new(stream: TlsStream<TcpStream>, local: SocketAddr, peer: SocketAddr) -> Arc<Self> {
+        let (reader, writer) = tokio::io::split(stream);
+        Arc::new_cyclic(|connection| Connection {
+            writer: tokio::sync::Mutex::new(writer),
+            pending: Mutex::default(),
+            next_id: AtomicU16::new(rand::random()),
+            closed: AtomicBool::new(false),
+            reader: tokio::spawn(Self::read(connection.clone(), reader)).abort_handle(),
+            local,
+            peer,
+        })
+    }
+
+    async fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+        let id = Header::unpack_id(query)?;
+        let (upstream_id, reply) = self.register();
+        let _pending = Registered(self, upstream_id);
+
+        let mut framed = Vec::with_capacity(query.len() + ID_SIZE);
+        tcp::send(&mut framed, query)?;
+        framed[ID_SIZE..ID_SIZE * 2].copy_from_slice(&upstream_id.to_be_bytes());
+        self.writer.lock().await.write_all(&framed).await?;
+        self.tap(dnstap, dnstap::Kind::ForwarderQuery, &framed[ID_SIZE..]);
+
+        let mut reply = reply.await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "TLS upstream closed the connection",
+            )
+        })?;
+        self.tap(dnstap, dnstap::Kind::ForwarderResponse, &reply);
+        reply[..ID_SIZE].copy_from_slice(&id.to_be_bytes());
+        Ok(reply)
+    }
+
+    /// Picks an ID no pending query uses, and the receiver of the reply carrying it.
+    fn register(&self) -> (u16, oneshot::Receiver<Vec<u8>>) {
+        let (sender, receiver) = oneshot::channel();
+        let mut pending = self.pending();
+        loop {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            if let std::collections::hash_map::Entry::Vacant(entry) = pending.entry(id) {
+                entry.insert(sender);
+                return (id, receiver);
+            }
+        }
+    }
+
+    /// Hands replies over to their queries until the upstream closes the connection, which then
+    /// fails the pending queries.
+    async fn read(connection: Weak<Self>, mut reader: ReadHalf<TlsStream<TcpStream>>) {
+        while let Ok(reply) = tcp::recv_async(&mut reader).await {
+            let (Some(connection), Ok(id)) = (connection.upgrade(), Header::unpack_id(&reply))
+            else {
+                continue;
+            };
+            let sender = connection.pending().remove(&id);
+            if let Some(sender) = sender {
+                // The query may have timed out in the meantime.
+                let _ = sender.send(reply);
+            }
+        }
+        if let Some(connection) = connection.upgrade() {
+            connection.close();
+        }
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, HashMap<u16, oneshot::Sender<Vec<u8>>>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.pending().clear();
+    }
+
+    fn tap(&self, dnstap: Option<&Dnstap>, kind: dnstap::Kind, wire: &[u8]) {
+        if let Some(dnstap) = dnstap {
+            dnstap.log(kind, dnstap::Protocol::Dot, self.local, self.peer, wire);
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Query waiting for its reply, forgotten once the exchange ends however it does.
+struct Registered<'a>(&'a Connection, u16);
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        self.0.pending().remove(&self.1);
+    }
+}
+
+/// Client configuration authenticating upstreams as `options` says, without ALPN.
+pub(crate) fn client_config(options: &TlsOptions) -> Result<ClientConfig> {
+    let provider = Arc::new(default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let config = if !options.pins.is_empty() {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinVerifier {
+                pins: options.pins.clone(),
+                algorithms: provider.signature_verification_algorithms,
+            }))
+            .with_no_client_auth()
+    } else {
+        builder
+            .with_root_certificates(roots(options)?)
+            .with_no_client_auth()
+    };
+    Ok(config)
+}
+
+fn roots(options: &TlsOptions) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match &options.ca {
+        Some(path) => {
+            for cert in CertificateDer::pem_file_iter(path)
+                .with_context(|| format!("Cannot read CA file {}", path.display()))?
+            {
+                roots.add(cert?)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    Ok(roots)
+}
+
+/// Splits `host:port`, `host`, `[v6]:port` or `[v6]` apart.
+pub(super) fn split_host_port(address: &str) -> Result<(String, u16)> {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Ok((address.ip().to_string(), address.port()));
+    }
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => (
+            host,
+            port.parse()
+                .map_err(|_| DnsError::InvalidName(address.into()))?,
+        ),
+        _ => (address, DEFAULT_PORT),
+    };
+    Ok((host.trim_matches(['[', ']']).to_string(), port))
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::net::UdpSocket;
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::{pki_types::PrivateKeyDer, ServerConfig};
+
+    use crate::{
+        message::{answer::Answer, question::Question, rr, Message},
+        Handler, Resolver,
+    };
+
+    use super::*;
+
+    /// Self-signed CA and the certificate it issued to `dns.test`.
+    pub(crate) struct Pki {
+        pub(crate) ca: PathBuf,
+        pub(crate) chain: Vec<CertificateDer<'static>>,
+        pub(crate) key: KeyPair,
+        chain_pem: String,
+    }
+
+    pub(crate) fn pki(test: &str) -> Pki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = params.self_signed(&ca_key).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["dns.test".into()])
+            .unwrap()
+            .signed_by(&key, &ca_cert, &ca_key)
+            .unwrap();
+
+        let ca = std::env::temp_dir().join(format!("ca-{}-{}.pem", std::process::id(), test));
+        std::fs::write(&ca, ca_cert.pem()).unwrap();
+        Pki {
+            ca,
+            chain: vec![cert.der().clone(), ca_cert.der().clone()],
+            chain_pem: cert.pem() + &ca_cert.pem(),
+            key,
+        }
+    }
+
+    impl Pki {
+        /// Server configuration presenting the certificate of `dns.test`.
+        pub(crate) fn server_config(&self, alpn: &[&[u8]]) -> ServerConfig {
+            let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    self.chain.clone(),
+                    PrivateKeyDer::Pkcs8(self.key.serialize_der().into()),
+                )
+                .unwrap();
+            config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+            config
+        }
+
+        /// Options trusting the CA, and expecting `dns.test`.
+        pub(crate) fn trusted(&self) -> TlsOptions {
+            TlsOptions {
+                server_name: Some("dns.test".into()),
+                ca: Some(self.ca.clone()),
+                ..Default::default()
+            }
+        }
+
+        /// Writes the chain and key of `dns.test` as PEM files.
+        pub(crate) fn write(&self, cert: &std::path::Path, key: &std::path::Path) {
+            std::fs::write(cert, &self.chain_pem).unwrap();
+            std::fs::write(key, self.key.serialize_pem()).unwrap();
+        }
+    }
+
+    /// DNS over TLS server presenting the certificate of `dns.test` and answering every query
+    /// with `reply`, serving one connection at a time.
+    pub(crate) fn upstream(
+        pki: &Pki,
+        reply: impl Fn(&Message) -> Message + Send + 'static,
+    ) -> SocketAddr {
+        let config = Arc::new(pki.server_config(&[]));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for tcp in listener.incoming() {
+                let connection = rustls::ServerConnection::new(config.clone()).unwrap();
+                let mut stream = rustls::StreamOwned::new(connection, tcp.unwrap());
+                while let Ok(query) = tcp::recv(&mut stream) {
+                    let response = reply(&Message::unpack(&query).unwrap());
+                    tcp::send(&mut stream, &response.pack().unwrap()).unwrap();
+                }
+            }
+        });
+        address
+    }
+
+    /// Handler forwarding to an upstream answering every query with an A record of `address` per
+    /// TTL of `ttls`.
+    pub(crate) fn handler(address: [u8; 4], ttls: &'static [u32]) -> Arc<Handler> {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::connect(socket.local_addr().unwrap()).unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            loop {
+                let (size, peer) = socket.recv_from(&mut buf).unwrap();
+                let query = Message::unpack(&buf[..size]).unwrap();
+                let name = &query.questions()[0].domain;
+                let response = ttls
+                    .iter()
+                    .fold(Message::response(&query), |response, &ttl| {
+                        response.answer(Answer::new(
+                            name.clone(),
+                            rr::Type::A,
+                            rr::Class::In,
+                            ttl,
+                            address.to_vec(),
+                        ))
+                    });
+                socket
+                    .send_to(&response.build().pack().unwrap(), peer)
+                    .unwrap();
+            }
+        });
+        Arc::new(Handler::new(resolver))
+    }
+
+    pub(crate) fn query(names: &[&str]) -> Message {
+        let mut query = Message::new_query().recursion_desired();
+        for name in names {
+            query = query.question(Question {
+                domain: name.parse().unwrap(),
+                qtype: rr::Type::A,
+                qclass: rr::Class::In,
+            });
+        }
+        query.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::AtomicUsize,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::{
+        message::{answer::Answer, rr, Message},
+        Resolver,
+    };
+
+    use super::{
+        testing::{pki, query, Pki},
+        *,
+    };
+
+    const SLOW: Duration = Duration::from_millis(200);
+
+    /// DNS over TLS server answering every query with an A record, names starting with `slow`
+    /// after the others. With `close`, connections are closed after their first reply.
+    ///
+    /// Returns its address and how many connections it accepted.
+    fn upstream(pki: &Pki, close: bool) -> (SocketAddr, Arc<AtomicUsize>) {
+        let acceptor = TlsAcceptor::from(Arc::new(pki.server_config(&[])));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        let counter = accepted.clone();
+        thread::spawn(move || {
+            runtime.block_on(async move {
+                loop {
+                    let (tcp, _) = listener.accept().await.unwrap();
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let Ok(stream) = acceptor.accept(tcp).await else {
+                        continue;
+                    };
+                    tokio::spawn(serve(stream, close));
+                }
+            })
+        });
+        (address, accepted)
+    }
+
+    async fn serve(stream: tokio_rustls::server::TlsStream<TcpStream>, close: bool) {
+        let (mut reader, writer) = tokio::io::split(stream);
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        loop {
+            let Ok(query) = tcp::recv_async(&mut reader).await else {
+                return;
+            };
+            let query = Message::unpack(&query).unwrap();
+            let name = query.questions()[0].domain.clone();
+
+            let writer = writer.clone();
+            let reply = tokio::spawn(async move {
+                if name.to_string().starts_with("slow") {
+                    tokio::time::sleep(SLOW).await;
+                }
+                let answer = Answer::new(name, rr::Type::A, rr::Class::In, 300, vec![127, 0, 0, 1]);
+                let response = Message::response(&query).answer(answer).build();
+                let mut framed = Vec::new();
+                tcp::send(&mut framed, &response.pack().unwrap()).unwrap();
+                let mut writer = writer.lock().await;
+                writer.write_all(&framed).await.unwrap();
+                if close {
+                    writer.shutdown().await.unwrap();
+                }
+            });
+            if close {
+                let _ = reply.await;
+                return;
+            }
+        }
+    }
+
+    fn answers(response: &Message) -> Vec<String> {
+        response
+            .answers()
+            .iter()
+            .map(|answer| answer.name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn validates_with_ca_and_server_name() {
+        let pki = pki("validates");
+        let (address, _) = upstream(&pki, false);
+        let resolver = Resolver::connect_tls(&address.to_string(), &pki.trusted()).unwrap();
+
+        let query = query(&["google.com"]);
+        let response = resolver.resolve(query.clone()).unwrap();
+        assert_eq!(query.get_id(), response.get_id());
+        assert_eq!(vec!["google.com"], answers(&response));
+    }
+
+    #[test]
+    fn rejects_other_server_name() {
+        let pki = pki("other_name");
+        let (address, _) = upstream(&pki, false);
+        let options = TlsOptions {
+            server_name: Some("other.test".into()),
+            ..pki.trusted()
+        };
+        let resolver = Resolver::connect_tls(&address.to_string(), &options).unwrap();
+        assert!(resolver.resolve(query(&["google.com"])).is_err());
+    }
+
+    #[test]
+    fn reuses_connection() {
+        let pki = pki("reuses");
+        let (address, accepted) = upstream(&pki, false);
+        let resolver = Resolver::connect_tls(&address.to_string(), &pki.trusted()).unwrap();
+
+        resolver.resolve(query(&["google.com"])).unwrap();
+        resolver.resolve(query(&["example.com"])).unwrap();
+        assert_eq!(1, accepted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn reconnects_once_closed() {
+        let pki = pki("reconnects");
+        let (address, accepted) = upstream(&pki, true);
+        let resolver = Resolver::connect_tls(&address.to_string(), &pki.trusted()).unwrap();
+
+        resolver.resolve(query(&["google.com"])).unwrap();
+        let response = resolver.resolve(query(&["example.com"])).unwrap();
+        assert_eq!(vec!["example.com"], answers(&response));
+        assert_eq!(2, accepted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn pipelines_queries_with_the_same_id() {
+        let pki = pki("pipelines");
+        let (address, accepted) = upstream(&pki, false);
+        let resolver = Resolver::connect_tls(&address.to_string(), &pki.trusted()).unwrap();
+
+        // Split into two concurrent lookups carrying the ID of the query, the slow reply comes
+        // second.
+        let started = Instant::now();
+        let response = resolver
+            .resolve(query(&["slow.example.com", "google.com"]))
+            .unwrap();
+        assert!(started.elapsed() < SLOW * 2);
+        assert_eq!(vec!["slow.example.com", "google.com"], answers(&response));
+        assert_eq!(1, accepted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn pinned_spki_needs_no_ca() {
+        let pki = pki("pinned");
+        let (address, _) = upstream(&pki, false);
+        let spki = digest(&SHA256, &pki.key.public_key_der());
+        let pin = format!("spki-sha256:{}", STANDARD.encode(spki));
+        let options = TlsOptions {
+            pins: vec![pin.parse().unwrap()],
+            ..Default::default()
+        };
+        let resolver = Resolver::connect_tls(&address.to_string(), &options).unwrap();
+        assert!(resolver.resolve(query(&["google.com"])).is_ok());
+    }
+
+    #[test]
+    fn rejects_other_pins() {
+        let pki = pki("other_pins");
+        let (address, _) = upstream(&pki, false);
+        // The CA is trusted, but pins take over.
+        let options = TlsOptions {
+            pins: vec![Pin::Spki([0; 32]), Pin::Cert([0; 32])],
+            ..pki.trusted()
+        };
+        let resolver = Resolver::connect_tls(&address.to_string(), &options).unwrap();
+        assert!(resolver.resolve(query(&["google.com"])).is_err());
+    }
+
+    #[test]
+    fn pin_from_str() {
+        let digest = [7u8; 32];
+        let encoded = STANDARD.encode(digest);
+        assert_eq!(
+            Pin::Spki(digest),
+            format!("spki-sha256:{}", encoded).parse().unwrap()
+        );
+        assert_eq!(
+            Pin::Cert(digest),
+            format!("cert-sha256:{}", encoded).parse().unwrap()
+        );
+        assert!(format!("sha1:{}", encoded).parse::<Pin>().is_err());
+        assert!("spki-sha256:AAAA".parse::<Pin>().is_err());
+        assert!(encoded.parse::<Pin>().is_err());
+    }
+
+    #[test]
+    fn host_port() {
+        assert_eq!(
+            ("dns.quad9.net".into(), 853),
+            split_host_port("dns.quad9.net").unwrap()
+        );
+        assert_eq!(
+            ("1.1.1.1".into(), 8853),
+            split_host_port("1.1.1.1:8853").unwrap()
+        );
+        assert_eq!(("::1".into(), 853), split_host_port("[::1]").unwrap());
+        assert_eq!(("::1".into(), 53), split_host_port("[::1]:53").unwrap());
+        assert!(split_host_port("dns.quad9.net:tls").is_err());
+    }
+}
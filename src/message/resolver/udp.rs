@@ -0,0 +1,88 @@
+use std::{
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{ensure, Result};
+
+use crate::{
+    dnstap::{self, Dnstap},
+    errors::DnsError,
+    message::header::Header,
+    tcp,
+};
+
+use super::Transport;
+
+pub(super) const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Plain DNS over UDP, on a socket connected to the upstream.
+pub(super) struct Udp {
+    socket: Mutex<UdpSocket>,
+    peer: SocketAddr,
+}
+
+impl Udp {
+    pub(super) fn connect(address: impl ToSocketAddrs) -> Result<Self> {
+        let socket = bind(address)?;
+        Ok(Udp {
+            peer: socket.peer_addr()?,
+            socket: Mutex::new(socket),
+        })
+    }
+}
+
+impl Transport for Udp {
+    /// Concurrent exchanges would steal each other's replies, so while the shared socket is busy
+    /// an exchange gets an ephemeral socket of its own.
+    fn exchange(&self, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+        match self.socket.try_lock() {
+            Ok(socket) => exchange_on(&socket, query, dnstap),
+            Err(_) => exchange_on(&bind(self.peer)?, query, dnstap),
+        }
+    }
+
+    fn name(&self) -> String {
+        self.peer.to_string()
+    }
+}
+
+/// Late replies to earlier queries are skipped.
+fn exchange_on(socket: &UdpSocket, query: &[u8], dnstap: Option<&Dnstap>) -> Result<Vec<u8>> {
+    let id = Header::unpack_id(query)?;
+    let sent = socket.send(query)?;
+    ensure!(sent > 0, DnsError::ResolverNoRecv);
+    tap(dnstap, socket, dnstap::Kind::ForwarderQuery, query);
+
+    let mut buf = vec![0u8; tcp::MAX_MESSAGE_SIZE];
+    loop {
+        let size = socket.recv(&mut buf)?;
+        if Header::unpack_id(&buf[..size]).ok() == Some(id) {
+            tap(
+                dnstap,
+                socket,
+                dnstap::Kind::ForwarderResponse,
+                &buf[..size],
+            );
+            return Ok(buf[..size].to_vec());
+        }
+    }
+}
+
+fn bind(address: impl ToSocketAddrs) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind("localhost:0")?;
+    socket.set_read_timeout(Some(READ_TIMEOUT))?;
+    socket.connect(address)?;
+    Ok(socket)
+}
+
+fn tap(dnstap: Option<&Dnstap>, socket: &UdpSocket, kind: dnstap::Kind, wire: &[u8]) {
+    let Some(dnstap) = dnstap else {
+        return;
+    };
+    match (socket.local_addr(), socket.peer_addr()) {
+        (Ok(local), Ok(peer)) => dnstap.log(kind, dnstap::Protocol::Udp, local, peer, wire),
+        (Err(e), _) | (_, Err(e)) => eprintln!("Cannot log upstream message: {}", e),
+    }
+}
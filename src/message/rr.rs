@@ -1,74 +1,163 @@
-#[derive(Debug, PartialEq)]
-pub enum Error {
-    UnsupportedType(u16),
-    UnsupportedClass(u16),
-}
-
-impl std::error::Error for Error {}
-
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Error::UnsupportedType(value) => {
-                write!(f, "Unsupported type {}", value)
-            }
-            Error::UnsupportedClass(value) => {
-                write!(f, "Unsupported class {}", value)
-            }
-        }
-    }
-}
+use crate::errors::DnsError;
 
 /// TYPE  | value and meaning
 /// ------+-----------------------------------------
 /// A     | a host address
+/// NS    | an authoritative name server
+/// CNAME | the canonical name for an alias
+/// SOA   | marks the start of a zone of authority
+/// PTR   | a domain name pointer
+/// MX    | mail exchange
+/// TXT   | text strings
+/// AAAA  | an IPv6 host address
+/// SRV   | location of a service
+/// OPT   | EDNS pseudo-record, only valid in the additional section
 #[repr(u16)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
 pub enum Type {
     #[default]
     A = 1,
+    Ns = 2,
+    Cname = 5,
+    Soa = 6,
+    Ptr = 12,
+    Mx = 15,
+    Txt = 16,
+    Aaaa = 28,
+    Srv = 33,
+    Opt = 41,
 }
 
 impl TryFrom<u16> for Type {
-    type Error = Error;
+    type Error = DnsError;
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         match value {
             1 => Ok(Type::A),
-            _ => Err(Error::UnsupportedType(value)),
+            2 => Ok(Type::Ns),
+            5 => Ok(Type::Cname),
+            6 => Ok(Type::Soa),
+            12 => Ok(Type::Ptr),
+            15 => Ok(Type::Mx),
+            16 => Ok(Type::Txt),
+            28 => Ok(Type::Aaaa),
+            33 => Ok(Type::Srv),
+            41 => Ok(Type::Opt),
+            _ => Err(DnsError::UnsupportedType(value)),
         }
     }
 }
 
-impl Into<u16> for Type {
-    fn into(self) -> u16 {
-        self as u16
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = match self {
+            Type::A => "A",
+            Type::Ns => "NS",
+            Type::Cname => "CNAME",
+            Type::Soa => "SOA",
+            Type::Ptr => "PTR",
+            Type::Mx => "MX",
+            Type::Txt => "TXT",
+            Type::Aaaa => "AAAA",
+            Type::Srv => "SRV",
+            Type::Opt => "OPT",
+        };
+        write!(f, "{}", mnemonic)
+    }
+}
+
+impl std::str::FromStr for Type {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "A" => Ok(Type::A),
+            "NS" => Ok(Type::Ns),
+            "CNAME" => Ok(Type::Cname),
+            "SOA" => Ok(Type::Soa),
+            "PTR" => Ok(Type::Ptr),
+            "MX" => Ok(Type::Mx),
+            "TXT" => Ok(Type::Txt),
+            "AAAA" => Ok(Type::Aaaa),
+            "SRV" => Ok(Type::Srv),
+            "OPT" => Ok(Type::Opt),
+            upper => match upper.strip_prefix("TYPE").map(str::parse::<u16>) {
+                Some(Ok(value)) => value.try_into(),
+                _ => Err(DnsError::UnknownMnemonic(s.into())),
+            },
+        }
+    }
+}
+
+impl From<Type> for u16 {
+    fn from(value: Type) -> Self {
+        value as u16
     }
 }
 
 /// CLASS  | value and meaning
 /// -------+-----------------------------------------
 /// IN     | an Internet host
+/// CH     | the CHAOS class
+/// HS     | Hesiod
+/// ANY    | any class, only valid in questions
 #[repr(u16)]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
 pub enum Class {
     #[default]
     In = 1,
+    Ch = 3,
+    Hs = 4,
+    Any = 255,
 }
 
 impl TryFrom<u16> for Class {
-    type Error = Error;
+    type Error = DnsError;
 
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         match value {
             1 => Ok(Class::In),
-            _ => Err(Error::UnsupportedClass(value)),
+            3 => Ok(Class::Ch),
+            4 => Ok(Class::Hs),
+            255 => Ok(Class::Any),
+            _ => Err(DnsError::UnsupportedClass(value)),
+        }
+    }
+}
+
+impl std::fmt::Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = match self {
+            Class::In => "IN",
+            Class::Ch => "CH",
+            Class::Hs => "HS",
+            Class::Any => "ANY",
+        };
+        write!(f, "{}", mnemonic)
+    }
+}
+
+impl std::str::FromStr for Class {
+    type Err = DnsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "IN" => Ok(Class::In),
+            "CH" => Ok(Class::Ch),
+            "HS" => Ok(Class::Hs),
+            "ANY" => Ok(Class::Any),
+            upper => match upper.strip_prefix("CLASS").map(str::parse::<u16>) {
+                Some(Ok(value)) => value.try_into(),
+                _ => Err(DnsError::UnknownMnemonic(s.into())),
+            },
         }
     }
 }
 
-impl Into<u16> for Class {
-    fn into(self) -> u16 {
-        self as u16
+impl From<Class> for u16 {
+    fn from(value: Class) -> Self {
+        value as u16
     }
 }
@@ -0,0 +1,213 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use crate::{
+    errors::DnsError,
+    message::{header::ResponseCode, Message},
+};
+
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prometheus metrics of the proxy, exposed in the text format over HTTP at `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    queries: IntCounterVec,
+    upstream_latency: HistogramVec,
+    errors: IntCounterVec,
+    in_flight: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("dns_proxy".into()), None)?;
+
+        let queries = IntCounterVec::new(
+            Opts::new(
+                "queries_total",
+                "Answered queries by question type and response code",
+            ),
+            &["qtype", "rcode"],
+        )?;
+        let upstream_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_duration_seconds",
+                "Round trip time of exchanges with the upstream resolver",
+            )
+            .buckets(vec![
+                0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+            ]),
+            &["upstream"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new("errors_total", "Failures by error kind"),
+            &["error"],
+        )?;
+        let in_flight = IntGauge::new("in_flight_queries", "Queries currently being handled")?;
+
+        registry.register(Box::new(queries.clone()))?;
+        registry.register(Box::new(upstream_latency.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            queries,
+            upstream_latency,
+            errors,
+            in_flight,
+        })
+    }
+
+    /// Counts an answered query, labelled by each of its questions and the response code.
+    pub fn query(&self, query: &Message, rcode: ResponseCode) {
+        let rcode = rcode.to_string();
+        for question in query.questions() {
+            self.queries
+                .with_label_values(&[question.qtype.to_string().as_str(), &rcode])
+                .inc();
+        }
+    }
+
+    pub fn upstream(&self, upstream: &str, elapsed: Duration) {
+        self.upstream_latency
+            .with_label_values(&[upstream])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn error(&self, err: &anyhow::Error) {
+        self.errors.with_label_values(&[error_name(err)]).inc();
+    }
+
+    /// Marks a query as in flight until the returned guard is dropped.
+    pub fn in_flight(&self) -> InFlight<'_> {
+        self.in_flight.inc();
+        InFlight(&self.in_flight)
+    }
+
+    pub fn render(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Serves `GET /metrics` on `address` from a background thread, returning the bound address.
+    pub fn serve(self: Arc<Self>, address: impl ToSocketAddrs) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        let bound = listener.local_addr()?;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Err(e) = stream.map_err(Into::into).and_then(|s| self.respond(s)) {
+                    eprintln!("Cannot serve metrics: {}", e);
+                }
+            }
+        });
+        Ok(bound)
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()?),
+            (Some("GET"), _) => ("404 Not Found", b"Not Found\n".to_vec()),
+            _ => ("405 Method Not Allowed", b"Method Not Allowed\n".to_vec()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            prometheus::TEXT_FORMAT,
+            body.len(),
+        )?;
+        stream.write_all(&body)?;
+        Ok(())
+    }
+}
+
+/// Decrements the in-flight gauge when dropped.
+pub struct InFlight<'a>(&'a IntGauge);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+fn error_name(err: &anyhow::Error) -> &'static str {
+    if let Some(err) = err.downcast_ref::<DnsError>() {
+        err.name()
+    } else if err.is::<std::io::Error>() {
+        "Io"
+    } else if err.is::<packed_struct::PackingError>() {
+        "Packing"
+    } else {
+        "Other"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new().unwrap();
+        let query = Message::unpack(
+            b"\x04\xD2\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x06google\x03com\x00\x00\x01\x00\x01",
+        )
+        .unwrap();
+        metrics.query(&query, ResponseCode::NoError);
+        metrics.query(&query, ResponseCode::ServerFailure);
+        metrics.upstream("8.8.8.8:53", Duration::from_millis(20));
+        metrics.error(&anyhow!(DnsError::ResolverMalformed));
+        metrics.error(&anyhow!(DnsError::UnsupportedType(28)));
+        let guard = metrics.in_flight();
+
+        let text = String::from_utf8(metrics.render().unwrap()).unwrap();
+        assert!(text.contains(r#"dns_proxy_queries_total{qtype="A",rcode="NOERROR"} 1"#));
+        assert!(text.contains(r#"dns_proxy_queries_total{qtype="A",rcode="SERVFAIL"} 1"#));
+        assert!(
+            text.contains(r#"dns_proxy_upstream_duration_seconds_count{upstream="8.8.8.8:53"} 1"#)
+        );
+        assert!(text.contains(r#"dns_proxy_errors_total{error="ResolverMalformed"} 1"#));
+        assert!(text.contains(r#"dns_proxy_errors_total{error="UnsupportedType"} 1"#));
+        assert!(text.contains("dns_proxy_in_flight_queries 1"));
+
+        drop(guard);
+        let text = String::from_utf8(metrics.render().unwrap()).unwrap();
+        assert!(text.contains("dns_proxy_in_flight_queries 0"));
+    }
+
+    #[test]
+    fn serve() {
+        let address = Arc::new(Metrics::new().unwrap())
+            .serve("127.0.0.1:0")
+            .unwrap();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("dns_proxy_in_flight_queries 0"));
+    }
+}
@@ -0,0 +1,68 @@
+//! DNS over TCP framing (RFC 1035 section 4.2.2): every message is prefixed with its length as a
+//! two byte integer.
+
+use std::io::{self, Read, Write};
+
+use anyhow::{ensure, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::errors::DnsError;
+
+pub const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+/// Writes `msg` with its length prefix in a single write, so pipelined messages never interleave.
+pub fn send(stream: &mut impl Write, msg: &[u8]) -> Result<()> {
+    ensure!(
+        msg.len() <= MAX_MESSAGE_SIZE,
+        DnsError::MsgTooLong {
+            max: MAX_MESSAGE_SIZE,
+            act: msg.len()
+        }
+    );
+    let mut framed = Vec::with_capacity(msg.len() + 2);
+    framed.write_u16::<BigEndian>(msg.len() as u16)?;
+    framed.extend_from_slice(msg);
+    stream.write_all(&framed)?;
+    stream.flush()?;
+    Ok(())
+}
+
+pub fn recv(stream: &mut impl Read) -> Result<Vec<u8>> {
+    let len = stream.read_u16::<BigEndian>()? as usize;
+    let mut msg = vec![0u8; len];
+    stream.read_exact(&mut msg)?;
+    Ok(msg)
+}
+
+/// Reads a message off an asynchronous stream, e.g. a TLS connection.
+pub async fn recv_async(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+    let len = stream.read_u16().await? as usize;
+    let mut msg = vec![0u8; len];
+    stream.read_exact(&mut msg).await?;
+    Ok(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buf = Vec::new();
+        send(&mut buf, b"first").unwrap();
+        send(&mut buf, b"second").unwrap();
+        assert_eq!(b"\x00\x05first", &buf[..7]);
+
+        let mut reader = &buf[..];
+        assert_eq!(b"first", &recv(&mut reader).unwrap()[..]);
+        assert_eq!(b"second", &recv(&mut reader).unwrap()[..]);
+        assert!(recv(&mut reader).is_err());
+    }
+
+    #[test]
+    fn send_too_long() {
+        let msg = vec![0u8; MAX_MESSAGE_SIZE + 1];
+        assert!(send(&mut Vec::new(), &msg).is_err());
+    }
+}
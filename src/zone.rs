@@ -0,0 +1,1098 @@
+//! Zones served authoritatively, and the master files (RFC 1035 section 5) they are loaded from:
+//! the records of a zone in presentation format, one per line or spanning lines within
+//! parentheses, along with the `$ORIGIN`, `$TTL` (RFC 2308), `$INCLUDE` and BIND's `$GENERATE`
+//! directives. Zones are written back as canonical master files, sorted, for them to be diffed.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs,
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{
+    errors::DnsError,
+    message::{
+        answer::Answer,
+        edns::Edns,
+        header::ResponseCode,
+        labels::Labels,
+        presentation::{tokenize, Fields, Token},
+        question::Question,
+        rdata::RData,
+        rr, Message,
+    },
+};
+
+/// Name given to master files parsed from a string in errors.
+const INLINE: &str = "<inline>";
+
+/// Files may include files that include files, up to this depth, which rules out loops.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Leftmost label of wildcard owners.
+const WILDCARD: &str = "*";
+
+/// CNAME records followed within a zone before giving up on a chain, which rules out loops.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Records of the master file at `path`, names being relative to `origin` until `$ORIGIN` says
+/// otherwise. Included files are looked up relative to the directory of the including one.
+pub fn load(path: impl AsRef<Path>, origin: &Labels) -> Result<Vec<Answer>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Cannot read zone file `{}`", path.display()))?;
+    let mut parser = Parser::default();
+    parser.file(&path.display().to_string(), &text, Scope::new(path, origin))?;
+    Ok(parser.records)
+}
+
+/// Records of master file `text`, names being relative to `origin` until `$ORIGIN` says
+/// otherwise. Included files are looked up relative to the working directory.
+pub fn parse(text: &str, origin: &Labels) -> Result<Vec<Answer>> {
+    let mut parser = Parser::default();
+    parser.file(INLINE, text, Scope::new(Path::new(""), origin))?;
+    Ok(parser.records)
+}
+
+/// Zone answered authoritatively, from the records of its master file.
+///
+/// Names compare case-insensitively; records are kept by owner, its labels from the top level
+/// domain down, lowercased.
+#[derive(Debug)]
+pub struct Zone {
+    origin: Labels,
+    class: rr::Class,
+    soa: Answer,
+    names: BTreeMap<Vec<String>, Vec<Answer>>,
+}
+
+/// What a zone holds for a name and type.
+enum Lookup<'a> {
+    Records(Vec<Answer>),
+    Cname(Answer, Labels),
+    /// The name is at or below a zone cut: its name servers, and their addresses in the zone.
+    Referral(Vec<&'a Answer>, Vec<&'a Answer>),
+    NoData,
+    NxDomain,
+}
+
+impl Zone {
+    /// Zone of apex `origin` holding `records`, which must all be in the zone and include a
+    /// single SOA record, at the apex.
+    pub fn new(origin: Labels, records: Vec<Answer>) -> Result<Self> {
+        let apex = key(&origin);
+        let mut soa = None;
+        let mut names: BTreeMap<Vec<String>, Vec<Answer>> = BTreeMap::new();
+        for record in records {
+            let owner = key(record.name());
+            ensure!(
+                owner.starts_with(&apex),
+                DnsError::InvalidZoneData(format!(
+                    "`{}` is outside of zone `{}`",
+                    record.name().to_fqdn(),
+                    origin.to_fqdn()
+                ))
+            );
+            if record.atype() == rr::Type::Soa {
+                ensure!(
+                    owner == apex && soa.is_none(),
+                    DnsError::InvalidZoneData(format!(
+                        "SOA record at `{}`, expected a single one at the apex",
+                        record.name().to_fqdn()
+                    ))
+                );
+                soa = Some(record.clone());
+            }
+            names.entry(owner).or_default().push(record);
+        }
+        let soa = soa.ok_or_else(|| {
+            DnsError::InvalidZoneData(format!("no SOA record at `{}`", origin.to_fqdn()))
+        })?;
+        for records in names.values() {
+            let cname = records.iter().any(|r| r.atype() == rr::Type::Cname);
+            ensure!(
+                !cname || records.len() == 1,
+                DnsError::InvalidZoneData(format!(
+                    "CNAME at `{}` along with other records",
+                    records[0].name().to_fqdn()
+                ))
+            );
+        }
+        Ok(Zone {
+            origin,
+            class: soa.aclass(),
+            soa,
+            names,
+        })
+    }
+
+    /// Zone of apex `origin` out of the master file at `path`.
+    pub fn load(path: impl AsRef<Path>, origin: Labels) -> Result<Self> {
+        let records = load(path.as_ref(), &origin)?;
+        Zone::new(origin, records)
+            .with_context(|| format!("Cannot load zone file `{}`", path.as_ref().display()))
+    }
+
+    pub fn origin(&self) -> &Labels {
+        &self.origin
+    }
+
+    /// Whether `question` is about a name of the zone, in its class.
+    pub fn serves(&self, question: &Question) -> bool {
+        self.serves_name(&question.domain, question.qclass)
+    }
+
+    /// Whether `name` is a name of the zone and `class` its class, or ANY.
+    pub fn serves_name(&self, name: &Labels, class: rr::Class) -> bool {
+        (class == self.class || class == rr::Class::Any)
+            && key(name).starts_with(&key(&self.origin))
+    }
+
+    /// Records of the zone in canonical order (RFC 4034 section 6): by owner, names before the
+    /// ones below them, then by type and RDATA; the SOA record first.
+    pub fn sorted_records(&self) -> impl Iterator<Item = &Answer> {
+        self.names.values().flat_map(|records| {
+            let mut records = records.iter().collect::<Vec<_>>();
+            records.sort_by_key(|record| {
+                let rtype = record.atype();
+                (rtype != rr::Type::Soa, u16::from(rtype), record.data())
+            });
+            records
+        })
+    }
+
+    /// Writes the zone to `path` as a canonical master file, through a temporary file renamed
+    /// over it so that readers never see it half written.
+    ///
+    /// Zones cannot be modified once built, so this writes back the records the zone was built
+    /// from: edits are made by building a new zone out of different records.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, self.to_string())
+            .and_then(|_| fs::rename(&temporary, path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&temporary);
+            })
+            .with_context(|| format!("Cannot save zone file `{}`", path.display()))
+    }
+
+    /// Response to `query`, whose question the zone serves (RFC 1034 section 4.3.2): the records
+    /// asked for, possibly synthesized from a wildcard, after any CNAME chain within the zone, or
+    /// the SOA record when there are none; or a referral to the name servers of a delegated child
+    /// zone, with their addresses.
+    pub fn answer(&self, query: &Message) -> Message {
+        let question = &query.questions()[0];
+        self.answer_type(query, &question.domain, question.qtype.into())
+    }
+
+    /// Response to `query` about `name` and the type numbered `qtype`, which need not be one the
+    /// parser models: the zone holds no records of such types, so they get no data. The question
+    /// section is the one of `query`.
+    pub fn answer_type(&self, query: &Message, name: &Labels, qtype: u16) -> Message {
+        let mut response = Message::response(query);
+        if query.edns().is_some() {
+            response = response.edns(Edns::default());
+        }
+
+        let (mut name, mut chain) = (name.clone(), 0);
+        let lookup = loop {
+            match self.lookup(&name, qtype) {
+                Lookup::Cname(cname, target) => {
+                    response = response.answer(cname);
+                    chain += 1;
+                    // Names outside of the zone are left for the client to chase.
+                    if !key(&target).starts_with(&key(&self.origin)) || chain == MAX_CNAME_CHAIN {
+                        break Lookup::Records(Vec::new());
+                    }
+                    name = target;
+                }
+                lookup => break lookup,
+            }
+        };
+
+        match lookup {
+            Lookup::Records(records) => records
+                .into_iter()
+                .fold(response.authoritative(), |response, record| {
+                    response.answer(record)
+                }),
+            Lookup::Referral(servers, glue) => {
+                let response = if chain > 0 {
+                    response.authoritative()
+                } else {
+                    response
+                };
+                let response = servers
+                    .into_iter()
+                    .fold(response, |response, ns| response.authority(ns.clone()));
+                glue.into_iter()
+                    .fold(response, |response, glue| response.additional(glue.clone()))
+            }
+            Lookup::NoData => response.authoritative().authority(self.negative_soa()),
+            Lookup::NxDomain => response
+                .authoritative()
+                .rcode(ResponseCode::NameError)
+                .authority(self.negative_soa()),
+            Lookup::Cname(..) => unreachable!("CNAME chains are followed"),
+        }
+        .build()
+    }
+
+    fn lookup(&self, name: &Labels, qtype: u16) -> Lookup<'_> {
+        let owner = name;
+        let name = key(name);
+        // Zone cuts between the apex and the name, the closest to the apex first.
+        for depth in key(&self.origin).len() + 1..=name.len() {
+            let servers = self.records(&name[..depth], rr::Type::Ns);
+            if !servers.is_empty() {
+                let glue = servers.iter().flat_map(|ns| match ns.rdata() {
+                    Ok(RData::Ns(server)) => {
+                        let server = key(&server);
+                        let mut glue = self.records(&server, rr::Type::A);
+                        glue.extend(self.records(&server, rr::Type::Aaaa));
+                        glue
+                    }
+                    _ => Vec::new(),
+                });
+                let glue = glue.collect();
+                return Lookup::Referral(servers, glue);
+            }
+        }
+
+        // Names holding no records but some below them exist all the same, and are answered
+        // with no data. Others may be synthesized from the wildcard of their closest encloser
+        // (RFC 4592 section 3.3.1), under the name asked for.
+        let records = match self.names.get(&name) {
+            Some(records) => records.clone(),
+            None if self.exists(&name) => return Lookup::NoData,
+            None => match self.wildcard(&name) {
+                Some(records) => records
+                    .iter()
+                    .map(|record| {
+                        let data = record.data().to_vec();
+                        Answer::new(
+                            owner.clone(),
+                            record.atype(),
+                            record.aclass(),
+                            record.ttl(),
+                            data,
+                        )
+                    })
+                    .collect(),
+                None => return Lookup::NxDomain,
+            },
+        };
+        let (matching, others): (Vec<_>, Vec<_>) = records
+            .into_iter()
+            .partition(|record| u16::from(record.atype()) == qtype);
+        if !matching.is_empty() {
+            return Lookup::Records(matching);
+        }
+        match others
+            .into_iter()
+            .find(|record| record.atype() == rr::Type::Cname)
+        {
+            Some(cname) => match cname.rdata() {
+                Ok(RData::Cname(target)) => Lookup::Cname(cname, target),
+                _ => Lookup::NoData,
+            },
+            None => Lookup::NoData,
+        }
+    }
+
+    /// Whether `name` holds records, or names below it do.
+    fn exists(&self, name: &[String]) -> bool {
+        let below = self
+            .names
+            .range::<[String], _>((Bound::Included(name), Bound::Unbounded));
+        matches!(below.into_iter().next(), Some((owner, _)) if owner.starts_with(name))
+    }
+
+    /// Records of the wildcard at the closest encloser of `name`, the closest of its ancestors
+    /// that exists.
+    fn wildcard(&self, name: &[String]) -> Option<&Vec<Answer>> {
+        let apex = key(&self.origin).len();
+        let encloser = (apex..name.len())
+            .rev()
+            .map(|depth| &name[..depth])
+            .find(|ancestor| self.exists(ancestor))?;
+        self.names
+            .get(&[encloser, &[WILDCARD.to_string()]].concat())
+    }
+
+    fn records(&self, owner: &[String], rtype: rr::Type) -> Vec<&Answer> {
+        self.names
+            .get(owner)
+            .into_iter()
+            .flatten()
+            .filter(|record| record.atype() == rtype)
+            .collect()
+    }
+
+    /// SOA record of negative answers, whose TTL is also how long they may be cached (RFC 2308
+    /// section 3).
+    fn negative_soa(&self) -> Answer {
+        let minimum = match self.soa.rdata() {
+            Ok(RData::Soa(soa)) => soa.minimum,
+            _ => 0,
+        };
+        Answer::new(
+            self.soa.name().clone(),
+            rr::Type::Soa,
+            self.class,
+            self.soa.ttl().min(minimum),
+            self.soa.data().to_vec(),
+        )
+    }
+}
+
+/// Canonical master file of the zone: `$ORIGIN` and `$TTL` headers, the latter being the TTL of
+/// the SOA record, then its records in canonical order, one per line, with names relative to the
+/// origin and TTLs only where they differ from the default.
+impl Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "$ORIGIN {}", self.origin.to_fqdn())?;
+        writeln!(f, "$TTL {}", self.soa.ttl())?;
+        for record in self.sorted_records() {
+            write!(f, "{}", record.name().relative_to(&self.origin))?;
+            if record.ttl() != self.soa.ttl() {
+                write!(f, " {}", record.ttl())?;
+            }
+            let rdata = record
+                .rdata()
+                .unwrap_or_else(|_| RData::Unknown(record.data().to_vec()));
+            writeln!(
+                f,
+                " {} {} {}",
+                record.aclass(),
+                record.atype(),
+                rdata.relative_to(&self.origin)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Labels of `name` from the top level domain down, lowercased.
+fn key(name: &Labels) -> Vec<String> {
+    name.as_slice()
+        .iter()
+        .rev()
+        .map(|label| label.to_ascii_lowercase())
+        .collect()
+}
+
+/// State carried from one entry to the next. TTLs and the class carry over into included files
+/// and back.
+#[derive(Default)]
+struct Parser {
+    records: Vec<Answer>,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_class: rr::Class,
+    depth: usize,
+}
+
+/// State of the file being read, restored once a file it includes was read.
+struct Scope {
+    dir: PathBuf,
+    origin: Labels,
+    owner: Option<Labels>,
+}
+
+impl Scope {
+    fn new(path: &Path, origin: &Labels) -> Self {
+        Scope {
+            dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            origin: origin.clone(),
+            owner: None,
+        }
+    }
+}
+
+/// Directive or record, starting at `line` and spanning more lines within parentheses.
+struct Entry {
+    line: usize,
+    indented: bool,
+    tokens: Vec<Token>,
+}
+
+/// File to read at the point of an `$INCLUDE` directive.
+struct Include {
+    name: String,
+    text: String,
+    scope: Scope,
+}
+
+impl Parser {
+    fn file(&mut self, name: &str, text: &str, mut scope: Scope) -> Result<()> {
+        let mut pending: Option<Entry> = None;
+        let mut depth = 0usize;
+        for (number, line) in text.lines().enumerate() {
+            let located = |line| DnsError::InvalidZone {
+                file: name.into(),
+                line,
+            };
+            let tokens = tokenize(line).with_context(|| located(number + 1))?;
+            let entry = pending.get_or_insert_with(|| Entry {
+                line: number + 1,
+                indented: line.starts_with([' ', '\t']),
+                tokens: Vec::new(),
+            });
+            for token in tokens {
+                match token {
+                    Token::Open => depth += 1,
+                    Token::Close => {
+                        depth = depth
+                            .checked_sub(1)
+                            .ok_or_else(|| DnsError::InvalidSyntax("unbalanced `)`".into()))
+                            .with_context(|| located(number + 1))?
+                    }
+                    _ => {}
+                }
+                entry.tokens.push(token);
+            }
+            if depth > 0 {
+                continue;
+            }
+
+            let Some(entry) = pending.take().filter(|entry| !entry.tokens.is_empty()) else {
+                continue;
+            };
+            // Errors of included files are located in those files already.
+            if let Some(include) = self
+                .entry(&entry, &mut scope)
+                .with_context(|| located(entry.line))?
+            {
+                self.depth += 1;
+                self.file(&include.name, &include.text, include.scope)?;
+                self.depth -= 1;
+            }
+        }
+        if let Some(entry) = pending {
+            return Err(DnsError::InvalidSyntax("unbalanced `(`".into())).with_context(|| {
+                DnsError::InvalidZone {
+                    file: name.into(),
+                    line: entry.line,
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, entry: &Entry, scope: &mut Scope) -> Result<Option<Include>> {
+        let mut fields = Fields::new(&entry.tokens);
+        let directive = match fields.peek() {
+            Some(word) if word.starts_with('$') && !entry.indented => word.to_ascii_uppercase(),
+            _ => {
+                self.record(fields, entry.indented, scope)?;
+                return Ok(None);
+            }
+        };
+        fields.word()?;
+        match directive.as_str() {
+            "$ORIGIN" => {
+                scope.origin = fields.name(&scope.origin)?;
+                fields.end()?;
+            }
+            "$TTL" => {
+                self.default_ttl = Some(fields.ttl()?);
+                fields.end()?;
+            }
+            "$INCLUDE" => {
+                ensure!(
+                    self.depth < MAX_INCLUDE_DEPTH,
+                    DnsError::InvalidSyntax(format!(
+                        "files included more than {} levels deep",
+                        MAX_INCLUDE_DEPTH
+                    ))
+                );
+                let path = scope.dir.join(String::from_utf8(fields.string()?)?);
+                let origin = if fields.is_empty() {
+                    scope.origin.clone()
+                } else {
+                    fields.name(&scope.origin)?
+                };
+                fields.end()?;
+                let text = fs::read_to_string(&path)
+                    .with_context(|| format!("Cannot read zone file `{}`", path.display()))?;
+                return Ok(Some(Include {
+                    name: path.display().to_string(),
+                    scope: Scope::new(&path, &origin),
+                    text,
+                }));
+            }
+            "$GENERATE" => self.generate(fields, scope)?,
+            _ => bail!(DnsError::InvalidSyntax(format!(
+                "unknown directive `{}`",
+                directive
+            ))),
+        }
+        Ok(None)
+    }
+
+    /// Reads `[owner] [ttl] [class] type rdata`, the owner of an indented record being the one of
+    /// the record before it.
+    fn record(&mut self, mut fields: Fields, indented: bool, scope: &mut Scope) -> Result<()> {
+        let owner = if indented {
+            scope
+                .owner
+                .clone()
+                .ok_or_else(|| DnsError::InvalidSyntax("no owner to inherit".into()))?
+        } else {
+            fields.name(&scope.origin)?
+        };
+        let (ttl, class, rtype) = fields.record_type()?;
+        let rdata = RData::from_fields(rtype, fields, &scope.origin)?;
+        // Without a TTL, $TTL applies, or else the last TTL given (RFC 1035 section 5.1). The SOA
+        // MINIMUM stands in for a first TTL, as in BIND.
+        let ttl = match (ttl.or(self.default_ttl).or(self.last_ttl), &rdata) {
+            (Some(ttl), _) => ttl,
+            (None, RData::Soa(soa)) => soa.minimum,
+            (None, _) => bail!(DnsError::InvalidSyntax("missing TTL, without $TTL".into())),
+        };
+        let class = class.unwrap_or(self.last_class);
+
+        self.records
+            .push(Answer::new(owner.clone(), rtype, class, ttl, rdata.pack()?));
+        self.last_ttl = Some(ttl);
+        self.last_class = class;
+        scope.owner = Some(owner);
+        Ok(())
+    }
+
+    /// `$GENERATE start-stop[/step] lhs [ttl] [class] type rhs`: one record per value of the
+    /// range, with `$` in its fields replaced by the value.
+    fn generate(&mut self, mut fields: Fields, scope: &mut Scope) -> Result<()> {
+        let range = fields.word()?;
+        let invalid = || DnsError::InvalidSyntax(format!("range `{}`", range));
+        let (bounds, step) = range.split_once('/').unwrap_or((range, "1"));
+        let (start, stop) = bounds.split_once('-').ok_or_else(invalid)?;
+        let (start, stop, step): (u32, u32, usize) = (
+            start.parse().map_err(|_| invalid())?,
+            stop.parse().map_err(|_| invalid())?,
+            step.parse().map_err(|_| invalid())?,
+        );
+        ensure!(start <= stop && step > 0, invalid());
+
+        let template = fields.rest().collect::<Vec<_>>();
+        for value in (start..=stop).step_by(step) {
+            let tokens = template
+                .iter()
+                .map(|token| {
+                    Ok(match token {
+                        Token::Word(text) => Token::Word(substitute(text, value)?),
+                        Token::Quoted(text) => Token::Quoted(substitute(text, value)?),
+                        token => (*token).clone(),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            self.record(Fields::new(&tokens), false, scope)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces `$` in `template` with `value`, and `${offset[,width[,base]]}` with `value + offset`
+/// padded with zeros to `width` in base `d`, `o`, `x`, `X`, or nibbles `n` and `N` as in reverse
+/// IPv6 names. `\$` is a dollar sign.
+fn substitute(template: &str, value: u32) -> Result<String> {
+    let mut text = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('$') => text.push('$'),
+                next => text.extend(Some('\\').into_iter().chain(next)),
+            },
+            '$' if chars.as_str().starts_with('{') => {
+                let rest = chars.as_str();
+                let end = rest.find('}').ok_or_else(|| {
+                    DnsError::InvalidSyntax(format!("unterminated `${{` in `{}`", template))
+                })?;
+                text.push_str(&modified(&rest[1..end], value)?);
+                chars = rest[end + 1..].chars();
+            }
+            '$' => text.push_str(&value.to_string()),
+            c => text.push(c),
+        }
+    }
+    Ok(text)
+}
+
+fn modified(modifiers: &str, value: u32) -> Result<String> {
+    let invalid = || DnsError::InvalidSyntax(format!("modifiers `${{{}}}`", modifiers));
+    let mut modifiers = modifiers.split(',');
+    let offset: i64 = modifiers
+        .next()
+        .unwrap_or("0")
+        .parse()
+        .map_err(|_| invalid())?;
+    let width: usize = match modifiers.next() {
+        Some(width) => width.parse().map_err(|_| invalid())?,
+        None => 0,
+    };
+    let base = modifiers.next().unwrap_or("d");
+    ensure!(modifiers.next().is_none(), invalid());
+    let value = u32::try_from(i64::from(value) + offset).map_err(|_| invalid())?;
+    Ok(match base {
+        "d" => format!("{:0width$}", value),
+        "o" => format!("{:0width$o}", value),
+        "x" => format!("{:0width$x}", value),
+        "X" => format!("{:0width$X}", value),
+        "n" | "N" => {
+            let nibbles = match base {
+                "n" => format!("{:0width$x}", value),
+                _ => format!("{:0width$X}", value),
+            };
+            let nibbles = nibbles.chars().rev().map(String::from).collect::<Vec<_>>();
+            nibbles.join(".")
+        }
+        _ => bail!(invalid()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::message::{header::AuthoritativeAnswer, rdata::Soa};
+
+    use super::*;
+
+    fn origin() -> Labels {
+        "example.com".parse().unwrap()
+    }
+
+    fn presented(records: &[Answer]) -> Vec<String> {
+        records.iter().map(Answer::to_string).collect()
+    }
+
+    #[test]
+    fn parses_records() {
+        let text = r#"
+$TTL 1h
+@       IN  SOA ns1 hostmaster.example.com. (
+                2024010101 ; serial
+                2h         ; refresh
+                15m 2w 5m )
+        IN  NS  ns1
+        IN  MX  10 mail
+ns1     300 A   192.0.2.53
+mail        A   192.0.2.25  ; TTL back to $TTL
+            AAAA 2001:db8::25
+www     IN CNAME @
+txt     TXT "a ; b" (
+            "c" )
+$ORIGIN sub.example.com.
+host    2m IN A 192.0.2.1
+"#;
+        let records = parse(text, &origin()).unwrap();
+        assert_eq!(
+            vec![
+                "example.com. 3600 IN SOA ns1.example.com. hostmaster.example.com. 2024010101 7200 900 1209600 300",
+                "example.com. 3600 IN NS ns1.example.com.",
+                "example.com. 3600 IN MX 10 mail.example.com.",
+                "ns1.example.com. 300 IN A 192.0.2.53",
+                "mail.example.com. 3600 IN A 192.0.2.25",
+                "mail.example.com. 3600 IN AAAA 2001:db8::25",
+                "www.example.com. 3600 IN CNAME example.com.",
+                r#"txt.example.com. 3600 IN TXT "a ; b" "c""#,
+                "host.sub.example.com. 120 IN A 192.0.2.1",
+            ],
+            presented(&records)
+        );
+    }
+
+    #[test]
+    fn ttl_without_directive() {
+        let text =
+            "@ SOA ns1 hostmaster 1 2 3 4 60\n  NS ns1\nns1 30 A 192.0.2.53\nns2 A 192.0.2.54";
+        let records = parse(text, &origin()).unwrap();
+        assert!(matches!(
+            records[0].rdata().unwrap(),
+            RData::Soa(Soa { minimum: 60, .. })
+        ));
+        assert_eq!(
+            vec![60, 60, 30, 30],
+            records.iter().map(Answer::ttl).collect::<Vec<_>>()
+        );
+        assert!(parse("www A 192.0.2.1", &origin()).is_err());
+    }
+
+    #[test]
+    fn includes_files() {
+        let dir = std::env::temp_dir().join(format!("zone-{}", std::process::id()));
+        fs::create_dir_all(dir.join("hosts")).unwrap();
+        fs::write(
+            dir.join("db.example"),
+            "$TTL 300\n$INCLUDE hosts/db.sub sub\nafter A 192.0.2.2\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("hosts/db.sub"),
+            "$ORIGIN deeper\nhost A 192.0.2.1\n",
+        )
+        .unwrap();
+        fs::write(dir.join("db.loop"), "$INCLUDE db.loop\n").unwrap();
+        fs::write(dir.join("db.broken"), "$INCLUDE hosts/db.bad\n").unwrap();
+        fs::write(dir.join("hosts/db.bad"), "\nhost A 192.0.2.300\n").unwrap();
+
+        let records = load(dir.join("db.example"), &origin()).unwrap();
+        assert_eq!(
+            vec![
+                "host.deeper.sub.example.com. 300 IN A 192.0.2.1",
+                "after.example.com. 300 IN A 192.0.2.2",
+            ],
+            presented(&records)
+        );
+
+        let err = load(dir.join("db.loop"), &origin()).unwrap_err();
+        assert!(format!("{:#}", err).contains("more than 8 levels deep"));
+        let err = load(dir.join("db.broken"), &origin()).unwrap_err();
+        assert_eq!(
+            DnsError::InvalidZone {
+                file: dir.join("hosts/db.bad").display().to_string(),
+                line: 2
+            }
+            .to_string(),
+            err.to_string()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn generates_records() {
+        let text = "$TTL 300
+$GENERATE 1-3 host$ A 192.0.2.$
+$GENERATE 10-14/4 ${-9,2,d} PTR host${0,2,x}.example.com.
+$GENERATE 255-255 ${0,4,n}.ip6 CNAME \\$${0,0,X}";
+        let records = parse(text, &origin()).unwrap();
+        assert_eq!(
+            vec![
+                "host1.example.com. 300 IN A 192.0.2.1",
+                "host2.example.com. 300 IN A 192.0.2.2",
+                "host3.example.com. 300 IN A 192.0.2.3",
+                "01.example.com. 300 IN PTR host0a.example.com.",
+                "05.example.com. 300 IN PTR host0e.example.com.",
+                "f.f.0.0.ip6.example.com. 300 IN CNAME \\$FF.example.com.",
+            ],
+            presented(&records)
+        );
+        assert!(parse("$GENERATE 3-1 host$ A 192.0.2.$", &origin()).is_err());
+        assert!(parse("$TTL 1\n$GENERATE 0-1 ${-1} A 192.0.2.1", &origin()).is_err());
+    }
+
+    #[test]
+    fn locates_errors() {
+        let error = |text| {
+            let err = parse(text, &origin()).unwrap_err();
+            (err.to_string(), format!("{:#}", err))
+        };
+
+        let (located, chain) = error("$TTL 300\n\nwww A 192.0.2.1\nbad A 192.0.2\n");
+        assert_eq!("Invalid zone file `<inline>` at line 4", located);
+        assert!(chain.contains("Invalid A record data"));
+
+        let (located, _) = error("$TTL 300\n@ SOA ns1 hostmaster (\n 1 2 3 4 5\n");
+        assert_eq!("Invalid zone file `<inline>` at line 2", located);
+        assert_eq!(
+            "Invalid zone file `<inline>` at line 1",
+            error("  A 192.0.2.1").0
+        );
+        assert_eq!("Invalid zone file `<inline>` at line 1", error("a A )").0);
+        assert!(error("$BOGUS 1").1.contains("unknown directive `$BOGUS`"));
+        assert!(error("$TTL 1 2").1.contains("unexpected field `2`"));
+    }
+
+    const ZONE: &str = "$TTL 300
+@           SOA     ns1 hostmaster 1 7200 3600 1209600 60
+            NS      ns1
+ns1         A       192.0.2.53
+www         A       192.0.2.1
+            AAAA    2001:db8::1
+alias       CNAME   www
+chain       CNAME   alias
+away        CNAME   www.example.org.
+dangling    CNAME   missing
+a.b.c       A       192.0.2.3
+sub         NS      ns.sub
+            NS      ns.example.org.
+ns.sub      A       192.0.2.54
+";
+
+    fn zone() -> Zone {
+        Zone::new(origin(), parse(ZONE, &origin()).unwrap()).unwrap()
+    }
+
+    fn ask(zone: &Zone, name: &str, qtype: rr::Type) -> Message {
+        let query = Message::query(name.parse().unwrap(), qtype).build();
+        assert!(zone.serves(&query.questions()[0]));
+        zone.answer(&query)
+    }
+
+    fn sections(response: &Message) -> [Vec<String>; 3] {
+        [
+            presented(response.answers()),
+            presented(response.authorities()),
+            presented(response.additionals()),
+        ]
+    }
+
+    const NEGATIVE_SOA: &str =
+        "example.com. 60 IN SOA ns1.example.com. hostmaster.example.com. 1 7200 3600 1209600 60";
+
+    #[test]
+    fn writes_canonical_master_files() {
+        let text = "$TTL 300
+Zeta        A       192.0.2.9
+@           MX      10 mail.example.org.
+            NS      ns1
+            SOA     ns1 hostmaster 1 7200 3600 1209600 60
+b.ZETA      TXT     \"quoted; text\"
+a.zeta  60  A       192.0.2.2
+a.zeta      A       192.0.2.1
+alias       CNAME   @
+srv         SRV     0 5 53 ns1
+";
+        let zone = Zone::new(origin(), parse(text, &origin()).unwrap()).unwrap();
+        let written = zone.to_string();
+        assert_eq!(
+            r#"$ORIGIN example.com.
+$TTL 300
+@ IN SOA ns1 hostmaster 1 7200 3600 1209600 60
+@ IN NS ns1
+@ IN MX 10 mail.example.org.
+alias IN CNAME @
+srv IN SRV 0 5 53 ns1
+Zeta IN A 192.0.2.9
+a.zeta IN A 192.0.2.1
+a.zeta 60 IN A 192.0.2.2
+b.ZETA IN TXT "quoted; text"
+"#,
+            written
+        );
+
+        let reloaded = Zone::new(origin(), parse(&written, &origin()).unwrap()).unwrap();
+        assert_eq!(written, reloaded.to_string());
+        let dir = std::env::temp_dir().join(format!("zone-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("db.example");
+        zone.save(&path).unwrap();
+        assert_eq!(written, Zone::load(&path, origin()).unwrap().to_string());
+        // Failing to replace the zone file leaves no temporary file behind.
+        let occupied = dir.join("db.occupied");
+        fs::create_dir_all(occupied.join("file")).unwrap();
+        assert!(zone.save(&occupied).is_err());
+        assert!(!dir.join("db.occupied.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn answers_records() {
+        let response = ask(&zone(), "WWW.Example.com", rr::Type::Aaaa);
+        assert_eq!(AuthoritativeAnswer::Yes, response.header().aa);
+        assert_eq!(ResponseCode::NoError, response.header().rcode);
+        assert_eq!(
+            [
+                vec!["www.example.com. 300 IN AAAA 2001:db8::1".to_string()],
+                vec![],
+                vec![]
+            ],
+            sections(&response)
+        );
+    }
+
+    #[test]
+    fn answers_negatively() {
+        let zone = zone();
+        let nxdomain = ask(&zone, "missing.example.com", rr::Type::A);
+        assert_eq!(AuthoritativeAnswer::Yes, nxdomain.header().aa);
+        assert_eq!(ResponseCode::NameError, nxdomain.header().rcode);
+        assert_eq!(
+            [vec![], vec![NEGATIVE_SOA.to_string()], vec![]],
+            sections(&nxdomain)
+        );
+
+        for (name, qtype) in [
+            ("www.example.com", rr::Type::Mx),
+            ("b.c.example.com", rr::Type::A),
+        ] {
+            let nodata = ask(&zone, name, qtype);
+            assert_eq!(AuthoritativeAnswer::Yes, nodata.header().aa);
+            assert_eq!(ResponseCode::NoError, nodata.header().rcode, "{}", name);
+            assert_eq!(
+                [vec![], vec![NEGATIVE_SOA.to_string()], vec![]],
+                sections(&nodata)
+            );
+        }
+    }
+
+    #[test]
+    fn follows_cnames() {
+        let zone = zone();
+        let response = ask(&zone, "chain.example.com", rr::Type::A);
+        assert_eq!(AuthoritativeAnswer::Yes, response.header().aa);
+        assert_eq!(
+            vec![
+                "chain.example.com. 300 IN CNAME alias.example.com.",
+                "alias.example.com. 300 IN CNAME www.example.com.",
+                "www.example.com. 300 IN A 192.0.2.1",
+            ],
+            presented(response.answers())
+        );
+
+        let response = ask(&zone, "alias.example.com", rr::Type::Cname);
+        assert_eq!(1, response.answers().len());
+
+        let response = ask(&zone, "away.example.com", rr::Type::A);
+        assert_eq!(
+            vec!["away.example.com. 300 IN CNAME www.example.org."],
+            presented(response.answers())
+        );
+        assert!(response.authorities().is_empty());
+
+        let response = ask(&zone, "dangling.example.com", rr::Type::A);
+        assert_eq!(ResponseCode::NameError, response.header().rcode);
+        assert_eq!(1, response.answers().len());
+        assert_eq!(vec![NEGATIVE_SOA], presented(response.authorities()));
+    }
+
+    #[test]
+    fn refers_to_delegations() {
+        let zone = zone();
+        for name in [
+            "sub.example.com",
+            "host.sub.example.com",
+            "ns.sub.example.com",
+        ] {
+            let response = ask(&zone, name, rr::Type::A);
+            assert_eq!(AuthoritativeAnswer::No, response.header().aa);
+            assert_eq!(ResponseCode::NoError, response.header().rcode);
+            assert_eq!(
+                [
+                    vec![],
+                    vec![
+                        "sub.example.com. 300 IN NS ns.sub.example.com.".to_string(),
+                        "sub.example.com. 300 IN NS ns.example.org.".to_string(),
+                    ],
+                    vec!["ns.sub.example.com. 300 IN A 192.0.2.54".to_string()],
+                ],
+                sections(&response)
+            );
+        }
+        // Name servers at the apex are no delegation.
+        let response = ask(&zone, "example.com", rr::Type::Ns);
+        assert_eq!(AuthoritativeAnswer::Yes, response.header().aa);
+        assert_eq!(1, response.answers().len());
+    }
+
+    #[test]
+    fn serves_its_names_only() {
+        let zone = zone();
+        let question = |name: &str, qclass| Question {
+            domain: name.parse().unwrap(),
+            qtype: rr::Type::A,
+            qclass,
+        };
+        assert!(zone.serves(&question("EXAMPLE.com", rr::Class::In)));
+        assert!(zone.serves(&question("www.example.com", rr::Class::Any)));
+        assert!(!zone.serves(&question("www.example.com", rr::Class::Ch)));
+        assert!(!zone.serves(&question("example.org", rr::Class::In)));
+        assert!(!zone.serves(&question("com", rr::Class::In)));
+        assert!(!zone.serves(&question("badexample.com", rr::Class::In)));
+
+        let query = Message::query("www.example.com".parse().unwrap(), rr::Type::A)
+            .edns(Edns::default())
+            .build();
+        assert!(zone.answer(&query).edns().is_some());
+    }
+
+    #[test]
+    fn rejects_invalid_zones() {
+        let invalid = |text: &str| {
+            let records = parse(text, &origin()).unwrap();
+            Zone::new(origin(), records).unwrap_err().to_string()
+        };
+        let soa = "@ 300 SOA ns1 hostmaster 1 2 3 4 5\n";
+        assert_eq!(
+            "Invalid zone data: no SOA record at `example.com.`",
+            invalid("www 300 A 192.0.2.1")
+        );
+        assert_eq!(
+            "Invalid zone data: `www.example.org.` is outside of zone `example.com.`",
+            invalid(&format!("{}www.example.org. 300 A 192.0.2.1", soa))
+        );
+        assert!(invalid(&format!("{}www 300 SOA ns1 hostmaster 1 2 3 4 5", soa)).contains("SOA"));
+        assert!(invalid(&format!("{}www 300 CNAME @\nwww 300 A 192.0.2.1", soa)).contains("CNAME"));
+    }
+
+    #[test]
+    fn synthesizes_from_wildcards() {
+        let text = format!(
+            "{}{}",
+            ZONE,
+            "*.preview   A       192.0.2.80
+            TXT     \"preview\"
+host.preview A      192.0.2.81
+a.ent.preview A     192.0.2.82
+*.cname     CNAME   www
+*.loop      CNAME   again.loop
+"
+        );
+        let zone = Zone::new(origin(), parse(&text, &origin()).unwrap()).unwrap();
+
+        for name in ["pr-12.preview.example.com", "a.b.PREVIEW.example.com"] {
+            let response = ask(&zone, name, rr::Type::A);
+            assert_eq!(AuthoritativeAnswer::Yes, response.header().aa);
+            assert_eq!(
+                vec![format!("{}. 300 IN A 192.0.2.80", name)],
+                presented(response.answers())
+            );
+        }
+        let response = ask(&zone, "*.preview.example.com", rr::Type::Txt);
+        assert_eq!(
+            vec![r#"*.preview.example.com. 300 IN TXT "preview""#],
+            presented(response.answers())
+        );
+
+        // Existing names, empty non-terminals included, are never synthesized.
+        let response = ask(&zone, "host.preview.example.com", rr::Type::Txt);
+        assert_eq!(
+            [vec![], vec![NEGATIVE_SOA.to_string()], vec![]],
+            sections(&response)
+        );
+        let response = ask(&zone, "ent.preview.example.com", rr::Type::A);
+        assert_eq!(ResponseCode::NoError, response.header().rcode);
+        assert!(response.answers().is_empty());
+        // Their closest encloser being the empty non-terminal, with no wildcard of its own.
+        let response = ask(&zone, "b.ent.preview.example.com", rr::Type::A);
+        assert_eq!(ResponseCode::NameError, response.header().rcode);
+
+        let response = ask(&zone, "pr-12.preview.example.com", rr::Type::Mx);
+        assert_eq!(ResponseCode::NoError, response.header().rcode);
+        assert_eq!(
+            [vec![], vec![NEGATIVE_SOA.to_string()], vec![]],
+            sections(&response)
+        );
+
+        let response = ask(&zone, "x.cname.example.com", rr::Type::Aaaa);
+        assert_eq!(
+            vec![
+                "x.cname.example.com. 300 IN CNAME www.example.com.",
+                "www.example.com. 300 IN AAAA 2001:db8::1",
+            ],
+            presented(response.answers())
+        );
+        let response = ask(&zone, "x.loop.example.com", rr::Type::A);
+        assert_eq!(MAX_CNAME_CHAIN, response.answers().len());
+
+        // No wildcard at the apex.
+        let response = ask(&zone, "nowhere.example.com", rr::Type::A);
+        assert_eq!(ResponseCode::NameError, response.header().rcode);
+    }
+}